@@ -0,0 +1,26 @@
+use clap::Parser;
+use std::process::ExitCode;
+
+use ppm_core::cli::{self, Cli};
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match ppm_core::load_config().await {
+        Ok(config) => {
+            let config = match &cli.root {
+                Some(root) => config.with_root(root),
+                None => config,
+            };
+            cli::run(cli.command, cli.output, &config).await
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            cli::report_error(&e, cli.output);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
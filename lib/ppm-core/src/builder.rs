@@ -0,0 +1,520 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::formats::plpm::{
+    PlpmCompression, PlpmFile, PlpmHeader, PlpmScripts, PLPM_FORMAT_VERSION, PLPM_MAGIC,
+};
+use crate::cache::checksum_file;
+use crate::formats::{PlpmPackage, PlpmWriter};
+use crate::package::SandboxConfig;
+use crate::{compute_checksum, Architecture, Channel, Dependency, PackageMetadata, PpmError, Result, Version};
+
+pub const MANIFEST_FILE: &str = "PLPM.toml";
+pub const DEFAULT_STAGING_DIR: &str = "staging";
+
+// `PLPM.toml`: a `[package]` table mirroring `PackageMetadata`, optional
+// `[scripts]` naming script files relative to the manifest, and `[build]`
+// settings for where the staged tree lives and how to compress it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PackageManifest {
+    pub package: ManifestPackage,
+    #[serde(default)]
+    pub scripts: ManifestScripts,
+    #[serde(default)]
+    pub build: ManifestBuild,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestPackage {
+    pub name: String,
+    pub version: Version,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub license: Option<String>,
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
+    #[serde(default)]
+    pub conflicts: Vec<Dependency>,
+    #[serde(default)]
+    pub provides: Vec<Dependency>,
+    #[serde(default)]
+    pub replaces: Vec<Dependency>,
+    #[serde(default)]
+    pub recommends: Vec<Dependency>,
+    #[serde(default)]
+    pub features: BTreeMap<String, Vec<Dependency>>,
+    #[serde(default)]
+    pub architectures: Vec<Architecture>,
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+    pub build_script: Option<String>,
+    pub install_script: Option<String>,
+    pub sandbox: Option<SandboxConfig>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ManifestScripts {
+    pub pre_install: Option<PathBuf>,
+    pub post_install: Option<PathBuf>,
+    pub pre_remove: Option<PathBuf>,
+    pub post_remove: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestBuild {
+    #[serde(default = "default_staging_dir")]
+    pub staging: PathBuf,
+    #[serde(default = "default_compressed")]
+    pub compressed: bool,
+    #[serde(default)]
+    pub compression: PlpmCompression,
+}
+
+fn default_staging_dir() -> PathBuf {
+    PathBuf::from(DEFAULT_STAGING_DIR)
+}
+
+fn default_compressed() -> bool {
+    true
+}
+
+impl Default for ManifestBuild {
+    fn default() -> Self {
+        Self {
+            staging: default_staging_dir(),
+            compressed: default_compressed(),
+            compression: PlpmCompression::default(),
+        }
+    }
+}
+
+impl PackageManifest {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let manifest: Self = toml::from_str(&contents)
+            .map_err(|e| PpmError::InvalidPackage(format!("{}: {}", path.display(), e)))?;
+        if manifest.package.name.is_empty() {
+            return Err(PpmError::InvalidPackage(format!("{}: package name is empty", path.display())));
+        }
+        Ok(manifest)
+    }
+
+    pub fn metadata(&self) -> PackageMetadata {
+        let package = &self.package;
+        PackageMetadata {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            description: package.description.clone(),
+            author: package.author.clone(),
+            license: package.license.clone(),
+            dependencies: package.dependencies.clone(),
+            conflicts: package.conflicts.clone(),
+            provides: package.provides.clone(),
+            replaces: package.replaces.clone(),
+            recommends: package.recommends.clone(),
+            features: package.features.clone(),
+            architectures: package.architectures.clone(),
+            channels: package.channels.clone(),
+            build_script: package.build_script.clone(),
+            install_script: package.install_script.clone(),
+            sandbox_config: package.sandbox.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BuiltPackage {
+    pub path: PathBuf,
+    pub name: String,
+    pub version: Version,
+    pub architecture: Architecture,
+    pub channel: Channel,
+    pub files: usize,
+    pub size: u64,
+    pub checksum: String,
+}
+
+pub struct PackageBuilder {
+    dir: PathBuf,
+    manifest_path: PathBuf,
+    manifest: PackageManifest,
+    staging: Option<PathBuf>,
+    architecture: Option<Architecture>,
+    channel: Option<Channel>,
+    default_architecture: Architecture,
+    default_channel: Channel,
+}
+
+impl PackageBuilder {
+    // Accepts either the manifest itself or the directory containing it.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let manifest_path = if path.is_dir() { path.join(MANIFEST_FILE) } else { path.to_path_buf() };
+        let dir = manifest_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+        Ok(Self {
+            manifest: PackageManifest::load(&manifest_path)?,
+            dir,
+            manifest_path,
+            staging: None,
+            architecture: None,
+            channel: None,
+            default_architecture: Architecture::current(),
+            default_channel: Channel::Stable,
+        })
+    }
+
+    pub fn manifest(&self) -> &PackageManifest {
+        &self.manifest
+    }
+
+    pub fn with_staging(mut self, staging: impl Into<PathBuf>) -> Self {
+        self.staging = Some(staging.into());
+        self
+    }
+
+    pub fn with_architecture(mut self, architecture: Architecture) -> Self {
+        self.architecture = Some(architecture);
+        self
+    }
+
+    pub fn with_channel(mut self, channel: Channel) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    // Used when neither an explicit target nor a single-entry list in the
+    // manifest decides the architecture or channel.
+    pub fn with_defaults(mut self, architecture: Architecture, channel: Channel) -> Self {
+        self.default_architecture = architecture;
+        self.default_channel = channel;
+        self
+    }
+
+    pub fn staging_dir(&self) -> PathBuf {
+        self.dir.join(self.staging.as_ref().unwrap_or(&self.manifest.build.staging))
+    }
+
+    pub fn file_name(&self, architecture: Architecture) -> String {
+        format!(
+            "{}-{}-{}.plpm",
+            self.manifest.package.name,
+            self.manifest.package.version,
+            architecture.as_str()
+        )
+    }
+
+    pub fn build(&self) -> Result<PlpmPackage> {
+        let (header, staged) = self.prepare()?;
+        let files = staged
+            .into_iter()
+            .map(|file| {
+                let data = fs::read(&file.source)?;
+                Ok(PlpmFile {
+                    path: file.path,
+                    permissions: file.permissions,
+                    checksum: compute_checksum(&data),
+                    data,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(PlpmPackage {
+            header,
+            metadata: self.manifest.metadata(),
+            files,
+            scripts: self.scripts()?,
+            signature: None,
+        })
+    }
+
+    fn prepare(&self) -> Result<(PlpmHeader, Vec<StagedFile>)> {
+        let package = &self.manifest.package;
+        let architecture = pick_target(
+            self.architecture,
+            &package.architectures,
+            self.default_architecture,
+        )
+        .ok_or_else(|| {
+            PpmError::InvalidPackage(format!(
+                "{} is not built for {}; choose one of: {}",
+                package.name,
+                self.architecture.unwrap_or(self.default_architecture).as_str(),
+                package.architectures.iter().map(|a| a.as_str()).collect::<Vec<_>>().join(", ")
+            ))
+        })?;
+        let channel = pick_target(self.channel, &package.channels, self.default_channel).ok_or_else(|| {
+            PpmError::InvalidPackage(format!(
+                "{} is not published to the {} channel; choose one of: {}",
+                package.name,
+                self.channel.unwrap_or(self.default_channel).name(),
+                package.channels.iter().map(|c| c.name()).collect::<Vec<_>>().join(", ")
+            ))
+        })?;
+
+        let staging = self.staging_dir();
+        if !staging.is_dir() {
+            return Err(PpmError::InvalidPackage(format!(
+                "staging directory {} does not exist",
+                staging.display()
+            )));
+        }
+        let mut files = Vec::new();
+        collect_staging(&staging, &staging, &mut files)?;
+        // Byte order of the normalized path, so the file table never depends
+        // on directory iteration order.
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let header = PlpmHeader {
+            magic: PLPM_MAGIC,
+            version: PLPM_FORMAT_VERSION,
+            architecture,
+            channel,
+            compressed: self.manifest.build.compressed,
+            compression: self.manifest.build.compression,
+        };
+        Ok((header, files))
+    }
+
+    // Writes `<output>/<name>-<version>-<arch>.plpm`. The file's modification
+    // time is pinned to `SOURCE_DATE_EPOCH` when set, otherwise to the newest
+    // input, so rebuilding unchanged sources leaves the artifact untouched.
+    pub fn write(&self, output: impl AsRef<Path>) -> Result<BuiltPackage> {
+        let (header, staged) = self.prepare()?;
+        let metadata = self.manifest.metadata();
+        let output = output.as_ref();
+        fs::create_dir_all(output)?;
+        let path = output.join(self.file_name(header.architecture));
+        let tmp = path.with_extension("plpm.tmp");
+
+        let mut writer = PlpmWriter::new(
+            BufWriter::new(File::create(&tmp)?),
+            header.clone(),
+            &metadata,
+            self.scripts()?.as_ref(),
+        )?;
+        for file in &staged {
+            writer.add_file(&file.path, file.permissions, &mut File::open(&file.source)?)?;
+        }
+        let file = writer.finish(None)?.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        file.set_modified(self.source_date()?)?;
+        drop(file);
+        fs::rename(&tmp, &path)?;
+
+        Ok(BuiltPackage {
+            size: fs::metadata(&path)?.len(),
+            checksum: checksum_file(&path)?,
+            path,
+            name: metadata.name,
+            version: metadata.version,
+            architecture: header.architecture,
+            channel: header.channel,
+            files: staged.len(),
+        })
+    }
+
+    fn scripts(&self) -> Result<Option<PlpmScripts>> {
+        let scripts = &self.manifest.scripts;
+        let read = |path: &Option<PathBuf>| -> Result<Option<String>> {
+            path.as_ref()
+                .map(|p| fs::read_to_string(self.dir.join(p)))
+                .transpose()
+                .map_err(Into::into)
+        };
+        let scripts = PlpmScripts {
+            pre_install: read(&scripts.pre_install)?,
+            post_install: read(&scripts.post_install)?,
+            pre_remove: read(&scripts.pre_remove)?,
+            post_remove: read(&scripts.post_remove)?,
+        };
+        let empty = scripts.pre_install.is_none()
+            && scripts.post_install.is_none()
+            && scripts.pre_remove.is_none()
+            && scripts.post_remove.is_none();
+        Ok((!empty).then_some(scripts))
+    }
+
+    fn source_date(&self) -> Result<SystemTime> {
+        if let Ok(epoch) = std::env::var("SOURCE_DATE_EPOCH") {
+            let secs: u64 = epoch.trim().parse().map_err(|_| {
+                PpmError::InvalidPackage(format!("SOURCE_DATE_EPOCH is not a timestamp: {}", epoch))
+            })?;
+            return Ok(UNIX_EPOCH + Duration::from_secs(secs));
+        }
+        let scripts = &self.manifest.scripts;
+        let mut inputs = vec![self.manifest_path.clone()];
+        inputs.extend(
+            [&scripts.pre_install, &scripts.post_install, &scripts.pre_remove, &scripts.post_remove]
+                .into_iter()
+                .flatten()
+                .map(|p| self.dir.join(p)),
+        );
+        let mut newest = UNIX_EPOCH;
+        for input in inputs {
+            if let Ok(meta) = fs::metadata(&input) {
+                newest = newest.max(meta.modified()?);
+            }
+        }
+        Ok(newest.max(newest_modified(&self.staging_dir())?))
+    }
+}
+
+fn pick_target<T: Copy + PartialEq>(explicit: Option<T>, listed: &[T], fallback: T) -> Option<T> {
+    match (explicit, listed) {
+        (Some(target), _) => (listed.is_empty() || listed.contains(&target)).then_some(target),
+        (None, [only]) => Some(*only),
+        (None, _) => (listed.is_empty() || listed.contains(&fallback)).then_some(fallback),
+    }
+}
+
+// Group and other write are dropped from the staged mode, since whether they
+// are set depends on the umask of whoever ran the build. Everything else,
+// private files and setuid bits included, is kept as staged.
+#[cfg(unix)]
+fn normalized_permissions(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7755
+}
+
+#[cfg(not(unix))]
+fn normalized_permissions(_meta: &fs::Metadata) -> u32 {
+    0o644
+}
+
+struct StagedFile {
+    path: String,
+    source: PathBuf,
+    permissions: u32,
+}
+
+fn collect_staging(root: &Path, dir: &Path, out: &mut Vec<StagedFile>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_staging(root, &path, out)?;
+        } else if file_type.is_file() {
+            let relative = path
+                .strip_prefix(root)
+                .map_err(|_| PpmError::InvalidPackage(format!("{} is outside the staging tree", path.display())))?;
+            let relative = relative
+                .components()
+                .map(|c| c.as_os_str().to_str())
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| PpmError::InvalidPackage(format!("{} is not valid UTF-8", path.display())))?
+                .join("/");
+            out.push(StagedFile {
+                path: relative,
+                permissions: normalized_permissions(&entry.metadata()?),
+                source: path,
+            });
+        } else {
+            return Err(PpmError::InvalidPackage(format!(
+                "{} is not a regular file; PLPM packages can only contain files",
+                path.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn newest_modified(dir: &Path) -> Result<SystemTime> {
+    let mut newest = UNIX_EPOCH;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        newest = newest.max(meta.modified()?);
+        if meta.is_dir() {
+            newest = newest.max(newest_modified(&entry.path())?);
+        }
+    }
+    Ok(newest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::TestDir;
+
+    struct Fixture {
+        dir: TestDir,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = TestDir::new("builder", name);
+            Self { dir }
+        }
+
+        // Lays out a project whose staging tree is created in the given file
+        // order and with the given modes.
+        fn project(&self, name: &str, files: &[(&str, &str, u32)]) -> PathBuf {
+            let project = self.dir.join(name);
+            fs::create_dir_all(&project).unwrap();
+            fs::write(
+                project.join(MANIFEST_FILE),
+                "[package]\nname = \"demo\"\nversion = \"1.0.0\"\narchitectures = [\"x86_64\"]\n",
+            )
+            .unwrap();
+            for (path, contents, mode) in files {
+                let path = project.join(DEFAULT_STAGING_DIR).join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, contents).unwrap();
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    fs::set_permissions(&path, fs::Permissions::from_mode(*mode)).unwrap();
+                }
+            }
+            project
+        }
+    }
+
+    #[test]
+    fn rebuilds_are_byte_identical() {
+        let fixture = Fixture::new("reproducible");
+        let files = [
+            ("usr/bin/demo", "#!/bin/sh\necho demo\n", 0o755),
+            ("usr/share/demo/a.txt", "alpha", 0o644),
+            ("etc/demo.conf", "key = 1\n", 0o600),
+        ];
+        let project = fixture.project("one", &files);
+        let builder = PackageBuilder::open(&project).unwrap();
+        let first = builder.write(fixture.dir.join("out1")).unwrap();
+        let second = builder.write(fixture.dir.join("out2")).unwrap();
+        assert_eq!(first.checksum, second.checksum);
+        assert_eq!(first.size, second.size);
+        assert_eq!(fs::read(&first.path).unwrap(), fs::read(&second.path).unwrap());
+        let modified = |path: &Path| fs::metadata(path).unwrap().modified().unwrap();
+        assert_eq!(modified(&first.path), modified(&second.path));
+
+        // Neither the order files were staged in nor a umask that leaves
+        // group write on changes the artifact.
+        let reordered = [
+            ("etc/demo.conf", "key = 1\n", 0o600),
+            ("usr/share/demo/a.txt", "alpha", 0o664),
+            ("usr/bin/demo", "#!/bin/sh\necho demo\n", 0o775),
+        ];
+        let other = PackageBuilder::open(fixture.project("two", &reordered)).unwrap();
+        let third = other.write(fixture.dir.join("out3")).unwrap();
+        assert_eq!(third.checksum, first.checksum);
+        assert_eq!(third.files, 3);
+
+        // Modes the package asks for on purpose are kept.
+        let package = PlpmPackage::load(&first.path).unwrap();
+        let mode = |path: &str| package.files.iter().find(|f| f.path == path).unwrap().permissions;
+        assert_eq!(mode("etc/demo.conf"), 0o600);
+        assert_eq!(mode("usr/bin/demo"), 0o755);
+        let private = [("etc/demo.conf", "key = 1\n", 0o640), files[1], files[2]];
+        let other = PackageBuilder::open(fixture.project("three", &private)).unwrap();
+        assert_ne!(other.write(fixture.dir.join("out4")).unwrap().checksum, first.checksum);
+    }
+}
@@ -0,0 +1,432 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::{PpmError, Result};
+
+const OBJECTS_DIR: &str = "objects/sha256";
+const TMP_DIR: &str = "objects/tmp";
+const PARTIAL_DIR: &str = "objects/partial";
+const STALE_TEMP_AGE: Duration = Duration::from_secs(86_400);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub max_size: Option<u64>,
+    pub max_age_days: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub checksum: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub last_used: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub removed: usize,
+    pub freed: u64,
+}
+
+impl CacheStats {
+    fn add(&mut self, entry: &CacheEntry) {
+        self.removed += 1;
+        self.freed += entry.size;
+    }
+}
+
+// Objects live at `<dir>/objects/sha256/<first two hex digits>/<checksum>`.
+// An entry's modification time doubles as its last-use time, so several
+// processes can share one cache without coordinating on an index file.
+#[derive(Debug, Clone)]
+pub struct PackageCache {
+    dir: PathBuf,
+}
+
+impl PackageCache {
+    pub fn open(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn path(&self, checksum: &str) -> Result<PathBuf> {
+        let checksum = checksum.to_ascii_lowercase();
+        if checksum.len() != 64 || !checksum.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(PpmError::InvalidPackage(format!("'{}' is not a sha256 checksum", checksum)));
+        }
+        Ok(self.dir.join(OBJECTS_DIR).join(&checksum[..2]).join(&checksum))
+    }
+
+    // Re-hashes the object before handing it out; a corrupted entry is
+    // dropped and reported as a miss so the caller fetches it again.
+    pub fn get(&self, checksum: &str) -> Result<Option<PathBuf>> {
+        let path = self.path(checksum)?;
+        match checksum_file(&path) {
+            Ok(actual) if actual.eq_ignore_ascii_case(checksum) => {
+                touch(&path);
+                Ok(Some(path))
+            }
+            Ok(_) => {
+                remove_if_exists(&path)?;
+                Ok(None)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn contains(&self, checksum: &str) -> bool {
+        self.path(checksum).is_ok_and(|path| path.is_file())
+    }
+
+    // A scratch file on the same filesystem as the objects, for callers that
+    // stream content in before calling `insert_file`.
+    pub fn temp_path(&self) -> Result<PathBuf> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let dir = self.dir.join(TMP_DIR);
+        fs::create_dir_all(&dir)?;
+        Ok(dir.join(format!(
+            "{}-{}.part",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        )))
+    }
+
+    // Where an interrupted download of `checksum` is kept between attempts.
+    // Unlike `temp_path` the name is stable, so a later run can resume it.
+    pub fn partial_path(&self, checksum: &str) -> Result<PathBuf> {
+        let name = self.path(checksum)?;
+        let dir = self.dir.join(PARTIAL_DIR);
+        fs::create_dir_all(&dir)?;
+        Ok(dir.join(name.file_name().unwrap_or_default()).with_extension("part"))
+    }
+
+    // Moves `source` into the cache under `checksum`, refusing content that
+    // does not hash to it. `source` is consumed either way.
+    pub fn insert_file(&self, checksum: &str, source: &Path) -> Result<PathBuf> {
+        let target = self.path(checksum)?;
+        let actual = checksum_file(source)?;
+        if !actual.eq_ignore_ascii_case(checksum) {
+            let _ = fs::remove_file(source);
+            return Err(PpmError::SecurityViolation(format!(
+                "refusing to cache {}: expected sha256 {}, got {}",
+                source.display(),
+                checksum,
+                actual
+            )));
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        File::open(source)?.sync_all()?;
+        fs::rename(source, &target)?;
+        touch(&target);
+        Ok(target)
+    }
+
+    pub fn insert(&self, data: &[u8]) -> Result<(String, PathBuf)> {
+        let checksum = crate::compute_checksum(data);
+        let tmp = self.temp_path()?;
+        fs::write(&tmp, data)?;
+        let path = self.insert_file(&checksum, &tmp)?;
+        Ok((checksum, path))
+    }
+
+    pub fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let mut entries = Vec::new();
+        let shards = match fs::read_dir(self.dir.join(OBJECTS_DIR)) {
+            Ok(shards) => shards,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(e.into()),
+        };
+        for shard in shards {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+            for object in fs::read_dir(shard.path())? {
+                let object = object?;
+                let meta = object.metadata()?;
+                if !meta.is_file() {
+                    continue;
+                }
+                entries.push(CacheEntry {
+                    checksum: object.file_name().to_string_lossy().into_owned(),
+                    path: object.path(),
+                    size: meta.len(),
+                    last_used: meta.modified()?,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    pub fn size(&self) -> Result<u64> {
+        Ok(self.entries()?.iter().map(|e| e.size).sum())
+    }
+
+    pub fn retain(&self, referenced: &HashSet<String>) -> Result<CacheStats> {
+        let mut stats = CacheStats::default();
+        for entry in self.entries()? {
+            if !referenced.contains(&entry.checksum) {
+                remove_if_exists(&entry.path)?;
+                stats.add(&entry);
+            }
+        }
+        for partial in self.scratch(PARTIAL_DIR)? {
+            if !referenced.contains(&partial.checksum) && !in_use(&partial.path) {
+                remove_if_exists(&partial.path)?;
+                stats.add(&partial);
+            }
+        }
+        self.sweep_temps(&mut stats)?;
+        Ok(stats)
+    }
+
+    // Files in `objects/tmp` or `objects/partial`, keyed by their stem.
+    fn scratch(&self, dir: &str) -> Result<Vec<CacheEntry>> {
+        let mut scratch = Vec::new();
+        let files = match fs::read_dir(self.dir.join(dir)) {
+            Ok(files) => files,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(scratch),
+            Err(e) => return Err(e.into()),
+        };
+        for file in files {
+            let file = file?;
+            let meta = file.metadata()?;
+            let path = file.path();
+            let Some(checksum) = path.file_stem().map(|s| s.to_string_lossy().into_owned()) else {
+                continue;
+            };
+            if meta.is_file() {
+                scratch.push(CacheEntry {
+                    checksum,
+                    path,
+                    size: meta.len(),
+                    last_used: meta.modified()?,
+                });
+            }
+        }
+        Ok(scratch)
+    }
+
+    // Temporary files carry no lock, so one counts as abandoned by a process
+    // that died mid-write once it has gone untouched for `STALE_TEMP_AGE`.
+    fn sweep_temps(&self, stats: &mut CacheStats) -> Result<()> {
+        let cutoff = SystemTime::now().checked_sub(STALE_TEMP_AGE).unwrap_or(SystemTime::UNIX_EPOCH);
+        for temp in self.scratch(TMP_DIR)? {
+            if temp.last_used < cutoff {
+                remove_if_exists(&temp.path)?;
+                stats.add(&temp);
+            }
+        }
+        Ok(())
+    }
+
+    // Drops entries unused for longer than `max_age_days`, then the least
+    // recently used ones until the cache fits in `max_size`. Partial
+    // downloads age out the same way unless one is still being written.
+    pub fn evict(&self, config: &CacheConfig) -> Result<CacheStats> {
+        let mut stats = CacheStats::default();
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|e| e.last_used);
+
+        if let Some(days) = config.max_age_days {
+            let cutoff = SystemTime::now()
+                .checked_sub(Duration::from_secs(days * 86_400))
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            let (expired, fresh): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| e.last_used < cutoff);
+            for entry in &expired {
+                remove_if_exists(&entry.path)?;
+                stats.add(entry);
+            }
+            entries = fresh;
+            for partial in self.scratch(PARTIAL_DIR)? {
+                if partial.last_used < cutoff && !in_use(&partial.path) {
+                    remove_if_exists(&partial.path)?;
+                    stats.add(&partial);
+                }
+            }
+        }
+
+        if let Some(max_size) = config.max_size {
+            let mut total: u64 = entries.iter().map(|e| e.size).sum();
+            for entry in &entries {
+                if total <= max_size {
+                    break;
+                }
+                remove_if_exists(&entry.path)?;
+                total -= entry.size;
+                stats.add(entry);
+            }
+        }
+        self.sweep_temps(&mut stats)?;
+        Ok(stats)
+    }
+
+    pub fn clear(&self) -> Result<CacheStats> {
+        self.retain(&HashSet::new())
+    }
+}
+
+pub fn checksum_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Best effort: a read-only shared cache still serves hits, it just cannot
+// record them for LRU purposes.
+fn touch(path: &Path) {
+    if let Ok(file) = File::options().write(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+// A download holds the lock on its partial file for as long as it writes to
+// it; see `Repository::fetch_package`.
+fn in_use(path: &Path) -> bool {
+    File::open(path).is_ok_and(|file| file.try_lock().is_err())
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::TestDir;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    struct Fixture {
+        cache: PackageCache,
+        _dir: TestDir,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = TestDir::new("cache", name);
+            Self { cache: PackageCache::open(dir.to_path_buf()), _dir: dir }
+        }
+
+        // Caches `data` as if it was last used `age` ago.
+        fn insert(&self, data: &[u8], age: Duration) -> String {
+            let (checksum, path) = self.cache.insert(data).unwrap();
+            age_file(&path, age);
+            checksum
+        }
+
+        fn scratch(&self, path: PathBuf, age: Duration) -> PathBuf {
+            fs::write(&path, b"partial").unwrap();
+            age_file(&path, age);
+            path
+        }
+
+        fn cached(&self) -> Vec<String> {
+            let mut cached: Vec<_> = self.cache.entries().unwrap().into_iter().map(|e| e.checksum).collect();
+            cached.sort();
+            cached
+        }
+    }
+
+    fn age_file(path: &Path, age: Duration) {
+        File::options().write(true).open(path).unwrap().set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    fn sorted<const N: usize>(mut checksums: [String; N]) -> Vec<String> {
+        checksums.sort();
+        checksums.to_vec()
+    }
+
+    #[test]
+    fn rehashes_entries_on_every_hit() {
+        let fixture = Fixture::new("get");
+        let checksum = fixture.insert(b"package", HOUR);
+        let path = fixture.cache.get(&checksum).unwrap().unwrap();
+        // A hit counts as a use for eviction.
+        assert!(fs::metadata(&path).unwrap().modified().unwrap() > SystemTime::now() - HOUR / 2);
+
+        fs::write(&path, b"tampered").unwrap();
+        assert_eq!(fixture.cache.get(&checksum).unwrap(), None);
+        assert!(!path.exists());
+        assert_eq!(fixture.cache.get(&"0".repeat(64)).unwrap(), None);
+        assert!(matches!(fixture.cache.get("not-a-checksum"), Err(PpmError::InvalidPackage(_))));
+
+        let tmp = fixture.cache.temp_path().unwrap();
+        fs::write(&tmp, b"other").unwrap();
+        assert!(matches!(fixture.cache.insert_file(&checksum, &tmp), Err(PpmError::SecurityViolation(_))));
+        assert!(!tmp.exists());
+    }
+
+    #[test]
+    fn retains_referenced_entries_and_live_scratch_files() {
+        let fixture = Fixture::new("retain");
+        let kept = fixture.insert(b"kept", HOUR);
+        let dropped = fixture.insert(b"dropped", HOUR);
+        let kept_partial = fixture.scratch(fixture.cache.partial_path(&kept).unwrap(), HOUR);
+        let stale_partial = fixture.scratch(fixture.cache.partial_path(&dropped).unwrap(), HOUR);
+        let stale_temp = fixture.scratch(fixture.cache.temp_path().unwrap(), STALE_TEMP_AGE + HOUR);
+        let fresh_temp = fixture.scratch(fixture.cache.temp_path().unwrap(), HOUR);
+
+        let busy = fixture.insert(b"busy", HOUR);
+        let busy_partial = fixture.scratch(fixture.cache.partial_path(&busy).unwrap(), HOUR);
+        let download = File::options().write(true).open(&busy_partial).unwrap();
+        download.lock().unwrap();
+
+        let stats = fixture.cache.retain(&HashSet::from([kept.clone()])).unwrap();
+        assert_eq!(fixture.cached(), [kept]);
+        assert_eq!(stats.removed, 4);
+        assert!(kept_partial.exists());
+        assert!(!stale_partial.exists());
+        assert!(!stale_temp.exists());
+        // Another process may still be writing these.
+        assert!(fresh_temp.exists());
+        assert!(busy_partial.exists());
+
+        drop(download);
+        fixture.cache.clear().unwrap();
+        assert!(fixture.cached().is_empty());
+        assert!(!kept_partial.exists() && !busy_partial.exists());
+    }
+
+    #[test]
+    fn evicts_by_age_then_least_recently_used() {
+        let fixture = Fixture::new("evict");
+        let ancient = fixture.insert(&[1; 100], 40 * 24 * HOUR);
+        let old = fixture.insert(&[2; 100], 3 * HOUR);
+        let recent = fixture.insert(&[3; 100], 2 * HOUR);
+        let newest = fixture.insert(&[4; 100], HOUR);
+        let old_partial = fixture.scratch(fixture.cache.partial_path(&ancient).unwrap(), 40 * 24 * HOUR);
+        let new_partial = fixture.scratch(fixture.cache.partial_path(&old).unwrap(), HOUR);
+
+        let stats = fixture.cache.evict(&CacheConfig { max_size: None, max_age_days: Some(30) }).unwrap();
+        assert_eq!(stats, CacheStats { removed: 2, freed: 100 + 7 });
+        assert_eq!(fixture.cached(), sorted([old.clone(), recent.clone(), newest.clone()]));
+        assert!(!old_partial.exists());
+        assert!(new_partial.exists());
+
+        let stats = fixture.cache.evict(&CacheConfig { max_size: Some(250), max_age_days: Some(30) }).unwrap();
+        assert_eq!(stats, CacheStats { removed: 1, freed: 100 });
+        assert_eq!(fixture.cached(), sorted([recent.clone(), newest.clone()]));
+        // Using an entry protects it from the next size eviction.
+        fixture.cache.get(&recent).unwrap().unwrap();
+        fixture.cache.evict(&CacheConfig { max_size: Some(150), max_age_days: None }).unwrap();
+        assert_eq!(fixture.cached(), [recent]);
+    }
+}
@@ -0,0 +1,655 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+
+use crate::builder::BuiltPackage;
+use crate::database::now_timestamp;
+use crate::operations::{
+    self, CleanReport, InstallReport, Ownership, PackageDetails, PackageFiles, RemoveReport, RepositoryBuild,
+    SearchResults, UpgradePlan,
+};
+use crate::{
+    Architecture, Channel, Config, Dependency, Event, Events, InstallReason, InstalledPackage, KeyRecord, KeyTrust,
+    Observer, PpmError, Result, ScriptRun, VersionReq,
+};
+
+#[derive(Debug, Parser)]
+#[command(name = "ppm", version, about = "Plum package manager")]
+pub struct Cli {
+    /// Print results as human-readable text or as JSON
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+    /// Install into DIR instead of the running system, keeping the package
+    /// database, cache and keyring inside it
+    #[arg(long, global = true, value_name = "DIR")]
+    pub root: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Install a package from the configured repositories or a local .plpm file
+    Install {
+        package: String,
+        /// Version requirement, e.g. ">=1.2, <2"
+        version: Option<VersionReq>,
+        #[arg(long)]
+        channel: Option<Channel>,
+        #[arg(long)]
+        arch: Option<Architecture>,
+        /// Optional features to enable, e.g. "ssl,gui"
+        #[arg(long, value_delimiter = ',')]
+        features: Vec<String>,
+        /// Install only the named package, without resolving dependencies
+        #[arg(long)]
+        no_deps: bool,
+        /// Skip recommended packages
+        #[arg(long)]
+        no_recommends: bool,
+        /// Run package scripts inside the sandbox
+        #[arg(long)]
+        sandbox: bool,
+        #[arg(long)]
+        force: bool,
+    },
+    /// Remove an installed package
+    Remove {
+        package: String,
+        /// Also remove dependencies that nothing else needs any more
+        #[arg(long)]
+        cascade: bool,
+        /// Run package scripts inside the sandbox
+        #[arg(long)]
+        sandbox: bool,
+        /// Remove the package even if others depend on it
+        #[arg(long)]
+        force: bool,
+    },
+    /// Remove dependencies that no installed package needs any more
+    Autoremove {
+        /// Run package scripts inside the sandbox
+        #[arg(long)]
+        sandbox: bool,
+    },
+    /// Update installed packages
+    Update {
+        package: Option<String>,
+        #[arg(long)]
+        channel: Option<Channel>,
+        /// Run package scripts inside the sandbox
+        #[arg(long)]
+        sandbox: bool,
+        /// Overwrite files that belong to other packages
+        #[arg(long)]
+        force: bool,
+    },
+    /// Search package names and descriptions
+    Search {
+        query: String,
+        #[arg(long)]
+        channel: Option<Channel>,
+    },
+    /// Show details about a package
+    Info { package: String },
+    /// List installed packages
+    List {
+        #[arg(long)]
+        channel: Option<Channel>,
+    },
+    /// Show which installed package owns a file or directory
+    Owns { path: PathBuf },
+    /// List the files an installed package owns
+    Files { package: String },
+    /// Show installed packages with newer versions available
+    CheckUpdates {
+        #[arg(long)]
+        channel: Option<Channel>,
+    },
+    /// Remove cached packages that are no longer needed
+    Clean {
+        /// Remove every cached package; repository indexes are kept
+        #[arg(long)]
+        all: bool,
+    },
+    /// Build a .plpm package from a PLPM.toml manifest
+    Build(BuildArgs),
+    /// Manage the signing keyring
+    #[command(subcommand)]
+    Key(KeyCommand),
+    /// Maintain package repositories
+    #[command(subcommand)]
+    Repo(RepoCommand),
+}
+
+pub async fn run(command: Command, output: OutputFormat, config: &Config) -> Result<()> {
+    let events = match output {
+        OutputFormat::Text => Events::new().with_observer(ProgressRenderer::new()),
+        OutputFormat::Json => Events::new(),
+    };
+    match command {
+        Command::Install { package, version, channel, arch, features, no_deps, no_recommends, sandbox, force } => {
+            let path = std::path::Path::new(&package);
+            let report = if package.ends_with(".plpm") && path.is_file() {
+                operations::install_from_file(path, InstallReason::Explicit, sandbox, force, config, &events).await?
+            } else {
+                operations::install_package(
+                    &package,
+                    version.as_ref(),
+                    channel,
+                    arch,
+                    &features,
+                    !no_deps,
+                    !no_recommends,
+                    sandbox,
+                    force,
+                    config,
+                    &events,
+                )
+                .await?
+            };
+            emit(&report, output)
+        }
+        Command::Remove { package, cascade, sandbox, force } => {
+            let report = operations::remove_package(&package, cascade, sandbox, force, config, &events).await?;
+            emit(&report, output)
+        }
+        Command::Autoremove { sandbox } => {
+            emit(&operations::autoremove(sandbox, config, &events).await?, output)
+        }
+        Command::Update { package, channel, sandbox, force } => {
+            let report =
+                operations::update_packages(package.as_deref(), channel, sandbox, force, config, &events).await?;
+            emit(&report, output)
+        }
+        Command::Search { query, channel } => {
+            emit(&operations::search_packages(&query, channel, config).await?, output)
+        }
+        Command::Info { package } => emit(&operations::show_package_info(&package, config).await?, output),
+        Command::List { channel } => emit(&operations::list_packages(channel, config).await?, output),
+        Command::Owns { path } => emit(&operations::find_owners(&path, config).await?, output),
+        Command::Files { package } => emit(&operations::list_files(&package, config).await?, output),
+        Command::CheckUpdates { channel } => emit(&operations::check_updates(channel, config).await?, output),
+        Command::Clean { all } => emit(&operations::clean_cache(all, config).await?, output),
+        Command::Build(args) => run_build(args, output, config).await,
+        Command::Key(command) => run_key_command(command, output, config).await,
+        Command::Repo(command) => run_repo_command(command, output).await,
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum KeyCommand {
+    /// Import a public key or an exported key record
+    Import {
+        path: PathBuf,
+        #[arg(long)]
+        owner: Option<String>,
+        #[arg(long, value_name = "DAYS")]
+        expires_in: Option<u64>,
+        #[arg(long = "channel")]
+        channels: Vec<Channel>,
+        #[arg(long = "repository")]
+        repositories: Vec<String>,
+        /// Trust the key immediately after importing it
+        #[arg(long)]
+        trust: bool,
+    },
+    /// Export a key record
+    Export {
+        key: String,
+        /// Write the record to a file instead of stdout
+        #[arg(short = 'o', long)]
+        file: Option<PathBuf>,
+    },
+    /// List keys in the keyring
+    List,
+    /// Trust a key for signature verification
+    Trust { key: String },
+    /// Stop trusting a key without revoking it
+    Distrust { key: String },
+    /// Permanently revoke a key
+    Revoke {
+        key: String,
+        #[arg(long)]
+        reason: Option<String>,
+    },
+}
+
+pub async fn run_key_command(command: KeyCommand, output: OutputFormat, config: &Config) -> Result<()> {
+    let record = match command {
+        KeyCommand::Import { path, owner, expires_in, channels, repositories, trust } => {
+            let expires_at = expires_in.map(|days| now_timestamp() + days * 86_400);
+            operations::import_key(&path, owner.as_deref(), expires_at, &channels, &repositories, trust, config)
+                .await?
+        }
+        // Exports are already a portable TOML record, so they bypass `--output`.
+        KeyCommand::Export { key, file } => {
+            let exported = operations::export_key(&key, config).await?;
+            match file {
+                Some(path) => tokio::fs::write(&path, exported).await?,
+                None => print!("{}", exported),
+            }
+            return Ok(());
+        }
+        KeyCommand::List => return emit(&operations::list_keys(config).await?, output),
+        KeyCommand::Trust { key } => operations::trust_key(&key, config).await?,
+        KeyCommand::Distrust { key } => operations::distrust_key(&key, config).await?,
+        KeyCommand::Revoke { key, reason } => operations::revoke_key(&key, reason.as_deref(), config).await?,
+    };
+    emit(&record, output)
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RepoCommand {
+    /// Generate indexes for a directory of .plpm packages
+    Build {
+        path: PathBuf,
+        /// Re-read every package instead of reusing the scan cache
+        #[arg(long)]
+        full: bool,
+    },
+}
+
+pub async fn run_repo_command(command: RepoCommand, output: OutputFormat) -> Result<()> {
+    match command {
+        RepoCommand::Build { path, full } => emit(&operations::build_repository(&path, full).await?, output),
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct BuildArgs {
+    /// Package manifest, or the directory containing PLPM.toml
+    #[arg(default_value = ".")]
+    pub manifest: PathBuf,
+    /// Staging tree to package instead of the one named in the manifest
+    #[arg(long)]
+    pub staging: Option<PathBuf>,
+    /// Directory to write the package to
+    #[arg(short = 'o', long, default_value = ".")]
+    pub out_dir: PathBuf,
+    #[arg(long)]
+    pub arch: Option<Architecture>,
+    #[arg(long)]
+    pub channel: Option<Channel>,
+}
+
+pub async fn run_build(args: BuildArgs, output: OutputFormat, config: &Config) -> Result<()> {
+    let built = operations::build_package(
+        &args.manifest,
+        args.staging.as_deref(),
+        &args.out_dir,
+        args.arch,
+        args.channel,
+        config,
+    )
+    .await?;
+    emit(&built, output)
+}
+
+// Text output for operation results. `--output json` serializes the same
+// values, so anything shown here should also be a field on the result.
+pub trait Render: Serialize {
+    fn render(&self);
+}
+
+pub fn emit<T: Render>(value: &T, output: OutputFormat) -> Result<()> {
+    match output {
+        OutputFormat::Text => value.render(),
+        OutputFormat::Json => println!("{}", to_json(value)?),
+    }
+    Ok(())
+}
+
+pub fn report_error(error: &PpmError, output: OutputFormat) {
+    #[derive(Serialize)]
+    struct ErrorReport {
+        error: String,
+        code: u8,
+    }
+
+    let report = ErrorReport {
+        error: error.to_string(),
+        code: error.exit_code(),
+    };
+    match (output, to_json(&report)) {
+        (OutputFormat::Json, Ok(json)) => println!("{}", json),
+        _ => eprintln!("❌ {}", error),
+    }
+}
+
+fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    serde_json::to_string_pretty(value).map_err(|e| PpmError::Serialization(e.to_string()))
+}
+
+fn warn(warnings: &[String]) {
+    for warning in warnings {
+        println!("⚠️ {}", warning);
+    }
+}
+
+fn show_scripts(runs: &[ScriptRun]) {
+    for run in runs {
+        println!(" 📜 {} script exited with status {}", run.script, run.status);
+        for line in run.stdout.lines().chain(run.stderr.lines()) {
+            println!("    {}", line);
+        }
+    }
+}
+
+impl Render for InstallReport {
+    fn render(&self) {
+        warn(&self.warnings);
+        if self.installed.is_empty() {
+            println!("✅ Nothing to install");
+        }
+        for pkg in &self.installed {
+            println!("📥 Installed {}-{} ({} files)", pkg.name, pkg.version, pkg.files);
+            if !pkg.features.is_empty() {
+                println!(" Features: {}", pkg.features.join(", "));
+            }
+            for name in &pkg.replaced {
+                println!(" 🔁 Replaced {}", name);
+            }
+            if let Some(key) = &pkg.signed_by {
+                println!(" 🔏 Signature verified (key {})", key);
+            }
+            show_scripts(&pkg.scripts);
+        }
+    }
+}
+
+impl Render for RemoveReport {
+    fn render(&self) {
+        warn(&self.warnings);
+        if self.removed.is_empty() {
+            println!("✅ Nothing to remove");
+        }
+        for pkg in &self.removed {
+            println!("🗑️ Removed package: {}-{} ({} files)", pkg.name, pkg.version, pkg.files);
+            show_scripts(&pkg.scripts);
+        }
+    }
+}
+
+impl Render for SearchResults {
+    fn render(&self) {
+        warn(&self.warnings);
+        println!("🔍 Results for '{}' in {} channel:", self.query, self.channel.name());
+        for pkg in &self.matches {
+            println!(" {} {} - {}", pkg.name, pkg.version, pkg.description.as_deref().unwrap_or(""));
+        }
+    }
+}
+
+impl Render for PackageDetails {
+    fn render(&self) {
+        warn(&self.warnings);
+        let pkg = &self.package;
+        println!(" Name:         {}", pkg.name);
+        println!(" Version:      {}", pkg.version);
+        println!(" Channel:      {}", pkg.channel);
+        println!(" Architecture: {}", pkg.architecture);
+        if let Some(description) = &pkg.description {
+            println!(" Description:  {}", description);
+        }
+        if let Some(license) = &pkg.license {
+            println!(" License:      {}", license);
+        }
+        show_relation("Depends on:  ", &pkg.dependencies);
+        show_relation("Recommends:  ", &pkg.recommends);
+        show_relation("Provides:    ", &pkg.provides);
+        show_relation("Conflicts:   ", &pkg.conflicts);
+        show_relation("Replaces:    ", &pkg.replaces);
+        for (feature, deps) in &pkg.features {
+            let deps: Vec<String> = deps.iter().map(|d| d.to_string()).collect();
+            if deps.is_empty() {
+                println!(" Feature:      {}", feature);
+            } else {
+                println!(" Feature:      {} ({})", feature, deps.join(", "));
+            }
+        }
+        println!(" Size:         {} bytes ({} installed)", pkg.size, pkg.install_size);
+        if let Some(version) = &self.installed {
+            println!(" Installed:    {}", version);
+        }
+    }
+}
+
+fn show_relation(label: &str, deps: &[Dependency]) {
+    if !deps.is_empty() {
+        let deps: Vec<String> = deps.iter().map(|d| d.to_string()).collect();
+        println!(" {} {}", label, deps.join(", "));
+    }
+}
+
+impl Render for Vec<InstalledPackage> {
+    fn render(&self) {
+        for pkg in self {
+            println!(
+                " {} {} [{}] ({})",
+                pkg.name, pkg.version, pkg.architecture, pkg.reason.as_str()
+            );
+        }
+    }
+}
+
+impl Render for Ownership {
+    fn render(&self) {
+        if self.directory {
+            println!("📂 {} holds files from {}", self.path, self.owners.join(", "));
+        } else {
+            println!("📄 {} is owned by {}", self.path, self.owners.join(", "));
+        }
+    }
+}
+
+impl Render for PackageFiles {
+    fn render(&self) {
+        for file in &self.files {
+            println!("{} {}", self.name, file.path);
+        }
+    }
+}
+
+impl Render for UpgradePlan {
+    fn render(&self) {
+        warn(&self.warnings);
+        if self.upgrades.is_empty() {
+            println!("✅ Everything in the {} channel is up to date", self.channel.name());
+        }
+        for upgrade in &self.upgrades {
+            println!(" {} {} -> {}", upgrade.name, upgrade.installed, upgrade.available);
+        }
+    }
+}
+
+impl Render for CleanReport {
+    fn render(&self) {
+        println!("🧹 Removed {} files ({} bytes) from the package cache", self.removed, self.freed);
+    }
+}
+
+impl Render for KeyRecord {
+    fn render(&self) {
+        let icon = match self.trust {
+            KeyTrust::Trusted => "✅",
+            KeyTrust::Untrusted => "🔑",
+            KeyTrust::Revoked => "⛔",
+        };
+        println!("{} Key {} ({}) is {}", icon, self.fingerprint, self.owner, self.trust.as_str());
+    }
+}
+
+impl Render for Vec<KeyRecord> {
+    fn render(&self) {
+        if self.is_empty() {
+            println!("🔑 No keys in the keyring");
+        }
+        let now = now_timestamp();
+        for key in self {
+            let status = if key.trust == KeyTrust::Trusted && key.is_expired(now) {
+                "expired"
+            } else {
+                key.trust.as_str()
+            };
+            let channels = if key.channels.is_empty() {
+                "all channels".to_string()
+            } else {
+                key.channels.iter().map(|c| c.name()).collect::<Vec<_>>().join(",")
+            };
+            let repositories = if key.repositories.is_empty() {
+                "all repositories".to_string()
+            } else {
+                key.repositories.join(",")
+            };
+            println!("  {} {} [{}] {}; {}", key.fingerprint, key.owner, status, channels, repositories);
+            if let Some(expiry) = key.expires_at {
+                println!("      expires at {}", expiry);
+            }
+            if let Some(reason) = &key.revocation_reason {
+                println!("      revoked: {}", reason);
+            }
+        }
+    }
+}
+
+impl Render for RepositoryBuild {
+    fn render(&self) {
+        println!(
+            "🏗️ Scanned {} packages ({} unchanged since the last build)",
+            self.scanned, self.reused
+        );
+        for index in &self.indexes {
+            println!(
+                " {} {}: {} packages",
+                index.channel.name(),
+                index.architecture.as_str(),
+                index.packages
+            );
+        }
+        for path in &self.written {
+            println!(" 📝 {}", path.display());
+        }
+    }
+}
+
+impl Render for BuiltPackage {
+    fn render(&self) {
+        println!(
+            "📦 Built {} {} ({} files, {} bytes, {} {})",
+            self.name,
+            self.version,
+            self.files,
+            self.size,
+            self.channel.name(),
+            self.architecture.as_str()
+        );
+        println!(" {}", self.path.display());
+        println!(" sha256 {}", self.checksum);
+    }
+}
+
+// Renders operation events on stderr so they never mix with results on
+// stdout. Download bars redraw in place only when stderr is a terminal.
+pub struct ProgressRenderer {
+    interactive: bool,
+}
+
+impl ProgressRenderer {
+    pub fn new() -> Self {
+        Self {
+            interactive: std::io::stderr().is_terminal(),
+        }
+    }
+}
+
+impl Default for ProgressRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Observer for ProgressRenderer {
+    fn on_event(&self, event: &Event) {
+        match event {
+            Event::ResolveStarted { requested } => eprintln!("🧩 Resolving {}", requested.join(", ")),
+            Event::ResolveFinished { packages } => eprintln!("🧩 {} packages to install", packages.len()),
+            Event::DownloadStarted { package, source, .. } => eprintln!("🌐 Fetching {} from {}", package, source),
+            Event::DownloadProgress { package, bytes, total } if self.interactive => {
+                eprint!("\r   {} {}", package, progress_bar(*bytes, *total));
+                let _ = std::io::stderr().flush();
+            }
+            Event::DownloadProgress { .. } => {}
+            Event::DownloadFinished { package, bytes, cached } => {
+                if self.interactive && !cached {
+                    eprint!("\r\x1b[2K");
+                }
+                let how = if *cached { "cached" } else { "downloaded" };
+                eprintln!("📦 {} {} ({})", package, how, format_bytes(*bytes));
+            }
+            Event::Verified { package, signed_by: Some(key) } => {
+                eprintln!("🔏 {} signature verified (key {})", package, key)
+            }
+            Event::Verified { package, signed_by: None } => eprintln!("⚠️ {} is unsigned", package),
+            Event::ExtractStarted { package, files } => eprintln!("📂 Extracting {} ({} files)", package, files),
+            Event::FileExtracted { index, total, .. } if self.interactive => {
+                eprint!("\r   {}/{} files", index, total);
+                let _ = std::io::stderr().flush();
+            }
+            Event::FileExtracted { .. } => {}
+            Event::ExtractFinished { .. } => {
+                if self.interactive {
+                    eprint!("\r\x1b[2K");
+                }
+            }
+            Event::ScriptStarted { package, script } => eprintln!("📜 Running {} script of {}", script, package),
+            Event::ScriptFinished { package, script, status } => {
+                eprintln!("📜 {} script of {} exited with {}", script, package, status)
+            }
+            Event::TransactionCommitted { installed, removed, .. } => eprintln!(
+                "✅ Committed ({} installed, {} removed)",
+                installed.len(),
+                removed.len()
+            ),
+        }
+    }
+}
+
+fn progress_bar(bytes: u64, total: Option<u64>) -> String {
+    const WIDTH: u64 = 30;
+    match total {
+        Some(total) if total > 0 => {
+            let filled = (bytes.min(total) * WIDTH / total) as usize;
+            format!(
+                "[{}{}] {} / {}",
+                "#".repeat(filled),
+                " ".repeat(WIDTH as usize - filled),
+                format_bytes(bytes),
+                format_bytes(total)
+            )
+        }
+        _ => format_bytes(bytes),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -1,22 +1,169 @@
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub repository_url: String,
+    #[serde(default)]
+    pub repositories: Vec<RepositoryConfig>,
+    #[serde(default)]
+    pub pins: Vec<PinRule>,
     pub cache_dir: String,
     pub keyring_dir: String,
+    #[serde(default)]
+    pub signature_policy: crate::SignaturePolicy,
+    #[serde(default = "default_state_dir")]
+    pub state_dir: String,
     pub architecture: crate::Architecture,
     pub channel: crate::Channel,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub cache: crate::CacheConfig,
+    #[serde(default = "default_root")]
+    pub root: String,
+    // Package scripts still running after this many seconds are killed; 0
+    // lets them run for as long as they like.
+    #[serde(default = "default_script_timeout")]
+    pub script_timeout_secs: u64,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            repository_url: "https:
+            repository_url: "https://repo.plumos.org".to_string(),
+            repositories: Vec::new(),
+            pins: Vec::new(),
             cache_dir: "/var/cache/ppm".to_string(),
             keyring_dir: "/etc/ppm/keys".to_string(),
+            signature_policy: crate::SignaturePolicy::default(),
+            state_dir: default_state_dir(),
             architecture: crate::Architecture::current(),
             channel: crate::Channel::Stable,
+            network: NetworkConfig::default(),
+            cache: crate::CacheConfig::default(),
+            root: default_root(),
+            script_timeout_secs: default_script_timeout(),
         }
     }
-}
\ No newline at end of file
+}
+
+impl Config {
+    // Installs into `root` instead of the running system, with the database,
+    // cache and keyring moved underneath it so the result is self-contained.
+    pub fn with_root(mut self, root: impl AsRef<Path>) -> Self {
+        let root = std::path::absolute(root.as_ref()).unwrap_or_else(|_| root.as_ref().to_path_buf());
+        let rebase = |dir: &str| root.join(dir.trim_start_matches('/')).to_string_lossy().into_owned();
+        self.state_dir = rebase(&self.state_dir);
+        self.cache_dir = rebase(&self.cache_dir);
+        self.keyring_dir = rebase(&self.keyring_dir);
+        self.root = root.to_string_lossy().into_owned();
+        self
+    }
+
+    // Without any `[[repositories]]` entries the legacy `repository_url` is
+    // used as a single repository named "main".
+    pub fn enabled_repositories(&self) -> Vec<RepositoryConfig> {
+        if self.repositories.is_empty() {
+            return vec![RepositoryConfig {
+                name: "main".to_string(),
+                url: self.repository_url.clone(),
+                channel: None,
+                architectures: Vec::new(),
+                priority: 0,
+                enabled: true,
+                signed_metadata: false,
+            }];
+        }
+        self.repositories.iter().filter(|r| r.enabled).cloned().collect()
+    }
+}
+
+fn default_state_dir() -> String {
+    "/var/lib/ppm".to_string()
+}
+
+fn default_root() -> String {
+    "/".to_string()
+}
+
+fn default_script_timeout() -> u64 {
+    600
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub timeout_secs: u64,
+    pub retries: u32,
+    pub retry_delay_ms: u64,
+    pub parallel_downloads: usize,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 30,
+            retries: 3,
+            retry_delay_ms: 500,
+            parallel_downloads: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepositoryConfig {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub channel: Option<crate::Channel>,
+    #[serde(default)]
+    pub architectures: Vec<crate::Architecture>,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub signed_metadata: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinRule {
+    pub package: String,
+    pub repository: String,
+    #[serde(default)]
+    pub version: Option<crate::VersionReq>,
+}
+
+impl PinRule {
+    // `package` is an exact name, or a prefix when it ends in `*`.
+    pub fn applies_to(&self, name: &str) -> bool {
+        match self.package.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => self.package == name,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebases_state_below_the_install_root() {
+        let config = Config::default().with_root("/mnt/target/");
+        assert_eq!(config.root, "/mnt/target/");
+        assert_eq!(config.state_dir, "/mnt/target/var/lib/ppm");
+        assert_eq!(config.cache_dir, "/mnt/target/var/cache/ppm");
+        assert_eq!(config.keyring_dir, "/mnt/target/etc/ppm/keys");
+
+        let config = Config { state_dir: "state".to_string(), ..Config::default() }.with_root("target");
+        let root = std::env::current_dir().unwrap().join("target");
+        assert_eq!(Path::new(&config.root), root);
+        assert_eq!(Path::new(&config.state_dir), root.join("state"));
+    }
+}
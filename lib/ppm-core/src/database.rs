@@ -0,0 +1,519 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::formats::plpm::PlpmScripts;
+use crate::package::SandboxConfig;
+use crate::{Architecture, Channel, Dependency, PpmError, Result, Version};
+
+const INSTALLED_DIR: &str = "installed";
+const LOCK_FILE: &str = "lock";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InstallReason {
+    #[serde(rename = "explicit")]
+    Explicit,
+    #[serde(rename = "dependency")]
+    Dependency,
+}
+
+impl InstallReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InstallReason::Explicit => "explicit",
+            InstallReason::Dependency => "dependency",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstalledFile {
+    pub path: String,
+    pub checksum: String,
+    pub size: u64,
+    pub permissions: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub version: Version,
+    pub architecture: Architecture,
+    pub channel: Channel,
+    pub reason: InstallReason,
+    pub installed_at: u64,
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
+    #[serde(default)]
+    pub recommends: Vec<Dependency>,
+    #[serde(default)]
+    pub conflicts: Vec<Dependency>,
+    #[serde(default)]
+    pub provides: Vec<Dependency>,
+    #[serde(default)]
+    pub replaces: Vec<Dependency>,
+    // Optional features enabled at install time; their dependencies are
+    // folded into `dependencies`.
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default)]
+    pub files: Vec<InstalledFile>,
+    // sha256 of the `.plpm` it was installed from, which keeps that archive
+    // alive in the package cache.
+    #[serde(default)]
+    pub archive: Option<String>,
+    // Kept so the remove hooks can run after the archive has left the cache.
+    #[serde(default)]
+    pub scripts: Option<PlpmScripts>,
+    #[serde(default)]
+    pub sandbox: Option<SandboxConfig>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Query<'a> {
+    pub name: Option<&'a str>,
+    pub channel: Option<Channel>,
+    pub architecture: Option<Architecture>,
+    pub reason: Option<InstallReason>,
+}
+
+impl Query<'_> {
+    pub fn matches(&self, pkg: &InstalledPackage) -> bool {
+        self.name.is_none_or(|n| pkg.name.contains(n))
+            && self.channel.is_none_or(|c| pkg.channel == c)
+            && self.architecture.is_none_or(|a| pkg.architecture == a)
+            && self.reason.is_none_or(|r| pkg.reason == r)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+// Each package lives in its own `installed/<name>.toml` record under the
+// state directory; `lock` serialises writers across processes. `owners`
+// maps every recorded file path to the packages listing it, which is one
+// package unless something was overwritten before ownership was tracked.
+pub struct InstalledDatabase {
+    dir: PathBuf,
+    mode: LockMode,
+    packages: BTreeMap<String, InstalledPackage>,
+    owners: BTreeMap<String, Vec<String>>,
+    _lock: Option<File>,
+}
+
+impl InstalledDatabase {
+    pub fn open(state_dir: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with(state_dir, LockMode::Exclusive)
+    }
+
+    pub fn open_read_only(state_dir: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with(state_dir, LockMode::Shared)
+    }
+
+    fn open_with(state_dir: impl AsRef<Path>, mode: LockMode) -> Result<Self> {
+        let state_dir = state_dir.as_ref();
+        let dir = state_dir.join(INSTALLED_DIR);
+        // Readers never create anything, so queries work for users who
+        // cannot write the state directory; one that was never set up is
+        // simply an empty database.
+        let lock = match mode {
+            LockMode::Shared => match File::open(state_dir.join(LOCK_FILE)) {
+                Ok(lock) => {
+                    lock.lock_shared()?;
+                    Some(lock)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            },
+            LockMode::Exclusive => {
+                fs::create_dir_all(&dir)?;
+                let lock = OpenOptions::new()
+                    .create(true)
+                    .truncate(false)
+                    .write(true)
+                    .open(state_dir.join(LOCK_FILE))?;
+                lock.lock()?;
+                Some(lock)
+            }
+        };
+
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries.collect::<std::io::Result<Vec<_>>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && mode == LockMode::Shared => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut packages = BTreeMap::new();
+        for entry in entries {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("toml") {
+                continue;
+            }
+            let contents = fs::read_to_string(&path)?;
+            let pkg: InstalledPackage = toml::from_str(&contents).map_err(|e| {
+                PpmError::Serialization(format!("{}: {}", path.display(), e))
+            })?;
+            packages.insert(pkg.name.clone(), pkg);
+        }
+
+        let mut db = Self {
+            dir,
+            mode,
+            packages: BTreeMap::new(),
+            owners: BTreeMap::new(),
+            _lock: lock,
+        };
+        for pkg in packages.into_values() {
+            db.index(pkg);
+        }
+        Ok(db)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&InstalledPackage> {
+        self.packages.get(name)
+    }
+
+    pub fn is_installed(&self, name: &str) -> bool {
+        self.packages.contains_key(name)
+    }
+
+    pub fn packages(&self) -> impl Iterator<Item = &InstalledPackage> {
+        self.packages.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    pub fn owners(&self, path: &str) -> &[String] {
+        self.owners.get(path).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    // Packages with files somewhere below the directory `dir`.
+    pub fn owners_under(&self, dir: &str) -> BTreeSet<&str> {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        self.owners
+            .range(prefix.clone()..)
+            .take_while(|(path, _)| path.starts_with(&prefix))
+            .flat_map(|(_, owners)| owners.iter().map(String::as_str))
+            .collect()
+    }
+
+    pub fn query<'a>(&'a self, query: &'a Query<'a>) -> impl Iterator<Item = &'a InstalledPackage> {
+        self.packages.values().filter(move |pkg| query.matches(pkg))
+    }
+
+    // Packages that would be left with an unmet dependency without `name`,
+    // because no other installed package provides what they need from it.
+    pub fn required_by(&self, name: &str) -> Vec<&InstalledPackage> {
+        let Some(target) = self.get(name) else {
+            return Vec::new();
+        };
+        self.packages
+            .values()
+            .filter(|pkg| pkg.name != name)
+            .filter(|pkg| {
+                pkg.dependencies.iter().any(|dep| {
+                    target.relations().satisfies(dep)
+                        && !self.packages.values().any(|other| other.name != name && other.relations().satisfies(dep))
+                })
+            })
+            .collect()
+    }
+
+    // Packages installed as dependencies that no explicit install reaches
+    // any more through dependencies or recommendations, treating `gone` as
+    // already removed.
+    pub fn orphans(&self, gone: &[&str]) -> Vec<&InstalledPackage> {
+        let present = |pkg: &&InstalledPackage| !gone.contains(&pkg.name.as_str());
+        let mut needed = HashSet::new();
+        let mut stack: Vec<&InstalledPackage> = self
+            .packages
+            .values()
+            .filter(present)
+            .filter(|pkg| pkg.reason == InstallReason::Explicit)
+            .collect();
+        while let Some(pkg) = stack.pop() {
+            if !needed.insert(pkg.name.as_str()) {
+                continue;
+            }
+            for dep in pkg.dependencies.iter().chain(&pkg.recommends) {
+                stack.extend(self.packages.values().filter(present).filter(|p| p.relations().satisfies(dep)));
+            }
+        }
+        self.packages
+            .values()
+            .filter(present)
+            .filter(|pkg| !needed.contains(pkg.name.as_str()))
+            .collect()
+    }
+
+    pub fn insert(&mut self, pkg: InstalledPackage) -> Result<()> {
+        self.ensure_writable()?;
+        check_package_name(&pkg.name)?;
+        let contents = toml::to_string_pretty(&pkg)
+            .map_err(|e| PpmError::Serialization(e.to_string()))?;
+        let path = self.record_path(&pkg.name);
+        let tmp = path.with_extension("toml.tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &path)?;
+        self.unindex(&pkg.name);
+        self.index(pkg);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<Option<InstalledPackage>> {
+        self.ensure_writable()?;
+        let removed = self.unindex(name);
+        if removed.is_some() {
+            match fs::remove_file(self.record_path(name)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(removed)
+    }
+
+    pub fn set_reason(&mut self, name: &str, reason: InstallReason) -> Result<()> {
+        let mut pkg = self
+            .get(name)
+            .cloned()
+            .ok_or_else(|| PpmError::PackageNotFound(name.to_string()))?;
+        pkg.reason = reason;
+        self.insert(pkg)
+    }
+
+    fn index(&mut self, pkg: InstalledPackage) {
+        for file in &pkg.files {
+            self.owners.entry(file.path.clone()).or_default().push(pkg.name.clone());
+        }
+        self.packages.insert(pkg.name.clone(), pkg);
+    }
+
+    fn unindex(&mut self, name: &str) -> Option<InstalledPackage> {
+        let pkg = self.packages.remove(name)?;
+        for file in &pkg.files {
+            if let Some(owners) = self.owners.get_mut(&file.path) {
+                owners.retain(|owner| owner != name);
+                if owners.is_empty() {
+                    self.owners.remove(&file.path);
+                }
+            }
+        }
+        Some(pkg)
+    }
+
+    fn ensure_writable(&self) -> Result<()> {
+        if self.mode == LockMode::Shared {
+            return Err(PpmError::Io(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "installed database was opened read-only",
+            )));
+        }
+        Ok(())
+    }
+
+    fn record_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.toml", name))
+    }
+}
+
+// Names become record file names, so nothing that could leave the database
+// directory or hide the record is accepted.
+pub(crate) fn check_package_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(PpmError::InvalidPackage(format!("Invalid package name: '{}'", name)));
+    }
+    Ok(())
+}
+
+pub fn now_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+impl InstalledPackage {
+    // Counterpart of `Package::for_test` for database records.
+    pub(crate) fn for_test(name: &str, version: &str, reason: InstallReason, dependencies: &[&str]) -> Self {
+        InstalledPackage {
+            name: name.to_string(),
+            version: version.parse().unwrap(),
+            architecture: Architecture::X86_64,
+            channel: Channel::Stable,
+            reason,
+            installed_at: 0,
+            dependencies: dependencies.iter().map(|d| d.parse().unwrap()).collect(),
+            recommends: Vec::new(),
+            conflicts: Vec::new(),
+            provides: Vec::new(),
+            replaces: Vec::new(),
+            features: Vec::new(),
+            files: Vec::new(),
+            archive: None,
+            scripts: None,
+            sandbox: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::TestDir;
+    use InstallReason::{Dependency as Dep, Explicit};
+
+    struct Fixture {
+        dir: TestDir,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = TestDir::new("database", name);
+            Self { dir }
+        }
+
+        fn open(&self, packages: Vec<InstalledPackage>) -> InstalledDatabase {
+            let mut db = InstalledDatabase::open(&self.dir).unwrap();
+            for pkg in packages {
+                db.insert(pkg).unwrap();
+            }
+            db
+        }
+    }
+
+    fn names(packages: Vec<&InstalledPackage>) -> Vec<&str> {
+        packages.into_iter().map(|p| p.name.as_str()).collect()
+    }
+
+    #[test]
+    fn finds_orphaned_dependencies() {
+        let fixture = Fixture::new("orphans");
+        let mut app = InstalledPackage::for_test("app", "1.0.0", Explicit, &["lib", "mta"]);
+        app.recommends = vec!["docs".parse().unwrap()];
+        let mut postfix = InstalledPackage::for_test("postfix", "3.0.0", Dep, &[]);
+        postfix.provides = vec!["mta".parse().unwrap()];
+        let db = fixture.open(vec![
+            app,
+            InstalledPackage::for_test("lib", "1.0.0", Dep, &["base"]),
+            InstalledPackage::for_test("base", "1.0.0", Dep, &[]),
+            InstalledPackage::for_test("docs", "1.0.0", Dep, &[]),
+            postfix,
+            InstalledPackage::for_test("stale", "1.0.0", Dep, &[]),
+        ]);
+        assert_eq!(names(db.orphans(&[])), ["stale"]);
+        // Without app, everything it pulled in goes too.
+        assert_eq!(names(db.orphans(&["app"])), ["base", "docs", "lib", "postfix", "stale"]);
+        assert_eq!(names(db.orphans(&["lib"])), ["base", "stale"]);
+    }
+
+    fn file(path: &str) -> InstalledFile {
+        InstalledFile {
+            path: path.to_string(),
+            checksum: String::new(),
+            size: 0,
+            permissions: 0o644,
+        }
+    }
+
+    fn held(fixture: &Fixture, shared: bool) -> bool {
+        let lock = File::open(fixture.dir.join(LOCK_FILE)).unwrap();
+        let taken = if shared { lock.try_lock_shared() } else { lock.try_lock() };
+        taken.is_err()
+    }
+
+    #[test]
+    fn readers_share_the_lock_and_writers_exclude_everyone() {
+        let fixture = Fixture::new("locking");
+        // A reader of a state directory that was never set up sees an empty
+        // database and creates nothing.
+        let db = InstalledDatabase::open_read_only(&fixture.dir).unwrap();
+        assert!(db.is_empty());
+        assert!(!fixture.dir.exists());
+
+        let db = fixture.open(vec![InstalledPackage::for_test("app", "1.0.0", Explicit, &[])]);
+        assert!(held(&fixture, true));
+        drop(db);
+
+        let mut db = InstalledDatabase::open_read_only(&fixture.dir).unwrap();
+        assert!(db.is_installed("app"));
+        assert!(!held(&fixture, true));
+        assert!(held(&fixture, false));
+        let denied = db.insert(InstalledPackage::for_test("lib", "1.0.0", Dep, &[]));
+        assert!(matches!(denied, Err(PpmError::Io(e)) if e.kind() == std::io::ErrorKind::PermissionDenied));
+        assert!(matches!(db.remove("app"), Err(PpmError::Io(_))));
+        drop(db);
+        assert!(!held(&fixture, false));
+    }
+
+    #[test]
+    fn inserts_replace_records_atomically() {
+        let fixture = Fixture::new("insert");
+        let mut db = fixture.open(vec![InstalledPackage::for_test("app", "1.0.0", Explicit, &[])]);
+        db.insert(InstalledPackage::for_test("app", "2.0.0", Explicit, &[])).unwrap();
+        let dir = fixture.dir.join(INSTALLED_DIR);
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["app.toml"]);
+        assert!(matches!(
+            db.insert(InstalledPackage::for_test("../app", "1.0.0", Explicit, &[])),
+            Err(PpmError::InvalidPackage(_))
+        ));
+        drop(db);
+
+        // A write interrupted before its rename leaves only a temporary file,
+        // which never shadows the committed record.
+        fs::write(dir.join("app.toml.tmp"), "not a record").unwrap();
+        let db = InstalledDatabase::open(&fixture.dir).unwrap();
+        assert_eq!(db.get("app").unwrap().version.to_string(), "2.0.0");
+        assert_eq!(db.len(), 1);
+    }
+
+    #[test]
+    fn tracks_file_owners_and_dependents() {
+        let fixture = Fixture::new("owners");
+        let mut app = InstalledPackage::for_test("app", "1.0.0", Explicit, &["lib", "mta"]);
+        app.files = vec![file("usr/bin/app"), file("usr/share/doc/README")];
+        let mut lib = InstalledPackage::for_test("lib", "1.0.0", Dep, &[]);
+        lib.files = vec![file("usr/lib/libfoo.so"), file("usr/share/doc/README")];
+        let mut postfix = InstalledPackage::for_test("postfix", "3.0.0", Dep, &[]);
+        postfix.provides = vec!["mta".parse().unwrap()];
+        let mut exim = InstalledPackage::for_test("exim", "4.0.0", Dep, &[]);
+        exim.provides = vec!["mta".parse().unwrap()];
+        let mut db = fixture.open(vec![app, lib, postfix, exim]);
+
+        assert_eq!(db.owners("usr/bin/app"), ["app"]);
+        assert_eq!(db.owners("usr/share/doc/README"), ["app", "lib"]);
+        assert!(db.owners("usr/bin").is_empty());
+        assert_eq!(db.owners_under("usr/share/").into_iter().collect::<Vec<_>>(), ["app", "lib"]);
+        assert_eq!(db.owners_under("usr/lib").into_iter().collect::<Vec<_>>(), ["lib"]);
+        // A sibling with a common prefix is not below the directory.
+        assert!(db.owners_under("usr/li").is_empty());
+
+        assert_eq!(names(db.required_by("lib")), ["app"]);
+        // Either mail transport satisfies app on its own.
+        assert!(db.required_by("postfix").is_empty());
+        db.remove("exim").unwrap();
+        assert_eq!(names(db.required_by("postfix")), ["app"]);
+        db.remove("lib").unwrap();
+        assert_eq!(db.owners("usr/share/doc/README"), ["app"]);
+        assert!(db.owners("usr/lib/libfoo.so").is_empty());
+    }
+}
@@ -14,14 +14,54 @@ pub enum PpmError {
     #[error("Invalid package format: {0}")]
     InvalidPackage(String),
     
+    #[error("Invalid version: {0}")]
+    InvalidVersion(String),
+    
     #[error("Package not found: {0}")]
     PackageNotFound(String),
     
     #[error("Dependency resolution failed: {0}")]
     DependencyResolution(String),
     
+    #[error("Transaction failed: {0}")]
+    Transaction(String),
+    
+    #[error("Repository error: {0}")]
+    Repository(String),
+    
+    #[error("Keyring error: {0}")]
+    Keyring(String),
+    
     #[error("Security violation: {0}")]
     SecurityViolation(String),
+
+    #[error("Package script failed: {0}")]
+    Script(String),
+
+    #[error("File conflict: {0}")]
+    FileConflict(String),
+}
+
+impl PpmError {
+    // Process exit status for the `ppm` binary. 1 and 2 stay reserved for
+    // unexpected failures and command-line usage errors.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            PpmError::PackageNotFound(_) => 3,
+            PpmError::DependencyResolution(_) => 4,
+            PpmError::InvalidPackage(_) => 5,
+            PpmError::InvalidVersion(_) => 6,
+            PpmError::Signature(_) => 7,
+            PpmError::SecurityViolation(_) => 8,
+            PpmError::Keyring(_) => 9,
+            PpmError::Repository(_) => 10,
+            PpmError::Transaction(_) => 11,
+            PpmError::Serialization(_) => 12,
+            PpmError::Io(_) => 13,
+            PpmError::Script(_) => 14,
+            PpmError::FileConflict(_) => 15,
+        }
+    }
 }
 
-pub type Result<T> = std::result::Result<T, PpmError>;
\ No newline at end of file
+pub type Result<T> = std::result::Result<T, PpmError>;
@@ -0,0 +1,124 @@
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event")]
+pub enum Event {
+    #[serde(rename = "resolve-started")]
+    ResolveStarted { requested: Vec<String> },
+    #[serde(rename = "resolve-finished")]
+    ResolveFinished { packages: Vec<String> },
+    #[serde(rename = "download-started")]
+    DownloadStarted { package: String, source: String, total: Option<u64> },
+    #[serde(rename = "download-progress")]
+    DownloadProgress { package: String, bytes: u64, total: Option<u64> },
+    #[serde(rename = "download-finished")]
+    DownloadFinished { package: String, bytes: u64, cached: bool },
+    #[serde(rename = "verified")]
+    Verified { package: String, signed_by: Option<String> },
+    #[serde(rename = "extract-started")]
+    ExtractStarted { package: String, files: usize },
+    #[serde(rename = "file-extracted")]
+    FileExtracted { package: String, path: String, index: usize, total: usize },
+    #[serde(rename = "extract-finished")]
+    ExtractFinished { package: String },
+    #[serde(rename = "script-started")]
+    ScriptStarted { package: String, script: String },
+    #[serde(rename = "script-finished")]
+    ScriptFinished { package: String, script: String, status: i32 },
+    #[serde(rename = "transaction-committed")]
+    TransactionCommitted { id: String, installed: Vec<String>, removed: Vec<String> },
+}
+
+// Observers are called synchronously on whichever task emits the event, so
+// they should hand off anything slow (UI updates, network) themselves.
+pub trait Observer: Send + Sync {
+    fn on_event(&self, event: &Event);
+}
+
+impl<F: Fn(&Event) + Send + Sync> Observer for F {
+    fn on_event(&self, event: &Event) {
+        self(event)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Events {
+    observers: Vec<Arc<dyn Observer>>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&mut self, observer: impl Observer + 'static) {
+        self.observers.push(Arc::new(observer));
+    }
+
+    pub fn with_observer(mut self, observer: impl Observer + 'static) -> Self {
+        self.subscribe(observer);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    pub fn emit(&self, event: Event) {
+        for observer in &self.observers {
+            observer.on_event(&event);
+        }
+    }
+}
+
+impl fmt::Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Events").field("observers", &self.observers.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder {
+        seen: Mutex<Vec<String>>,
+    }
+
+    impl Observer for Arc<Recorder> {
+        fn on_event(&self, event: &Event) {
+            self.seen.lock().unwrap().push(format!("recorder: {:?}", event));
+        }
+    }
+
+    #[test]
+    fn dispatches_to_every_observer_in_subscription_order() {
+        let recorder = Arc::new(Recorder::default());
+        let log = Arc::clone(&recorder);
+        let mut events = Events::new().with_observer(Arc::clone(&recorder));
+        assert!(!events.is_empty());
+        events.subscribe(move |event: &Event| {
+            if let Event::ExtractFinished { package } = event {
+                log.seen.lock().unwrap().push(format!("closure: {}", package));
+            }
+        });
+
+        // Clones share their observers.
+        events.clone().emit(Event::ResolveStarted { requested: vec!["app".to_string()] });
+        events.emit(Event::ExtractFinished { package: "app-1.0.0".to_string() });
+        assert_eq!(
+            *recorder.seen.lock().unwrap(),
+            [
+                r#"recorder: ResolveStarted { requested: ["app"] }"#,
+                r#"recorder: ExtractFinished { package: "app-1.0.0" }"#,
+                "closure: app-1.0.0",
+            ]
+        );
+        assert_eq!(format!("{:?}", events), "Events { observers: 2 }");
+        assert!(Events::default().is_empty());
+    }
+}
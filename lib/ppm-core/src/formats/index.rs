@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+
+use crate::formats::plpm::{
+    architecture_code, architecture_from_code, channel_code, channel_from_code,
+};
+use crate::package::PackageIndex;
+use crate::{Dependency, Package, PpmError, Result};
+
+pub const INDEX_MAGIC: [u8; 4] = *b"PIDX";
+pub const INDEX_FORMAT_VERSION: u16 = 2;
+pub const INDEX_BINARY_NAME: &str = "index.bin";
+pub const INDEX_TOML_NAME: &str = "index.toml";
+
+// Binary index layout:
+//
+//   0  magic "PIDX"
+//   4  format version   u16
+//   6  zstd frame holding:
+//        generated str, channel u8, package count u32, then per package:
+//        name, version, description?, author?, license?, dependency count u32
+//        + dependency strs, [v2: conflicts, provides, replaces and
+//        recommends as counted str lists, feature count u32 + per feature
+//        its name and a counted str list], architecture u8, channel u8,
+//        file, checksum, signature?, size u64, install size u64
+//
+// Strings are a u32 length followed by UTF-8 bytes; optional strings are
+// prefixed with a 0/1 presence byte. Integers are little-endian. Version 1
+// indexes, which predate the relationship fields, are still read.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexFormat {
+    Toml,
+    Binary,
+}
+
+impl IndexFormat {
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            IndexFormat::Toml => INDEX_TOML_NAME,
+            IndexFormat::Binary => INDEX_BINARY_NAME,
+        }
+    }
+
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(&INDEX_MAGIC) {
+            IndexFormat::Binary
+        } else {
+            IndexFormat::Toml
+        }
+    }
+}
+
+pub fn encode_index(index: &PackageIndex, format: IndexFormat) -> Result<Vec<u8>> {
+    match format {
+        IndexFormat::Toml => toml::to_string_pretty(index)
+            .map(String::into_bytes)
+            .map_err(|e| PpmError::Serialization(e.to_string())),
+        IndexFormat::Binary => {
+            let mut body = Vec::new();
+            put_str(&mut body, &index.generated)?;
+            body.push(channel_code(index.channel));
+            put_len(&mut body, index.packages.len())?;
+            for pkg in &index.packages {
+                put_package(&mut body, pkg)?;
+            }
+            let mut out = Vec::with_capacity(body.len() / 2);
+            out.extend_from_slice(&INDEX_MAGIC);
+            out.extend_from_slice(&INDEX_FORMAT_VERSION.to_le_bytes());
+            out.extend_from_slice(&zstd::encode_all(body.as_slice(), 19)?);
+            Ok(out)
+        }
+    }
+}
+
+pub fn decode_index(data: &[u8]) -> Result<PackageIndex> {
+    match IndexFormat::detect(data) {
+        IndexFormat::Toml => {
+            let text = std::str::from_utf8(data)
+                .map_err(|_| invalid("TOML index is not valid UTF-8"))?;
+            toml::from_str(text).map_err(|e| PpmError::Serialization(format!("invalid index: {}", e)))
+        }
+        IndexFormat::Binary => {
+            let version = data
+                .get(4..6)
+                .map(|v| u16::from_le_bytes([v[0], v[1]]))
+                .ok_or_else(|| invalid("truncated index header"))?;
+            if version == 0 || version > INDEX_FORMAT_VERSION {
+                return Err(invalid(&format!("unsupported index format version {}", version)));
+            }
+            let body = zstd::decode_all(&data[6..])
+                .map_err(|e| invalid(&format!("corrupt index payload: {}", e)))?;
+            let mut cursor = Cursor { buf: &body, pos: 0 };
+            let generated = cursor.str()?;
+            let channel = channel_from_code(cursor.u8()?).map_err(|e| invalid(&e.to_string()))?;
+            let count = cursor.u32()? as usize;
+            let mut packages = Vec::with_capacity(count.min(body.len()));
+            for _ in 0..count {
+                packages.push(cursor.package(version)?);
+            }
+            if cursor.pos != body.len() {
+                return Err(invalid("trailing data after the last package"));
+            }
+            Ok(PackageIndex {
+                packages,
+                generated,
+                channel,
+            })
+        }
+    }
+}
+
+fn invalid(msg: &str) -> PpmError {
+    PpmError::Serialization(format!("invalid index: {}", msg))
+}
+
+fn put_len(out: &mut Vec<u8>, len: usize) -> Result<()> {
+    let len = u32::try_from(len).map_err(|_| invalid("field is too large"))?;
+    out.extend_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) -> Result<()> {
+    put_len(out, s.len())?;
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn put_opt(out: &mut Vec<u8>, s: Option<&str>) -> Result<()> {
+    match s {
+        Some(s) => {
+            out.push(1);
+            put_str(out, s)
+        }
+        None => {
+            out.push(0);
+            Ok(())
+        }
+    }
+}
+
+fn put_deps(out: &mut Vec<u8>, deps: &[Dependency]) -> Result<()> {
+    put_len(out, deps.len())?;
+    for dep in deps {
+        put_str(out, &dep.to_string())?;
+    }
+    Ok(())
+}
+
+fn put_package(out: &mut Vec<u8>, pkg: &Package) -> Result<()> {
+    put_str(out, &pkg.name)?;
+    put_str(out, &pkg.version.to_string())?;
+    put_opt(out, pkg.description.as_deref())?;
+    put_opt(out, pkg.author.as_deref())?;
+    put_opt(out, pkg.license.as_deref())?;
+    put_deps(out, &pkg.dependencies)?;
+    put_deps(out, &pkg.conflicts)?;
+    put_deps(out, &pkg.provides)?;
+    put_deps(out, &pkg.replaces)?;
+    put_deps(out, &pkg.recommends)?;
+    put_len(out, pkg.features.len())?;
+    for (feature, deps) in &pkg.features {
+        put_str(out, feature)?;
+        put_deps(out, deps)?;
+    }
+    out.push(architecture_code(pkg.architecture));
+    out.push(channel_code(pkg.channel));
+    put_str(out, &pkg.file)?;
+    put_str(out, &pkg.checksum)?;
+    put_opt(out, pkg.signature.as_deref())?;
+    out.extend_from_slice(&pkg.size.to_le_bytes());
+    out.extend_from_slice(&pkg.install_size.to_le_bytes());
+    Ok(())
+}
+
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or_else(|| invalid("truncated package record"))?;
+        let slice = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?)
+            .map(str::to_string)
+            .map_err(|_| invalid("string is not valid UTF-8"))
+    }
+
+    fn opt(&mut self) -> Result<Option<String>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => self.str().map(Some),
+            _ => Err(invalid("bad optional field marker")),
+        }
+    }
+
+    fn deps(&mut self) -> Result<Vec<Dependency>> {
+        let count = self.u32()? as usize;
+        let mut deps = Vec::with_capacity(count.min(self.buf.len()));
+        for _ in 0..count {
+            deps.push(self.str()?.parse()?);
+        }
+        Ok(deps)
+    }
+
+    fn package(&mut self, format: u16) -> Result<Package> {
+        let name = self.str()?;
+        let version = self.str()?.parse()?;
+        let description = self.opt()?;
+        let author = self.opt()?;
+        let license = self.opt()?;
+        let dependencies = self.deps()?;
+        let (mut conflicts, mut provides, mut replaces, mut recommends) = Default::default();
+        let mut features = BTreeMap::new();
+        if format >= 2 {
+            conflicts = self.deps()?;
+            provides = self.deps()?;
+            replaces = self.deps()?;
+            recommends = self.deps()?;
+            for _ in 0..self.u32()? {
+                let feature = self.str()?;
+                features.insert(feature, self.deps()?);
+            }
+        }
+        let architecture = architecture_from_code(self.u8()?).map_err(|e| invalid(&e.to_string()))?;
+        let channel = channel_from_code(self.u8()?).map_err(|e| invalid(&e.to_string()))?;
+        Ok(Package {
+            name,
+            version,
+            description,
+            author,
+            license,
+            dependencies,
+            conflicts,
+            provides,
+            replaces,
+            recommends,
+            features,
+            architecture,
+            channel,
+            file: self.str()?,
+            checksum: self.str()?,
+            signature: self.opt()?,
+            size: self.u64()?,
+            install_size: self.u64()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Architecture, Channel};
+
+    fn index() -> PackageIndex {
+        let full = Package {
+            description: Some("a demo application".to_string()),
+            author: Some("PlumSDK".to_string()),
+            license: None,
+            dependencies: vec!["lib>=1.2".parse().unwrap(), "ui[icons]".parse().unwrap()],
+            conflicts: vec!["legacy".parse().unwrap()],
+            provides: vec!["viewer=2.0.0".parse().unwrap()],
+            replaces: vec!["old-app<2".parse().unwrap()],
+            recommends: vec!["docs".parse().unwrap()],
+            features: BTreeMap::from([
+                ("gpu".to_string(), vec!["vulkan>=1.3".parse().unwrap()]),
+                ("minimal".to_string(), Vec::new()),
+            ]),
+            architecture: Architecture::AArch64,
+            signature: Some("ab".repeat(64)),
+            size: 4096,
+            install_size: 1 << 40,
+            ..Package::for_test("app", "2.1.0-rc.1")
+        };
+        PackageIndex {
+            packages: vec![full, Package::for_test("lib", "1.2.0")],
+            generated: "1700000000".to_string(),
+            channel: Channel::Testing,
+        }
+    }
+
+    // Package has no PartialEq, so compare what a client would read back.
+    fn text(index: &PackageIndex) -> String {
+        toml::to_string(index).unwrap()
+    }
+
+    #[test]
+    fn round_trips_both_formats() {
+        let index = index();
+        for format in [IndexFormat::Binary, IndexFormat::Toml] {
+            let data = encode_index(&index, format).unwrap();
+            assert_eq!(IndexFormat::detect(&data), format);
+            assert_eq!(text(&decode_index(&data).unwrap()), text(&index), "{:?}", format);
+        }
+        let binary = encode_index(&index, IndexFormat::Binary).unwrap();
+        assert_eq!(encode_index(&decode_index(&binary).unwrap(), IndexFormat::Binary).unwrap(), binary);
+    }
+
+    #[test]
+    fn rejects_malformed_binary_indexes() {
+        let data = encode_index(&index(), IndexFormat::Binary).unwrap();
+        let message = |data: &[u8]| match decode_index(data) {
+            Err(PpmError::Serialization(message)) => message,
+            other => panic!("expected an invalid index, got {:?}", other.map(|_| ())),
+        };
+
+        let mut future = data.clone();
+        future[4..6].copy_from_slice(&(INDEX_FORMAT_VERSION + 1).to_le_bytes());
+        assert!(message(&future).contains("unsupported index format version"));
+        assert!(message(&data[..5]).contains("truncated index header"));
+        assert!(message(&data[..data.len() - 4]).contains("corrupt index payload"));
+
+        let mut body = zstd::decode_all(&data[6..]).unwrap();
+        body.push(0);
+        let mut trailing = data[..6].to_vec();
+        trailing.extend_from_slice(&zstd::encode_all(body.as_slice(), 3).unwrap());
+        assert!(message(&trailing).contains("trailing data"));
+    }
+}
@@ -1,3 +1,9 @@
+pub mod index;
 pub mod plpm;
+pub mod reader;
+pub mod writer;
 
-pub use plpm::PlpmPackage;
\ No newline at end of file
+pub use index::IndexFormat;
+pub use plpm::PlpmPackage;
+pub use reader::{PlpmEntryReader, PlpmReader};
+pub use writer::PlpmWriter;
\ No newline at end of file
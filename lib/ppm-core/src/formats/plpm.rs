@@ -1,5 +1,15 @@
 use serde::{Deserialize, Serialize};
-use crate::{Package, PackageMetadata, Architecture, Channel};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use crate::{Package, PackageMetadata, Architecture, Channel, PpmError, Result};
+use super::{PlpmReader, PlpmWriter};
+
+pub const PLPM_MAGIC: [u8; 4] = *b"PLPM";
+pub const PLPM_FORMAT_VERSION: u16 = 1;
+pub const PLPM_HEADER_SIZE: usize = 96;
+pub const PLPM_FLAG_COMPRESSED: u16 = 1 << 0;
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlpmPackage {
@@ -17,6 +27,42 @@ pub struct PlpmHeader {
     pub architecture: Architecture,
     pub channel: Channel,
     pub compressed: bool,
+    #[serde(default)]
+    pub compression: PlpmCompression,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompressionAlgorithm {
+    #[serde(rename = "zstd")]
+    Zstd,
+}
+
+// `PerFile` compresses every file as its own frame so entries stay randomly
+// accessible; `Archive` compresses the whole payload as one stream, which
+// packs better but has to be read front to back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompressionMode {
+    #[serde(rename = "per-file")]
+    PerFile,
+    #[serde(rename = "archive")]
+    Archive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlpmCompression {
+    pub algorithm: CompressionAlgorithm,
+    pub level: i32,
+    pub mode: CompressionMode,
+}
+
+impl Default for PlpmCompression {
+    fn default() -> Self {
+        Self {
+            algorithm: CompressionAlgorithm::Zstd,
+            level: DEFAULT_ZSTD_LEVEL,
+            mode: CompressionMode::PerFile,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,11 +85,12 @@ impl PlpmPackage {
     pub fn new(package: Package, files: Vec<PlpmFile>) -> Self {
         Self {
             header: PlpmHeader {
-                magic: *b"PLPM",
-                version: 1,
+                magic: PLPM_MAGIC,
+                version: PLPM_FORMAT_VERSION,
                 architecture: package.architecture,
                 channel: package.channel,
                 compressed: true,
+                compression: PlpmCompression::default(),
             },
             metadata: PackageMetadata {
                 name: package.name,
@@ -52,6 +99,11 @@ impl PlpmPackage {
                 author: package.author,
                 license: package.license,
                 dependencies: package.dependencies,
+                conflicts: package.conflicts,
+                provides: package.provides,
+                replaces: package.replaces,
+                recommends: package.recommends,
+                features: package.features,
                 architectures: vec![package.architecture],
                 channels: vec![package.channel],
                 build_script: None,
@@ -63,4 +115,277 @@ impl PlpmPackage {
             signature: package.signature,
         }
     }
-}
\ No newline at end of file
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        PlpmReader::new(BufReader::new(file))?.read_package()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = File::create(path)?;
+        let mut writer = PlpmWriter::write_package(self, BufWriter::new(file))?;
+        std::io::Write::flush(&mut writer)?;
+        writer.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+// On-disk layout of a `.plpm` file (all integers little-endian):
+//
+//   0   magic "PLPM"            4
+//   4   format version          u16
+//   6   flags                   u16
+//   8   architecture            u8
+//   9   channel                 u8
+//   10  compression algorithm   u8         0 = none, 1 = zstd
+//   11  compression mode        u8         0 = per file, 1 = whole archive
+//   12  compression level       i32
+//   16  metadata   offset/len   u64, u64   TOML `PlpmMetadataBlock`
+//   32  file table offset/len   u64, u64   `entry_count` encoded `PlpmEntry`s
+//   48  payload    offset/len   u64, u64   file contents, back to back, as stored
+//   64  signature  offset/len   u64, u64   UTF-8 signature, empty if unsigned
+//   80  entry count             u64
+//   88  reserved                8
+//
+// A file table entry is a u16 path length, the UTF-8 path, u32 permissions,
+// u64 offset, u64 size, u64 stored size and the raw 32-byte SHA-256 of the
+// uncompressed contents. Offsets point into the stored payload, except in
+// whole-archive mode where they index the decompressed stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlpmSection {
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl PlpmSection {
+    pub fn end(&self) -> Option<u64> {
+        self.offset.checked_add(self.length)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlpmSections {
+    pub metadata: PlpmSection,
+    pub file_table: PlpmSection,
+    pub payload: PlpmSection,
+    pub signature: PlpmSection,
+    pub entry_count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlpmMetadataBlock {
+    pub metadata: PackageMetadata,
+    pub scripts: Option<PlpmScripts>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlpmEntry {
+    pub path: String,
+    pub permissions: u32,
+    pub offset: u64,
+    pub size: u64,
+    pub stored_size: u64,
+    pub checksum: String,
+}
+
+impl PlpmHeader {
+    pub fn is_supported(&self) -> bool {
+        self.magic == PLPM_MAGIC && self.version == PLPM_FORMAT_VERSION
+    }
+
+    pub fn compression(&self) -> Option<PlpmCompression> {
+        self.compressed.then_some(self.compression)
+    }
+
+    pub fn to_bytes(&self, sections: &PlpmSections) -> [u8; PLPM_HEADER_SIZE] {
+        let mut buf = [0u8; PLPM_HEADER_SIZE];
+        let flags = if self.compressed { PLPM_FLAG_COMPRESSED } else { 0 };
+        buf[0..4].copy_from_slice(&self.magic);
+        buf[4..6].copy_from_slice(&self.version.to_le_bytes());
+        buf[6..8].copy_from_slice(&flags.to_le_bytes());
+        buf[8] = architecture_code(self.architecture);
+        buf[9] = channel_code(self.channel);
+        if let Some(compression) = self.compression() {
+            buf[10] = match compression.algorithm {
+                CompressionAlgorithm::Zstd => 1,
+            };
+            buf[11] = match compression.mode {
+                CompressionMode::PerFile => 0,
+                CompressionMode::Archive => 1,
+            };
+            buf[12..16].copy_from_slice(&compression.level.to_le_bytes());
+        }
+        let fields = [
+            sections.metadata.offset,
+            sections.metadata.length,
+            sections.file_table.offset,
+            sections.file_table.length,
+            sections.payload.offset,
+            sections.payload.length,
+            sections.signature.offset,
+            sections.signature.length,
+            sections.entry_count,
+        ];
+        for (i, value) in fields.iter().enumerate() {
+            let at = 16 + i * 8;
+            buf[at..at + 8].copy_from_slice(&value.to_le_bytes());
+        }
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8; PLPM_HEADER_SIZE]) -> Result<(Self, PlpmSections)> {
+        let magic: [u8; 4] = buf[0..4].try_into().unwrap();
+        if magic != PLPM_MAGIC {
+            return Err(PpmError::InvalidPackage(format!(
+                "bad magic {:02x?}, expected \"PLPM\"", magic
+            )));
+        }
+        let version = u16::from_le_bytes([buf[4], buf[5]]);
+        if version != PLPM_FORMAT_VERSION {
+            return Err(PpmError::InvalidPackage(format!(
+                "unsupported PLPM format version {} (this ppm reads version {})",
+                version, PLPM_FORMAT_VERSION
+            )));
+        }
+        let flags = u16::from_le_bytes([buf[6], buf[7]]);
+        if flags & !PLPM_FLAG_COMPRESSED != 0 {
+            return Err(PpmError::InvalidPackage(format!("unknown header flags {:#06x}", flags)));
+        }
+
+        let field = |i: usize| {
+            let at = 16 + i * 8;
+            u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+        };
+        let sections = PlpmSections {
+            metadata: PlpmSection { offset: field(0), length: field(1) },
+            file_table: PlpmSection { offset: field(2), length: field(3) },
+            payload: PlpmSection { offset: field(4), length: field(5) },
+            signature: PlpmSection { offset: field(6), length: field(7) },
+            entry_count: field(8),
+        };
+
+        let compressed = flags & PLPM_FLAG_COMPRESSED != 0;
+        let compression = if compressed {
+            PlpmCompression {
+                algorithm: match buf[10] {
+                    1 => CompressionAlgorithm::Zstd,
+                    code => {
+                        return Err(PpmError::InvalidPackage(format!(
+                            "unknown compression algorithm {}", code
+                        )))
+                    }
+                },
+                mode: match buf[11] {
+                    0 => CompressionMode::PerFile,
+                    1 => CompressionMode::Archive,
+                    code => {
+                        return Err(PpmError::InvalidPackage(format!(
+                            "unknown compression mode {}", code
+                        )))
+                    }
+                },
+                level: i32::from_le_bytes(buf[12..16].try_into().unwrap()),
+            }
+        } else {
+            PlpmCompression::default()
+        };
+
+        let header = PlpmHeader {
+            magic,
+            version,
+            architecture: architecture_from_code(buf[8])?,
+            channel: channel_from_code(buf[9])?,
+            compressed,
+            compression,
+        };
+        Ok((header, sections))
+    }
+}
+
+impl PlpmEntry {
+    pub fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        let path = self.path.as_bytes();
+        let path_len = u16::try_from(path.len())
+            .map_err(|_| PpmError::InvalidPackage(format!("path too long: {}", self.path)))?;
+        let checksum = hex::decode(&self.checksum)
+            .ok()
+            .filter(|c| c.len() == 32)
+            .ok_or_else(|| PpmError::InvalidPackage(format!("bad checksum for {}", self.path)))?;
+        out.extend_from_slice(&path_len.to_le_bytes());
+        out.extend_from_slice(path);
+        out.extend_from_slice(&self.permissions.to_le_bytes());
+        out.extend_from_slice(&self.offset.to_le_bytes());
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out.extend_from_slice(&self.stored_size.to_le_bytes());
+        out.extend_from_slice(&checksum);
+        Ok(())
+    }
+
+    pub fn decode(buf: &[u8], pos: &mut usize) -> Result<Self> {
+        fn take<'a>(buf: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8]> {
+            let end = pos
+                .checked_add(len)
+                .filter(|end| *end <= buf.len())
+                .ok_or_else(|| PpmError::InvalidPackage("truncated file table".to_string()))?;
+            let slice = &buf[*pos..end];
+            *pos = end;
+            Ok(slice)
+        }
+
+        let path_len = u16::from_le_bytes(take(buf, pos, 2)?.try_into().unwrap()) as usize;
+        let path = std::str::from_utf8(take(buf, pos, path_len)?)
+            .map_err(|_| PpmError::InvalidPackage("file path is not valid UTF-8".to_string()))?
+            .to_string();
+        let permissions = u32::from_le_bytes(take(buf, pos, 4)?.try_into().unwrap());
+        let offset = u64::from_le_bytes(take(buf, pos, 8)?.try_into().unwrap());
+        let size = u64::from_le_bytes(take(buf, pos, 8)?.try_into().unwrap());
+        let stored_size = u64::from_le_bytes(take(buf, pos, 8)?.try_into().unwrap());
+        let checksum = hex::encode(take(buf, pos, 32)?);
+        Ok(Self {
+            path,
+            permissions,
+            offset,
+            size,
+            stored_size,
+            checksum,
+        })
+    }
+}
+
+pub(crate) fn architecture_code(arch: Architecture) -> u8 {
+    match arch {
+        Architecture::X86_64 => 1,
+        Architecture::AArch64 => 2,
+        Architecture::RiscV64 => 3,
+        Architecture::Prum64 => 4,
+    }
+}
+
+pub(crate) fn architecture_from_code(code: u8) -> Result<Architecture> {
+    match code {
+        1 => Ok(Architecture::X86_64),
+        2 => Ok(Architecture::AArch64),
+        3 => Ok(Architecture::RiscV64),
+        4 => Ok(Architecture::Prum64),
+        _ => Err(PpmError::InvalidPackage(format!("unknown architecture code {}", code))),
+    }
+}
+
+pub(crate) fn channel_code(channel: Channel) -> u8 {
+    match channel {
+        Channel::Stable => 0,
+        Channel::Testing => 1,
+        Channel::Unstable => 2,
+        Channel::Dev => 3,
+    }
+}
+
+pub(crate) fn channel_from_code(code: u8) -> Result<Channel> {
+    match code {
+        0 => Ok(Channel::Stable),
+        1 => Ok(Channel::Testing),
+        2 => Ok(Channel::Unstable),
+        3 => Ok(Channel::Dev),
+        _ => Err(PpmError::InvalidPackage(format!("unknown channel code {}", code))),
+    }
+}
@@ -0,0 +1,550 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::plpm::{
+    CompressionMode, PlpmEntry, PlpmFile, PlpmHeader, PlpmMetadataBlock, PlpmPackage,
+    PlpmScripts, PlpmSection, PlpmSections, PLPM_HEADER_SIZE,
+};
+use super::writer::normalize_path;
+use crate::{PackageMetadata, PpmError, Result};
+
+type ArchiveDecoder<R> = zstd::stream::read::Decoder<'static, BufReader<io::Take<R>>>;
+
+// In whole-archive mode the payload is a single zstd stream, so the reader
+// keeps the decoder (and how far into the stream it is) between entries and
+// only restarts from the top when asked to go backwards.
+enum Source<R: Read> {
+    Raw(R),
+    Archive { decoder: ArchiveDecoder<R>, position: u64 },
+    Poisoned,
+}
+
+pub struct PlpmReader<R: Read + Seek> {
+    source: Source<R>,
+    base: u64,
+    header: PlpmHeader,
+    sections: PlpmSections,
+    metadata: PackageMetadata,
+    metadata_block: Vec<u8>,
+    scripts: Option<PlpmScripts>,
+    entries: Vec<PlpmEntry>,
+    signature: Option<String>,
+}
+
+impl<R: Read + Seek> PlpmReader<R> {
+    pub fn new(mut inner: R) -> Result<Self> {
+        let base = inner.stream_position()?;
+        let file_len = inner.seek(SeekFrom::End(0))? - base;
+        inner.seek(SeekFrom::Start(base))?;
+
+        let mut raw = [0u8; PLPM_HEADER_SIZE];
+        inner.read_exact(&mut raw).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => {
+                PpmError::InvalidPackage("file is too short for a PLPM header".to_string())
+            }
+            _ => PpmError::Io(e),
+        })?;
+        let (header, sections) = PlpmHeader::from_bytes(&raw)?;
+
+        for (name, section) in [
+            ("metadata", sections.metadata),
+            ("file table", sections.file_table),
+            ("payload", sections.payload),
+            ("signature", sections.signature),
+        ] {
+            let in_bounds = section.offset >= PLPM_HEADER_SIZE as u64
+                && section.end().is_some_and(|end| end <= file_len);
+            if !in_bounds {
+                return Err(PpmError::InvalidPackage(format!(
+                    "{} section [{}+{}] lies outside the file ({} bytes)",
+                    name, section.offset, section.length, file_len
+                )));
+            }
+        }
+
+        let metadata_bytes = read_section(&mut inner, base, sections.metadata)?;
+        let metadata_text = String::from_utf8(metadata_bytes)
+            .map_err(|_| PpmError::InvalidPackage("metadata block is not valid UTF-8".to_string()))?;
+        let block: PlpmMetadataBlock = toml::from_str(&metadata_text)
+            .map_err(|e| PpmError::InvalidPackage(format!("invalid metadata block: {}", e)))?;
+
+        let archive = header
+            .compression()
+            .is_some_and(|c| c.mode == CompressionMode::Archive);
+        let table = read_section(&mut inner, base, sections.file_table)?;
+        let mut entries = Vec::new();
+        let mut paths = HashSet::new();
+        let mut pos = 0;
+        while pos < table.len() {
+            let entry = PlpmEntry::decode(&table, &mut pos)?;
+            // The writer only ever stores normalized paths, so anything else,
+            // such as an absolute path, was not written by it.
+            if normalize_path(&entry.path)? != entry.path {
+                return Err(PpmError::InvalidPackage(format!("unsafe file path: '{}'", entry.path)));
+            }
+            if !paths.insert(entry.path.clone()) {
+                return Err(PpmError::InvalidPackage(format!("duplicate file path: {}", entry.path)));
+            }
+            let in_payload = if archive {
+                entry.stored_size == entry.size && entry.offset.checked_add(entry.size).is_some()
+            } else {
+                entry
+                    .offset
+                    .checked_add(entry.stored_size)
+                    .is_some_and(|end| end <= sections.payload.length)
+            };
+            if !in_payload {
+                return Err(PpmError::InvalidPackage(format!(
+                    "data for {} lies outside the payload", entry.path
+                )));
+            }
+            entries.push(entry);
+        }
+        if entries.len() as u64 != sections.entry_count {
+            return Err(PpmError::InvalidPackage(format!(
+                "header declares {} files but the file table holds {}",
+                sections.entry_count,
+                entries.len()
+            )));
+        }
+
+        let signature_bytes = read_section(&mut inner, base, sections.signature)?;
+        let signature = if signature_bytes.is_empty() {
+            None
+        } else {
+            Some(String::from_utf8(signature_bytes).map_err(|_| {
+                PpmError::InvalidPackage("signature block is not valid UTF-8".to_string())
+            })?)
+        };
+
+        Ok(Self {
+            source: Source::Raw(inner),
+            base,
+            header,
+            sections,
+            metadata: block.metadata,
+            metadata_block: metadata_text.into_bytes(),
+            scripts: block.scripts,
+            entries,
+            signature,
+        })
+    }
+
+    pub fn header(&self) -> &PlpmHeader {
+        &self.header
+    }
+
+    pub fn sections(&self) -> &PlpmSections {
+        &self.sections
+    }
+
+    pub fn metadata(&self) -> &PackageMetadata {
+        &self.metadata
+    }
+
+    pub fn metadata_block(&self) -> &[u8] {
+        &self.metadata_block
+    }
+
+    pub fn scripts(&self) -> Option<&PlpmScripts> {
+        self.scripts.as_ref()
+    }
+
+    pub fn entries(&self) -> &[PlpmEntry] {
+        &self.entries
+    }
+
+    pub fn signature(&self) -> Option<&str> {
+        self.signature.as_deref()
+    }
+
+    pub fn entry_reader(&mut self, index: usize) -> Result<PlpmEntryReader<'_>> {
+        if index >= self.entries.len() {
+            return Err(PpmError::InvalidPackage(format!("no file at index {}", index)));
+        }
+        let payload_start = self.base + self.sections.payload.offset;
+        let compression = self.header.compression();
+        let offset = self.entries[index].offset;
+
+        if compression.is_some_and(|c| c.mode == CompressionMode::Archive) {
+            let behind = matches!(&self.source, Source::Archive { position, .. } if *position <= offset);
+            if !behind {
+                let mut raw = self.take_raw()?;
+                raw.seek(SeekFrom::Start(payload_start))?;
+                let decoder = zstd::stream::read::Decoder::new(raw.take(self.sections.payload.length))?;
+                self.source = Source::Archive { decoder, position: 0 };
+            }
+            if let Source::Archive { decoder, position } = &mut self.source {
+                let skip = offset - *position;
+                let skipped = io::copy(&mut (&mut *decoder).take(skip), &mut io::sink())?;
+                *position += skipped;
+                if skipped != skip {
+                    return Err(PpmError::InvalidPackage(
+                        "compressed payload ends early".to_string(),
+                    ));
+                }
+            }
+        } else {
+            self.raw_mut()?.seek(SeekFrom::Start(payload_start + offset))?;
+        }
+
+        let entry = &self.entries[index];
+        let inner: Box<dyn Read + '_> = match &mut self.source {
+            Source::Raw(raw) => {
+                let stored = Read::take(raw, entry.stored_size);
+                if compression.is_some() {
+                    Box::new(zstd::stream::read::Decoder::new(stored)?)
+                } else {
+                    Box::new(stored)
+                }
+            }
+            Source::Archive { decoder, position } => Box::new(Tracked { inner: decoder, position }),
+            Source::Poisoned => return Err(poisoned()),
+        };
+        Ok(PlpmEntryReader {
+            inner,
+            remaining: entry.size,
+            entry,
+            hasher: Sha256::new(),
+            verified: false,
+        })
+    }
+
+    pub fn read_entry(&mut self, index: usize) -> Result<Vec<u8>> {
+        let mut reader = self.entry_reader(index)?;
+        // Sized by what actually arrives: the file table's size is untrusted
+        // until the entry has been read and verified.
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).map_err(entry_error)?;
+        Ok(data)
+    }
+
+    pub fn extract_entry(&mut self, index: usize, dest: impl AsRef<Path>) -> Result<u64> {
+        let dest = dest.as_ref();
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut reader = self.entry_reader(index)?;
+        let permissions = reader.entry().permissions;
+        let result = (|| -> Result<u64> {
+            let mut file = File::create(dest)?;
+            let written = io::copy(&mut reader, &mut file).map_err(entry_error)?;
+            file.flush()?;
+            set_permissions(&file, permissions)?;
+            Ok(written)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(dest);
+        }
+        result
+    }
+
+    pub fn extract_all(&mut self, root: impl AsRef<Path>) -> Result<()> {
+        let root = root.as_ref();
+        for index in 0..self.entries.len() {
+            let dest = root.join(normalize_path(&self.entries[index].path)?);
+            self.extract_entry(index, dest)?;
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(mut self) -> Result<R> {
+        self.take_raw()
+    }
+
+    fn take_raw(&mut self) -> Result<R> {
+        match std::mem::replace(&mut self.source, Source::Poisoned) {
+            Source::Raw(raw) => Ok(raw),
+            Source::Archive { decoder, .. } => Ok(decoder.finish().into_inner().into_inner()),
+            Source::Poisoned => Err(poisoned()),
+        }
+    }
+
+    fn raw_mut(&mut self) -> Result<&mut R> {
+        if !matches!(self.source, Source::Raw(_)) {
+            let raw = self.take_raw()?;
+            self.source = Source::Raw(raw);
+        }
+        match &mut self.source {
+            Source::Raw(raw) => Ok(raw),
+            _ => Err(poisoned()),
+        }
+    }
+
+    pub fn read_package(mut self) -> Result<PlpmPackage> {
+        let mut files = Vec::with_capacity(self.entries.len());
+        for index in 0..self.entries.len() {
+            let data = self.read_entry(index)?;
+            let entry = &self.entries[index];
+            files.push(PlpmFile {
+                path: entry.path.clone(),
+                data,
+                permissions: entry.permissions,
+                checksum: entry.checksum.clone(),
+            });
+        }
+        Ok(PlpmPackage {
+            header: self.header,
+            metadata: self.metadata,
+            files,
+            scripts: self.scripts,
+            signature: self.signature,
+        })
+    }
+}
+
+fn poisoned() -> PpmError {
+    PpmError::Io(io::Error::other("PLPM reader is unusable after an earlier I/O error"))
+}
+
+fn read_section<R: Read + Seek>(inner: &mut R, base: u64, section: PlpmSection) -> Result<Vec<u8>> {
+    inner.seek(SeekFrom::Start(base + section.offset))?;
+    let mut buf = vec![0u8; section.length as usize];
+    inner.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// Reads one file's bytes straight out of the payload (decompressing if
+// needed), hashing as it goes. Hitting the end of the entry with a digest that
+// doesn't match the file table fails the read with `InvalidData`.
+pub struct PlpmEntryReader<'a> {
+    inner: Box<dyn Read + 'a>,
+    remaining: u64,
+    entry: &'a PlpmEntry,
+    hasher: Sha256,
+    verified: bool,
+}
+
+impl PlpmEntryReader<'_> {
+    pub fn entry(&self) -> &PlpmEntry {
+        self.entry
+    }
+}
+
+impl Read for PlpmEntryReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = buf.len().min(self.remaining.min(usize::MAX as u64) as usize);
+        let n = if max == 0 { 0 } else { self.inner.read(&mut buf[..max])? };
+        self.hasher.update(&buf[..n]);
+        self.remaining -= n as u64;
+        if n == 0 && !buf.is_empty() && !self.verified {
+            if self.remaining != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("payload for {} is truncated", self.entry.path),
+                ));
+            }
+            let digest = format!("{:x}", std::mem::take(&mut self.hasher).finalize());
+            if digest != self.entry.checksum {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("checksum mismatch for {}", self.entry.path),
+                ));
+            }
+            self.verified = true;
+        }
+        Ok(n)
+    }
+}
+
+struct Tracked<'a, T: Read> {
+    inner: &'a mut T,
+    position: &'a mut u64,
+}
+
+impl<T: Read> Read for Tracked<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        *self.position += n as u64;
+        Ok(n)
+    }
+}
+
+pub(crate) fn entry_error(e: io::Error) -> PpmError {
+    match e.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+            PpmError::InvalidPackage(e.to_string())
+        }
+        _ => PpmError::Io(e),
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn set_permissions(file: &File, permissions: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if permissions != 0 {
+        file.set_permissions(fs::Permissions::from_mode(permissions & 0o7777))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_permissions(_file: &File, _permissions: u32) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::TestDir;
+    use crate::formats::plpm::{PlpmCompression, DEFAULT_ZSTD_LEVEL};
+    use crate::formats::PlpmWriter;
+    use crate::{compute_checksum, Package};
+    use std::io::Cursor;
+
+    fn package(files: &[(&str, &[u8], u32)], compression: Option<CompressionMode>) -> PlpmPackage {
+        let pkg = Package {
+            description: Some("demo package".to_string()),
+            ..Package::for_test("demo", "1.2.0")
+        };
+        let files = files
+            .iter()
+            .map(|(path, data, permissions)| PlpmFile {
+                path: path.to_string(),
+                data: data.to_vec(),
+                permissions: *permissions,
+                checksum: compute_checksum(data),
+            })
+            .collect();
+        let mut package = PlpmPackage::new(pkg, files);
+        package.header.compressed = compression.is_some();
+        if let Some(mode) = compression {
+            package.header.compression = PlpmCompression { mode, level: DEFAULT_ZSTD_LEVEL, ..Default::default() };
+        }
+        package.scripts = Some(PlpmScripts {
+            pre_install: None,
+            post_install: Some("echo installed\n".to_string()),
+            pre_remove: None,
+            post_remove: None,
+        });
+        package
+    }
+
+    fn encode(package: &PlpmPackage) -> Vec<u8> {
+        PlpmWriter::write_package(package, Cursor::new(Vec::new())).unwrap().into_inner()
+    }
+
+    // Swaps a placeholder in the file table for a path the writer would
+    // never produce; both must have the same length.
+    fn patch(bytes: &mut [u8], from: &str, to: &str) {
+        assert_eq!(from.len(), to.len());
+        let at = bytes
+            .windows(from.len())
+            .rposition(|w| w == from.as_bytes())
+            .expect("placeholder is in the file table");
+        bytes[at..at + to.len()].copy_from_slice(to.as_bytes());
+    }
+
+    fn open(bytes: Vec<u8>) -> Result<PlpmReader<Cursor<Vec<u8>>>> {
+        PlpmReader::new(Cursor::new(bytes))
+    }
+
+    fn invalid(result: Result<PlpmReader<Cursor<Vec<u8>>>>) -> String {
+        match result {
+            Err(PpmError::InvalidPackage(message)) => message,
+            Err(e) => panic!("expected an invalid package, got {}", e),
+            Ok(_) => panic!("expected an invalid package"),
+        }
+    }
+
+    fn temp_dir(name: &str) -> TestDir {
+        let dir = TestDir::new("reader", name);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn round_trips_in_every_compression_mode() {
+        let files: &[(&str, &[u8], u32)] = &[
+            ("usr/bin/demo", b"#!/bin/sh\necho demo\n", 0o755),
+            ("usr/share/demo/README", b"read me", 0o644),
+            ("usr/share/demo/empty", b"", 0o644),
+        ];
+        for mode in [None, Some(CompressionMode::PerFile), Some(CompressionMode::Archive)] {
+            let original = package(files, mode);
+            let read = open(encode(&original)).unwrap().read_package().unwrap();
+            assert_eq!(read.metadata.name, "demo");
+            assert_eq!(read.metadata.version, original.metadata.version);
+            assert_eq!(read.header.compression(), original.header.compression());
+            assert_eq!(read.scripts.unwrap().post_install.as_deref(), Some("echo installed\n"));
+            assert_eq!(read.files.len(), files.len());
+            for (read, (path, data, permissions)) in read.files.iter().zip(files) {
+                assert_eq!(read.path, *path);
+                assert_eq!(read.data, *data);
+                assert_eq!(read.permissions, *permissions);
+                assert_eq!(read.checksum, compute_checksum(data));
+            }
+        }
+    }
+
+    #[test]
+    fn reads_archive_entries_out_of_order() {
+        let files: &[(&str, &[u8], u32)] = &[("a", b"first", 0o644), ("b", b"second", 0o644)];
+        let mut reader = open(encode(&package(files, Some(CompressionMode::Archive)))).unwrap();
+        assert_eq!(reader.read_entry(1).unwrap(), b"second");
+        assert_eq!(reader.read_entry(0).unwrap(), b"first");
+        assert_eq!(reader.read_entry(1).unwrap(), b"second");
+    }
+
+    #[test]
+    fn extracts_below_the_root() {
+        let root = temp_dir("extract");
+        let files: &[(&str, &[u8], u32)] = &[("usr/bin/demo", b"demo", 0o755), ("etc/demo.conf", b"conf", 0o644)];
+        open(encode(&package(files, Some(CompressionMode::PerFile)))).unwrap().extract_all(&root).unwrap();
+        assert_eq!(fs::read(root.join("usr/bin/demo")).unwrap(), b"demo");
+        assert_eq!(fs::read(root.join("etc/demo.conf")).unwrap(), b"conf");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(root.join("usr/bin/demo")).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+    }
+
+    #[test]
+    fn rejects_absolute_entry_paths() {
+        let mut bytes = encode(&package(&[("zetc/passwd", b"root::0:0", 0o644)], None));
+        patch(&mut bytes, "zetc/passwd", "/etc/passwd");
+        assert!(invalid(open(bytes)).contains("unsafe file path: '/etc/passwd'"));
+    }
+
+    #[test]
+    fn rejects_path_traversal_entries() {
+        let mut bytes = encode(&package(&[("zz/evil", b"evil", 0o644)], None));
+        patch(&mut bytes, "zz/evil", "../evil");
+        assert!(invalid(open(bytes)).contains("unsafe file path: '../evil'"));
+    }
+
+    #[test]
+    fn rejects_duplicate_entry_paths() {
+        let files: &[(&str, &[u8], u32)] = &[("dup-a", b"one", 0o644), ("dup-b", b"two", 0o644)];
+        let mut bytes = encode(&package(files, None));
+        patch(&mut bytes, "dup-b", "dup-a");
+        assert!(invalid(open(bytes)).contains("duplicate file path: dup-a"));
+    }
+
+    #[test]
+    fn detects_corrupted_payloads() {
+        let root = temp_dir("corrupt");
+        let mut bytes = encode(&package(&[("data", b"payload bytes", 0o644)], None));
+        patch(&mut bytes, "payload bytes", "payload BYTES");
+        let mut reader = open(bytes).unwrap();
+        match reader.extract_entry(0, root.join("data")) {
+            Err(PpmError::InvalidPackage(message)) => assert!(message.contains("checksum mismatch for data")),
+            other => panic!("expected a checksum mismatch, got {:?}", other),
+        }
+        assert!(!root.join("data").exists());
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = encode(&package(&[("data", b"payload", 0o644)], None));
+        let message = invalid(open(bytes[..PLPM_HEADER_SIZE - 1].to_vec()));
+        assert!(message.contains("too short"), "{}", message);
+        let message = invalid(open(bytes[..bytes.len() - 1].to_vec()));
+        assert!(message.contains("lies outside the file"), "{}", message);
+    }
+}
@@ -0,0 +1,260 @@
+use ed25519_dalek::SigningKey;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path};
+
+use super::plpm::{
+    CompressionAlgorithm, CompressionMode, PlpmCompression, PlpmEntry, PlpmHeader,
+    PlpmMetadataBlock, PlpmPackage, PlpmScripts, PlpmSection, PlpmSections, PLPM_HEADER_SIZE,
+};
+use crate::signing::{package_digest, sign_digest};
+use crate::{PackageMetadata, PpmError, Result};
+
+enum Sink<W: Write> {
+    Raw(W),
+    Archive(zstd::stream::write::Encoder<'static, W>),
+    Poisoned,
+}
+
+pub struct PlpmWriter<W: Write + Seek> {
+    sink: Sink<W>,
+    base: u64,
+    header: PlpmHeader,
+    sections: PlpmSections,
+    metadata_block: Vec<u8>,
+    next_offset: u64,
+    entries: Vec<PlpmEntry>,
+    paths: HashSet<String>,
+}
+
+impl<W: Write + Seek> PlpmWriter<W> {
+    pub fn new(
+        mut inner: W,
+        header: PlpmHeader,
+        metadata: &PackageMetadata,
+        scripts: Option<&PlpmScripts>,
+    ) -> Result<Self> {
+        if !header.is_supported() {
+            return Err(PpmError::InvalidPackage(format!(
+                "cannot write PLPM format version {}", header.version
+            )));
+        }
+        if let Some(compression) = header.compression() {
+            match compression.algorithm {
+                CompressionAlgorithm::Zstd => {
+                    if !zstd::compression_level_range().contains(&compression.level) {
+                        return Err(PpmError::InvalidPackage(format!(
+                            "zstd compression level {} is out of range", compression.level
+                        )));
+                    }
+                }
+            }
+        }
+
+        let base = inner.stream_position()?;
+        inner.write_all(&[0u8; PLPM_HEADER_SIZE])?;
+
+        let block = PlpmMetadataBlock {
+            metadata: metadata.clone(),
+            scripts: scripts.cloned(),
+        };
+        let encoded = toml::to_string(&block)
+            .map_err(|e| PpmError::Serialization(e.to_string()))?;
+        inner.write_all(encoded.as_bytes())?;
+
+        let metadata_section = PlpmSection {
+            offset: PLPM_HEADER_SIZE as u64,
+            length: encoded.len() as u64,
+        };
+        let sections = PlpmSections {
+            metadata: metadata_section,
+            payload: PlpmSection {
+                offset: metadata_section.offset + metadata_section.length,
+                length: 0,
+            },
+            ..PlpmSections::default()
+        };
+
+        let sink = match header.compression() {
+            Some(PlpmCompression { mode: CompressionMode::Archive, level, .. }) => {
+                Sink::Archive(zstd::stream::write::Encoder::new(inner, level)?)
+            }
+            _ => Sink::Raw(inner),
+        };
+
+        Ok(Self {
+            sink,
+            base,
+            header,
+            sections,
+            metadata_block: encoded.into_bytes(),
+            next_offset: 0,
+            entries: Vec::new(),
+            paths: HashSet::new(),
+        })
+    }
+
+    pub fn write_package(package: &PlpmPackage, inner: W) -> Result<W> {
+        let mut writer = Self::new(inner, package.header.clone(), &package.metadata, package.scripts.as_ref())?;
+        for file in &package.files {
+            let entry = writer.add_file(&file.path, file.permissions, &mut file.data.as_slice())?;
+            if !file.checksum.is_empty() && file.checksum != entry.checksum {
+                return Err(PpmError::InvalidPackage(format!(
+                    "checksum mismatch for {}", file.path
+                )));
+            }
+        }
+        writer.finish(package.signature.as_deref())
+    }
+
+    pub fn entries(&self) -> &[PlpmEntry] {
+        &self.entries
+    }
+
+    pub fn add_file(&mut self, path: &str, permissions: u32, data: &mut impl Read) -> Result<PlpmEntry> {
+        let path = normalize_path(path)?;
+        if !self.paths.insert(path.clone()) {
+            return Err(PpmError::InvalidPackage(format!("duplicate file path: {}", path)));
+        }
+
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        let mut hashing = HashingReader {
+            inner: data,
+            hasher: &mut hasher,
+            size: &mut size,
+        };
+
+        let offset = self.next_offset;
+        let stored_size = match (&mut self.sink, self.header.compression()) {
+            (Sink::Raw(inner), Some(compression)) => {
+                let start = inner.stream_position()?;
+                let mut encoder = zstd::stream::write::Encoder::new(&mut *inner, compression.level)?;
+                io::copy(&mut hashing, &mut encoder)?;
+                encoder.finish()?;
+                inner.stream_position()? - start
+            }
+            (Sink::Raw(inner), None) => io::copy(&mut hashing, inner)?,
+            (Sink::Archive(encoder), _) => io::copy(&mut hashing, encoder)?,
+            (Sink::Poisoned, _) => unreachable!("writer used after a failed finish"),
+        };
+
+        let entry = PlpmEntry {
+            path,
+            permissions,
+            offset,
+            size,
+            stored_size,
+            checksum: format!("{:x}", hasher.finalize()),
+        };
+        self.next_offset += stored_size;
+        self.entries.push(entry.clone());
+        Ok(entry)
+    }
+
+    pub fn finish_signed(self, key: &SigningKey) -> Result<W> {
+        let digest = package_digest(&self.header, &self.metadata_block, &self.entries);
+        let signature = sign_digest(&digest, key).to_string();
+        self.finish(Some(&signature))
+    }
+
+    pub fn finish(mut self, signature: Option<&str>) -> Result<W> {
+        let mut inner = match std::mem::replace(&mut self.sink, Sink::Poisoned) {
+            Sink::Raw(inner) => inner,
+            Sink::Archive(encoder) => encoder.finish()?,
+            Sink::Poisoned => unreachable!("writer used after a failed finish"),
+        };
+        let payload_end = inner.stream_position()? - self.base;
+        self.sections.payload.length = payload_end - self.sections.payload.offset;
+
+        let mut table = Vec::new();
+        for entry in &self.entries {
+            entry.encode(&mut table)?;
+        }
+        inner.write_all(&table)?;
+        self.sections.file_table = PlpmSection {
+            offset: self.sections.payload.offset + self.sections.payload.length,
+            length: table.len() as u64,
+        };
+        self.sections.entry_count = self.entries.len() as u64;
+
+        let signature = signature.unwrap_or("").as_bytes();
+        inner.write_all(signature)?;
+        self.sections.signature = PlpmSection {
+            offset: self.sections.file_table.offset + self.sections.file_table.length,
+            length: signature.len() as u64,
+        };
+
+        let end = inner.stream_position()?;
+        inner.seek(SeekFrom::Start(self.base))?;
+        inner.write_all(&self.header.to_bytes(&self.sections))?;
+        inner.seek(SeekFrom::Start(end))?;
+        inner.flush()?;
+        Ok(inner)
+    }
+}
+
+// `Path::components` quietly drops `.` and empty segments, so those are
+// checked on the raw string as well; otherwise `usr/./bin` and `usr/bin`
+// would be two different entries for the same file.
+pub(crate) fn normalize_path(path: &str) -> Result<String> {
+    let relative = path.trim_start_matches('/');
+    let safe = !relative.is_empty()
+        && relative.split('/').all(|segment| !segment.is_empty() && segment != ".")
+        && Path::new(relative)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+    if !safe {
+        return Err(PpmError::InvalidPackage(format!("unsafe file path: '{}'", path)));
+    }
+    Ok(relative.to_string())
+}
+
+struct HashingReader<'a, R: Read> {
+    inner: &'a mut R,
+    hasher: &'a mut Sha256,
+    size: &'a mut u64,
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        *self.size += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Package;
+    use std::io::Cursor;
+
+    #[test]
+    fn normalizes_leading_slashes() {
+        assert_eq!(normalize_path("/usr/bin/demo").unwrap(), "usr/bin/demo");
+        assert_eq!(normalize_path("usr/bin/demo").unwrap(), "usr/bin/demo");
+    }
+
+    #[test]
+    fn rejects_paths_that_leave_the_root() {
+        for path in ["", "/", "../etc/passwd", "usr/../../etc", "usr/./bin", "usr//bin/.."] {
+            assert!(matches!(normalize_path(path), Err(PpmError::InvalidPackage(_))), "{}", path);
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_paths() {
+        let pkg = Package::for_test("demo", "1.0.0");
+        let package = PlpmPackage::new(pkg, Vec::new());
+        let mut writer =
+            PlpmWriter::new(Cursor::new(Vec::new()), package.header.clone(), &package.metadata, None).unwrap();
+        writer.add_file("usr/bin/demo", 0o755, &mut &b"one"[..]).unwrap();
+        match writer.add_file("/usr/bin/demo", 0o755, &mut &b"two"[..]) {
+            Err(PpmError::InvalidPackage(message)) => assert_eq!(message, "duplicate file path: usr/bin/demo"),
+            other => panic!("expected a duplicate path error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::cache::checksum_file;
+use crate::database::now_timestamp;
+use crate::formats::index::{encode_index, IndexFormat};
+use crate::formats::PlpmReader;
+use crate::package::PackageIndex;
+use crate::{Architecture, Channel, Package, PpmError, Result};
+
+const CACHE_FILE: &str = ".ppm-index-cache.toml";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    modified_ns: u64,
+    package: Package,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ScanCache {
+    #[serde(default)]
+    files: BTreeMap<String, CacheEntry>,
+}
+
+#[derive(Debug)]
+pub struct GeneratedRepository {
+    pub indexes: HashMap<(Channel, Architecture), PackageIndex>,
+    pub scanned: usize,
+    pub reused: usize,
+    pub written: Vec<PathBuf>,
+}
+
+// Builds indexes for a repository laid out as `<root>/<channel>/...`. Every
+// package must sit below the directory of the channel its header declares,
+// since clients resolve `Package::file` relative to that directory.
+pub struct RepositoryGenerator {
+    root: PathBuf,
+    formats: Vec<IndexFormat>,
+    incremental: bool,
+}
+
+impl RepositoryGenerator {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            formats: vec![IndexFormat::Binary, IndexFormat::Toml],
+            incremental: true,
+        }
+    }
+
+    pub fn with_formats(mut self, formats: Vec<IndexFormat>) -> Self {
+        self.formats = formats;
+        self
+    }
+
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    fn load_cache(&self) -> ScanCache {
+        if !self.incremental {
+            return ScanCache::default();
+        }
+        fs::read_to_string(self.root.join(CACHE_FILE))
+            .ok()
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    // Re-reads only packages whose size or modification time changed since
+    // the last scan; everything else comes from the scan cache.
+    pub fn scan(&self) -> Result<(Vec<Package>, usize)> {
+        let cache = self.load_cache();
+        let mut fresh = ScanCache::default();
+        let mut packages = Vec::new();
+        let mut reused = 0;
+
+        let mut files = Vec::new();
+        collect_packages(&self.root, &mut files)?;
+        files.sort();
+        for path in files {
+            let relative = path
+                .strip_prefix(&self.root)
+                .map_err(|_| PpmError::Repository(format!("{} is outside the repository", path.display())))?
+                .to_string_lossy()
+                .replace('\\', "/");
+            let meta = fs::metadata(&path)?;
+            let modified_ns = meta
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0);
+
+            let entry = match cache.files.get(&relative) {
+                Some(entry) if entry.size == meta.len() && entry.modified_ns == modified_ns => {
+                    reused += 1;
+                    entry.clone()
+                }
+                _ => CacheEntry {
+                    size: meta.len(),
+                    modified_ns,
+                    package: read_package(&path, &relative)?,
+                },
+            };
+            packages.push(entry.package.clone());
+            fresh.files.insert(relative, entry);
+        }
+
+        let contents = toml::to_string(&fresh).map_err(|e| PpmError::Serialization(e.to_string()))?;
+        write_file(&self.root.join(CACHE_FILE), contents.as_bytes())?;
+        Ok((packages, reused))
+    }
+
+    pub fn build(&self) -> Result<GeneratedRepository> {
+        let (packages, reused) = self.scan()?;
+        let scanned = packages.len();
+
+        let mut seen = HashSet::new();
+        for pkg in &packages {
+            if !seen.insert((pkg.name.clone(), pkg.version.clone(), pkg.architecture, pkg.channel)) {
+                return Err(PpmError::Repository(format!(
+                    "{} {} for {} appears more than once in the {} channel",
+                    pkg.name, pkg.version, pkg.architecture.as_str(), pkg.channel.name()
+                )));
+            }
+        }
+
+        let generated = now_timestamp().to_string();
+        let mut by_channel: HashMap<Channel, Vec<Package>> = HashMap::new();
+        for pkg in packages {
+            by_channel.entry(pkg.channel).or_default().push(pkg);
+        }
+
+        let mut indexes = HashMap::new();
+        let mut written = Vec::new();
+        for channel in Channel::all_channels() {
+            let dir = self.root.join(channel.name());
+            let Some(mut packages) = by_channel.remove(&channel) else {
+                remove_stale_indexes(&dir, &HashSet::new())?;
+                continue;
+            };
+            packages.sort_by(|a, b| {
+                (&a.name, &a.version, a.architecture.as_str()).cmp(&(&b.name, &b.version, b.architecture.as_str()))
+            });
+
+            let combined = PackageIndex {
+                packages: packages.clone(),
+                generated: generated.clone(),
+                channel,
+            };
+            written.extend(self.write_index(&dir, &combined)?);
+
+            let mut by_arch: HashMap<Architecture, Vec<Package>> = HashMap::new();
+            for pkg in packages {
+                by_arch.entry(pkg.architecture).or_default().push(pkg);
+            }
+            remove_stale_indexes(&dir, &by_arch.keys().copied().collect())?;
+            for (arch, packages) in by_arch {
+                let index = PackageIndex {
+                    packages,
+                    generated: generated.clone(),
+                    channel,
+                };
+                written.extend(self.write_index(&dir.join(arch.as_str()), &index)?);
+                indexes.insert((channel, arch), index);
+            }
+        }
+
+        Ok(GeneratedRepository {
+            indexes,
+            scanned,
+            reused,
+            written,
+        })
+    }
+
+    fn write_index(&self, dir: &Path, index: &PackageIndex) -> Result<Vec<PathBuf>> {
+        let mut written = Vec::new();
+        for format in &self.formats {
+            let path = dir.join(format.file_name());
+            write_file(&path, &encode_index(index, *format)?)?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+// Indexes of a channel, or of architectures within it, that no longer have
+// any packages would go on offering the packages that were removed. The
+// channel's own indexes go only once `live` is empty.
+fn remove_stale_indexes(dir: &Path, live: &HashSet<Architecture>) -> Result<()> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    let mut stale = Vec::new();
+    if live.is_empty() {
+        stale.push(dir.to_path_buf());
+    }
+    for entry in entries {
+        let entry = entry?;
+        let arch = entry.file_name().to_str().and_then(|name| name.parse::<Architecture>().ok());
+        if arch.is_some_and(|arch| !live.contains(&arch)) && entry.file_type()?.is_dir() {
+            stale.push(entry.path());
+        }
+    }
+    for dir in stale {
+        for format in [IndexFormat::Binary, IndexFormat::Toml] {
+            match fs::remove_file(dir.join(format.file_name())) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn collect_packages(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            collect_packages(&path, out)?;
+        } else if path.extension().and_then(|e| e.to_str()) == Some("plpm") {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn read_package(path: &Path, relative: &str) -> Result<Package> {
+    let reader = PlpmReader::new(BufReader::new(File::open(path)?))
+        .map_err(|e| PpmError::Repository(format!("{}: {}", relative, e)))?;
+    let header = reader.header();
+    let metadata = reader.metadata();
+
+    let file = relative
+        .strip_prefix(header.channel.name())
+        .and_then(|rest| rest.strip_prefix('/'))
+        .ok_or_else(|| {
+            PpmError::Repository(format!(
+                "{} is a {} package but is not stored under {}/",
+                relative,
+                header.channel.name(),
+                header.channel.name()
+            ))
+        })?
+        .to_string();
+
+    let size = fs::metadata(path)?.len();
+    Ok(Package {
+        name: metadata.name.clone(),
+        version: metadata.version.clone(),
+        description: metadata.description.clone(),
+        author: metadata.author.clone(),
+        license: metadata.license.clone(),
+        dependencies: metadata.dependencies.clone(),
+        conflicts: metadata.conflicts.clone(),
+        provides: metadata.provides.clone(),
+        replaces: metadata.replaces.clone(),
+        recommends: metadata.recommends.clone(),
+        features: metadata.features.clone(),
+        architecture: header.architecture,
+        channel: header.channel,
+        file,
+        checksum: checksum_file(path)?,
+        signature: reader.signature().map(str::to_string),
+        size,
+        install_size: reader.entries().iter().map(|e| e.size).sum(),
+    })
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    {
+        let mut file = File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::TestDir;
+    use crate::compute_checksum;
+    use crate::formats::plpm::PlpmFile;
+    use crate::formats::PlpmPackage;
+
+    struct Fixture {
+        dir: TestDir,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = TestDir::new("generator", name);
+            Self { dir }
+        }
+
+        fn publish(&self, name: &str, version: &str, contents: &str) {
+            self.publish_for(Architecture::X86_64, name, version, contents);
+        }
+
+        fn publish_for(&self, architecture: Architecture, name: &str, version: &str, contents: &str) {
+            let file = PlpmFile {
+                path: format!("usr/share/{}/data", name),
+                data: contents.as_bytes().to_vec(),
+                permissions: 0o644,
+                checksum: compute_checksum(contents.as_bytes()),
+            };
+            let path = self.dir.join("stable").join(format!("{}-{}-{}.plpm", name, version, architecture.as_str()));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let package = Package { architecture, ..Package::for_test(name, version) };
+            PlpmPackage::new(package, vec![file]).save(path).unwrap();
+        }
+
+        fn build(&self, incremental: bool) -> GeneratedRepository {
+            RepositoryGenerator::new(self.dir.to_path_buf()).incremental(incremental).build().unwrap()
+        }
+    }
+
+    fn install_size(generated: &GeneratedRepository, name: &str) -> u64 {
+        let index = &generated.indexes[&(Channel::Stable, Architecture::X86_64)];
+        index.packages.iter().find(|p| p.name == name).unwrap().install_size
+    }
+
+    #[test]
+    fn rescans_only_changed_packages() {
+        let fixture = Fixture::new("incremental");
+        fixture.publish("app", "1.0.0", "app data");
+        fixture.publish("lib", "1.0.0", "lib data");
+
+        let first = fixture.build(true);
+        assert_eq!((first.scanned, first.reused), (2, 0));
+        assert!(fixture.dir.join("stable/index.bin").exists());
+        assert!(fixture.dir.join("stable/x86_64/index.toml").exists());
+        let second = fixture.build(true);
+        assert_eq!((second.scanned, second.reused), (2, 2));
+
+        fixture.publish("app", "1.0.0", "app data, now larger");
+        let changed = fixture.build(true);
+        assert_eq!((changed.scanned, changed.reused), (2, 1));
+        assert_eq!(install_size(&changed, "app"), 20);
+        assert_eq!(install_size(&changed, "lib"), 8);
+
+        // A full rebuild ignores the scan cache but reads the same packages.
+        let full = fixture.build(false);
+        assert_eq!((full.scanned, full.reused), (2, 0));
+        assert_eq!(install_size(&full, "app"), 20);
+        assert_eq!(fixture.build(true).reused, 2);
+
+        fs::remove_file(fixture.dir.join("stable/lib-1.0.0-x86_64.plpm")).unwrap();
+        let removed = fixture.build(true);
+        assert_eq!((removed.scanned, removed.reused), (1, 1));
+
+        // Indexes of architectures and channels left without packages go.
+        fixture.publish_for(Architecture::AArch64, "app", "1.0.0", "app data");
+        fixture.build(true);
+        assert!(fixture.dir.join("stable/aarch64/index.bin").exists());
+        fs::remove_file(fixture.dir.join("stable/app-1.0.0-aarch64.plpm")).unwrap();
+        fixture.build(true);
+        assert!(!fixture.dir.join("stable/aarch64/index.bin").exists());
+        assert!(!fixture.dir.join("stable/aarch64/index.toml").exists());
+        assert!(fixture.dir.join("stable/x86_64/index.bin").exists());
+
+        fs::remove_file(fixture.dir.join("stable/app-1.0.0-x86_64.plpm")).unwrap();
+        let empty = fixture.build(true);
+        assert!(empty.indexes.is_empty());
+        for index in ["stable/index.bin", "stable/index.toml", "stable/x86_64/index.bin", "stable/x86_64/index.toml"] {
+            assert!(!fixture.dir.join(index).exists(), "{} is still there", index);
+        }
+    }
+}
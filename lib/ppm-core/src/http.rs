@@ -0,0 +1,226 @@
+use reqwest::header::{CONTENT_RANGE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE};
+use reqwest::StatusCode;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::time::Duration;
+
+use crate::config::NetworkConfig;
+use crate::repository::IndexValidators;
+use crate::{PpmError, Result};
+
+#[derive(Debug)]
+pub enum Fetched {
+    Modified { body: Vec<u8>, validators: IndexValidators },
+    NotModified,
+    Missing,
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl HttpClient {
+    pub fn new(network: &NetworkConfig) -> Result<Self> {
+        let timeout = Duration::from_secs(network.timeout_secs.max(1));
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("ppm/", env!("CARGO_PKG_VERSION")))
+            .connect_timeout(timeout)
+            .read_timeout(timeout)
+            .build()
+            .map_err(|e| PpmError::Repository(format!("cannot create HTTP client: {}", e)))?;
+        Ok(Self {
+            client,
+            retries: network.retries,
+            retry_delay: Duration::from_millis(network.retry_delay_ms),
+        })
+    }
+
+    // Sends a GET with exponential backoff between attempts. Connection
+    // errors, timeouts, 429 and 5xx responses are retried; anything else is
+    // returned to the caller as-is.
+    async fn send(
+        &self,
+        url: &str,
+        validators: Option<&IndexValidators>,
+        offset: u64,
+    ) -> Result<reqwest::Response> {
+        let mut attempt = 0;
+        loop {
+            let mut request = self.client.get(url);
+            if offset > 0 {
+                request = request.header(RANGE, format!("bytes={}-", offset));
+            }
+            if let Some(v) = validators.filter(|v| v.url == url) {
+                if let Some(etag) = &v.etag {
+                    request = request.header(IF_NONE_MATCH, etag);
+                }
+                if let Some(modified) = &v.last_modified {
+                    request = request.header(IF_MODIFIED_SINCE, modified);
+                }
+            }
+
+            let error = match request.send().await {
+                Ok(response) => {
+                    let status = response.status();
+                    if !(status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS) {
+                        return Ok(response);
+                    }
+                    format!("{} returned {}", url, status)
+                }
+                Err(e) if e.is_timeout() || e.is_connect() || e.is_request() => {
+                    format!("{}: {}", url, e)
+                }
+                Err(e) => return Err(PpmError::Repository(format!("{}: {}", url, e))),
+            };
+            if attempt >= self.retries {
+                return Err(PpmError::Repository(format!(
+                    "{} (gave up after {} attempts)", error, attempt + 1
+                )));
+            }
+            tokio::time::sleep(self.retry_delay * 2u32.saturating_pow(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    pub async fn get(&self, url: &str, validators: Option<&IndexValidators>) -> Result<Fetched> {
+        let response = self.send(url, validators, 0).await?;
+        match response.status() {
+            StatusCode::NOT_MODIFIED => return Ok(Fetched::NotModified),
+            StatusCode::NOT_FOUND | StatusCode::GONE => return Ok(Fetched::Missing),
+            status if !status.is_success() => {
+                return Err(PpmError::Repository(format!("{} returned {}", url, status)));
+            }
+            _ => {}
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let validators = IndexValidators {
+            url: url.to_string(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| PpmError::Repository(format!("{}: {}", url, e)))?;
+        Ok(Fetched::Modified {
+            body: body.to_vec(),
+            validators,
+        })
+    }
+
+    // Continues a download into `file`, asking only for the bytes past what
+    // it already holds. A server that ignores the range gets its full body
+    // written over the old content, and a transfer that drops midway is
+    // picked up again from where it stopped, up to the configured retries.
+    // `expected` stands in for the length when the server does not send one.
+    pub async fn download_resume(
+        &self,
+        url: &str,
+        file: &mut File,
+        expected: Option<u64>,
+        mut progress: impl FnMut(u64, Option<u64>),
+    ) -> Result<u64> {
+        let mut attempt = 0;
+        loop {
+            let offset = file.metadata()?.len();
+            let error = match self.transfer(url, file, offset, expected, &mut progress).await? {
+                Ok(length) => return Ok(length),
+                Err(error) => error,
+            };
+            if attempt >= self.retries {
+                return Err(PpmError::Repository(format!(
+                    "{} (gave up after {} attempts)", error, attempt + 1
+                )));
+            }
+            tokio::time::sleep(self.retry_delay * 2u32.saturating_pow(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    // The outer error is fatal; the inner one means the transfer was cut
+    // short and is worth resuming.
+    async fn transfer(
+        &self,
+        url: &str,
+        file: &mut File,
+        offset: u64,
+        expected: Option<u64>,
+        progress: &mut impl FnMut(u64, Option<u64>),
+    ) -> Result<std::result::Result<u64, String>> {
+        let mut response = self.send(url, None, offset).await?;
+        let start = match response.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let start = response
+                    .headers()
+                    .get(CONTENT_RANGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.strip_prefix("bytes "))
+                    .and_then(|v| v.split('-').next())
+                    .and_then(|v| v.parse::<u64>().ok());
+                if start != Some(offset) {
+                    file.set_len(0)?;
+                    return Ok(Err(format!("{} answered a range request with the wrong range", url)));
+                }
+                offset
+            }
+            // Asking for bytes past the end means we already have them all;
+            // the caller's checksum decides whether they are the right ones.
+            StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => return Ok(Ok(offset)),
+            status if status.is_success() => 0,
+            status => return Err(PpmError::Repository(format!("{} returned {}", url, status))),
+        };
+
+        file.set_len(start)?;
+        file.seek(SeekFrom::Start(start))?;
+        let total = response.content_length().map(|length| start + length).or(expected);
+        // A mirror that sends more than the package is known to weigh is
+        // cut off here rather than trusted to stop before the disk fills.
+        let oversized = |file: &mut File, size: u64| {
+            file.set_len(0)?;
+            Err(PpmError::SecurityViolation(format!(
+                "{} sent {} bytes for a package of {}",
+                url,
+                size,
+                expected.unwrap_or_default()
+            )))
+        };
+        if let (Some(total), Some(expected)) = (total, expected) {
+            if total > expected {
+                return oversized(file, total);
+            }
+        }
+        let mut written = start;
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    if expected.is_some_and(|expected| written + chunk.len() as u64 > expected) {
+                        return oversized(file, written + chunk.len() as u64);
+                    }
+                    file.write_all(&chunk)?;
+                    written += chunk.len() as u64;
+                    progress(written, total);
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    file.sync_all()?;
+                    return Ok(Err(format!("{}: {}", url, e)));
+                }
+            }
+        }
+        file.sync_all()?;
+        if total.is_some_and(|total| written < total) {
+            return Ok(Err(format!("{} ended after {} bytes", url, written)));
+        }
+        Ok(Ok(written))
+    }
+}
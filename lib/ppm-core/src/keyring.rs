@@ -0,0 +1,405 @@
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::database::now_timestamp;
+use crate::signing::{self, key_fingerprint, PackageSignature, SignaturePolicy, Verification};
+use crate::{Channel, PpmError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum KeyTrust {
+    #[serde(rename = "trusted")]
+    Trusted,
+    #[serde(rename = "untrusted")]
+    Untrusted,
+    #[serde(rename = "revoked")]
+    Revoked,
+}
+
+impl KeyTrust {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyTrust::Trusted => "trusted",
+            KeyTrust::Untrusted => "untrusted",
+            KeyTrust::Revoked => "revoked",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRecord {
+    pub fingerprint: String,
+    pub public_key: String,
+    pub owner: String,
+    pub created_at: u64,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    pub trust: KeyTrust,
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+    #[serde(default)]
+    pub repositories: Vec<String>,
+    #[serde(default)]
+    pub revoked_at: Option<u64>,
+    #[serde(default)]
+    pub revocation_reason: Option<String>,
+}
+
+impl KeyRecord {
+    pub fn new(key: &VerifyingKey, owner: impl Into<String>) -> Self {
+        Self {
+            fingerprint: key_fingerprint(key),
+            public_key: hex::encode(key.as_bytes()),
+            owner: owner.into(),
+            created_at: now_timestamp(),
+            expires_at: None,
+            trust: KeyTrust::Untrusted,
+            channels: Vec::new(),
+            repositories: Vec::new(),
+            revoked_at: None,
+            revocation_reason: None,
+        }
+    }
+
+    pub fn verifying_key(&self) -> Result<VerifyingKey> {
+        parse_public_key(&self.public_key)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expiry| expiry <= now)
+    }
+
+    // An empty scope list means the key may sign for any channel or repository.
+    pub fn allows(&self, channel: Channel, repository: Option<&str>) -> bool {
+        let channel_ok = self.channels.is_empty() || self.channels.contains(&channel);
+        let repository_ok = self.repositories.is_empty()
+            || repository.is_some_and(|r| self.repositories.iter().any(|allowed| allowed == r));
+        channel_ok && repository_ok
+    }
+}
+
+pub fn parse_public_key(text: &str) -> Result<VerifyingKey> {
+    hex::decode(text.trim())
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| PpmError::Keyring("not a valid hex-encoded ed25519 public key".to_string()))
+}
+
+pub struct Keyring {
+    dir: PathBuf,
+    keys: BTreeMap<String, KeyRecord>,
+}
+
+impl Keyring {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let mut keys = BTreeMap::new();
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self { dir, keys }),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("toml") {
+                continue;
+            }
+            let contents = fs::read_to_string(&path)?;
+            let record: KeyRecord = toml::from_str(&contents)
+                .map_err(|e| PpmError::Keyring(format!("{}: {}", path.display(), e)))?;
+            if key_fingerprint(&record.verifying_key()?) != record.fingerprint {
+                return Err(PpmError::Keyring(format!(
+                    "{}: fingerprint does not match the stored public key",
+                    path.display()
+                )));
+            }
+            keys.insert(record.fingerprint.clone(), record);
+        }
+        Ok(Self { dir, keys })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &KeyRecord> {
+        self.keys.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    // Accepts a full fingerprint or any unambiguous prefix of one.
+    pub fn get(&self, id: &str) -> Result<&KeyRecord> {
+        let id = id.trim().to_ascii_lowercase();
+        if let Some(record) = self.keys.get(&id) {
+            return Ok(record);
+        }
+        let mut matches = self.keys.values().filter(|k| !id.is_empty() && k.fingerprint.starts_with(&id));
+        match (matches.next(), matches.next()) {
+            (Some(record), None) => Ok(record),
+            (Some(_), Some(_)) => Err(PpmError::Keyring(format!("key id '{}' is ambiguous", id))),
+            (None, _) => Err(PpmError::Keyring(format!("no key matching '{}' in the keyring", id))),
+        }
+    }
+
+    // Re-importing a known key refreshes its owner, expiry and scope but keeps
+    // its trust state, so rotating metadata never silently re-trusts a key.
+    pub fn import(&mut self, record: KeyRecord) -> Result<&KeyRecord> {
+        if key_fingerprint(&record.verifying_key()?) != record.fingerprint {
+            return Err(PpmError::Keyring(format!(
+                "fingerprint {} does not match the public key",
+                record.fingerprint
+            )));
+        }
+        let record = match self.keys.get(&record.fingerprint) {
+            Some(existing) if existing.trust == KeyTrust::Revoked => {
+                return Err(PpmError::Keyring(format!(
+                    "key {} has been revoked and cannot be re-imported",
+                    existing.fingerprint
+                )));
+            }
+            Some(existing) => KeyRecord {
+                trust: existing.trust,
+                created_at: existing.created_at,
+                ..record
+            },
+            None => record,
+        };
+        self.store(record)
+    }
+
+    // Reads either an exported key record or a bare hex public key.
+    pub fn import_file(&mut self, path: impl AsRef<Path>, owner: &str) -> Result<&KeyRecord> {
+        let contents = fs::read_to_string(path.as_ref())?;
+        let record = match toml::from_str::<KeyRecord>(&contents) {
+            Ok(record) => KeyRecord {
+                trust: KeyTrust::Untrusted,
+                revoked_at: None,
+                revocation_reason: None,
+                ..record
+            },
+            Err(_) => KeyRecord::new(&parse_public_key(&contents)?, owner),
+        };
+        self.import(record)
+    }
+
+    pub fn export(&self, id: &str) -> Result<String> {
+        toml::to_string_pretty(self.get(id)?).map_err(|e| PpmError::Serialization(e.to_string()))
+    }
+
+    pub fn trust(&mut self, id: &str) -> Result<&KeyRecord> {
+        let record = self.get(id)?.clone();
+        if record.trust == KeyTrust::Revoked {
+            return Err(PpmError::Keyring(format!(
+                "key {} has been revoked and cannot be trusted again",
+                record.fingerprint
+            )));
+        }
+        self.store(KeyRecord { trust: KeyTrust::Trusted, ..record })
+    }
+
+    pub fn distrust(&mut self, id: &str) -> Result<&KeyRecord> {
+        let record = self.get(id)?.clone();
+        if record.trust == KeyTrust::Revoked {
+            return Ok(&self.keys[&record.fingerprint]);
+        }
+        self.store(KeyRecord { trust: KeyTrust::Untrusted, ..record })
+    }
+
+    pub fn revoke(&mut self, id: &str, reason: Option<&str>) -> Result<&KeyRecord> {
+        let record = self.get(id)?.clone();
+        self.store(KeyRecord {
+            trust: KeyTrust::Revoked,
+            revoked_at: record.revoked_at.or(Some(now_timestamp())),
+            revocation_reason: reason.map(str::to_string).or(record.revocation_reason),
+            ..record
+        })
+    }
+
+    pub fn trusted_key(&self, fingerprint: &str, channel: Channel, repository: Option<&str>) -> Option<VerifyingKey> {
+        self.keys
+            .get(fingerprint)
+            .filter(|k| k.trust == KeyTrust::Trusted && !k.is_expired(now_timestamp()))
+            .filter(|k| k.allows(channel, repository))
+            .and_then(|k| k.verifying_key().ok())
+    }
+
+    pub fn verify(
+        &self,
+        digest: &[u8; 32],
+        signature: Option<&str>,
+        policy: SignaturePolicy,
+        channel: Channel,
+        repository: Option<&str>,
+    ) -> Result<Verification> {
+        let Some(encoded) = signature else {
+            return signing::verify_digest(digest, None, &[], policy);
+        };
+        let parsed: PackageSignature = encoded.parse()?;
+        let record = self.keys.get(&parsed.key_id).ok_or_else(|| {
+            PpmError::SecurityViolation(format!(
+                "package is signed by key {} which is not in the keyring",
+                parsed.key_id
+            ))
+        })?;
+        let rejected = match record.trust {
+            KeyTrust::Revoked => Some("has been revoked".to_string()),
+            KeyTrust::Untrusted => Some("is not trusted".to_string()),
+            KeyTrust::Trusted if record.is_expired(now_timestamp()) => Some("has expired".to_string()),
+            KeyTrust::Trusted if !record.allows(channel, repository) => Some(match repository {
+                Some(repo) => format!("may not sign for the {} channel of '{}'", channel.name(), repo),
+                None => format!("may not sign for the {} channel", channel.name()),
+            }),
+            KeyTrust::Trusted => None,
+        };
+        if let Some(reason) = rejected {
+            return Err(PpmError::SecurityViolation(format!(
+                "signing key {} ({}) {}",
+                record.fingerprint, record.owner, reason
+            )));
+        }
+        signing::verify_digest(digest, Some(encoded), &[record.verifying_key()?], policy)
+    }
+
+    fn store(&mut self, record: KeyRecord) -> Result<&KeyRecord> {
+        fs::create_dir_all(&self.dir)?;
+        let contents = toml::to_string_pretty(&record)
+            .map_err(|e| PpmError::Serialization(e.to_string()))?;
+        let path = self.dir.join(format!("{}.toml", record.fingerprint));
+        let tmp = path.with_extension("toml.tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &path)?;
+        let fingerprint = record.fingerprint.clone();
+        self.keys.insert(fingerprint.clone(), record);
+        Ok(&self.keys[&fingerprint])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::TestDir;
+    use crate::signing::sign_digest;
+    use ed25519_dalek::SigningKey;
+
+    const DIGEST: [u8; 32] = [7; 32];
+
+    struct Fixture {
+        dir: TestDir,
+        keyring: Keyring,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = TestDir::new("keyring", name);
+            Self { keyring: Keyring::open(&dir).unwrap(), dir }
+        }
+
+        // Imports `key` with `scope` applied and trusts it.
+        fn trust(&mut self, key: &SigningKey, scope: impl FnOnce(&mut KeyRecord)) -> String {
+            let mut record = KeyRecord::new(&key.verifying_key(), "tester");
+            scope(&mut record);
+            let id = record.fingerprint.clone();
+            self.keyring.import(record).unwrap();
+            self.keyring.trust(&id).unwrap();
+            id
+        }
+
+        fn verify(&self, key: &SigningKey, channel: Channel, repository: Option<&str>) -> Result<Verification> {
+            let signature = sign_digest(&DIGEST, key).to_string();
+            self.keyring.verify(&DIGEST, Some(&signature), SignaturePolicy::Require, channel, repository)
+        }
+    }
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn rejection(result: Result<Verification>) -> String {
+        match result {
+            Err(PpmError::SecurityViolation(message)) => message,
+            other => panic!("expected a security violation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn verifies_with_trusted_keys_only() {
+        let mut fixture = Fixture::new("trust");
+        let signer = key(1);
+        let message = rejection(fixture.verify(&signer, Channel::Stable, None));
+        assert!(message.contains("which is not in the keyring"), "{}", message);
+
+        let id = fixture.trust(&signer, |_| {});
+        assert_eq!(
+            fixture.verify(&signer, Channel::Stable, None).unwrap(),
+            Verification::Signed { key_id: id.clone() }
+        );
+
+        fixture.keyring.distrust(&id[..8]).unwrap();
+        let message = rejection(fixture.verify(&signer, Channel::Stable, None));
+        assert!(message.contains("is not trusted"), "{}", message);
+        // Trust decisions survive reopening the keyring.
+        let reopened = Keyring::open(&fixture.dir).unwrap();
+        assert_eq!(reopened.get(&id).unwrap().trust, KeyTrust::Untrusted);
+    }
+
+    #[test]
+    fn rejects_revoked_keys_for_good() {
+        let mut fixture = Fixture::new("revoke");
+        let signer = key(1);
+        let id = fixture.trust(&signer, |_| {});
+        let revoked = fixture.keyring.revoke(&id, Some("leaked")).unwrap();
+        assert_eq!(revoked.revocation_reason.as_deref(), Some("leaked"));
+        let message = rejection(fixture.verify(&signer, Channel::Stable, None));
+        assert!(message.contains("has been revoked"), "{}", message);
+
+        assert!(matches!(fixture.keyring.trust(&id), Err(PpmError::Keyring(_))));
+        let reimport = fixture.keyring.import(KeyRecord::new(&signer.verifying_key(), "tester"));
+        assert!(matches!(reimport, Err(PpmError::Keyring(_))));
+    }
+
+    #[test]
+    fn rejects_expired_keys() {
+        let mut fixture = Fixture::new("expiry");
+        let signer = key(1);
+        let id = fixture.trust(&signer, |record| record.expires_at = Some(now_timestamp() - 1));
+        let message = rejection(fixture.verify(&signer, Channel::Stable, None));
+        assert!(message.contains("has expired"), "{}", message);
+        assert!(fixture.keyring.trusted_key(&id, Channel::Stable, None).is_none());
+    }
+
+    #[test]
+    fn rejects_keys_outside_their_scope() {
+        let mut fixture = Fixture::new("scope");
+        let signer = key(1);
+        let id = fixture.trust(&signer, |record| {
+            record.channels = vec![Channel::Testing];
+            record.repositories = vec!["main".to_string()];
+        });
+        fixture.verify(&signer, Channel::Testing, Some("main")).unwrap();
+
+        let message = rejection(fixture.verify(&signer, Channel::Stable, Some("main")));
+        assert!(message.contains("may not sign for the stable channel of 'main'"), "{}", message);
+        let message = rejection(fixture.verify(&signer, Channel::Testing, Some("extra")));
+        assert!(message.contains("may not sign for the testing channel of 'extra'"), "{}", message);
+        // A repository-scoped key does not vouch for local files.
+        let message = rejection(fixture.verify(&signer, Channel::Testing, None));
+        assert!(message.contains("may not sign for the testing channel"), "{}", message);
+        assert!(fixture.keyring.trusted_key(&id, Channel::Testing, Some("main")).is_some());
+        assert!(fixture.keyring.trusted_key(&id, Channel::Stable, Some("main")).is_none());
+    }
+}
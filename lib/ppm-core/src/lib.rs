@@ -1,33 +1,72 @@
 pub mod architecture;
+pub mod builder;
+pub mod cache;
 pub mod channel;
 pub mod config;
+pub mod database;
+pub mod events;
+pub mod keyring;
+pub mod metadata;
 pub mod package;
 pub mod security;
+pub mod signing;
 pub mod repository;
+pub mod sandbox;
+pub mod resolver;
+pub mod transaction;
+pub mod version;
 pub mod error;
 pub mod formats;
+pub mod generator;
+#[cfg(feature = "http")]
+pub mod http;
 
 pub use architecture::Architecture;
+pub use cache::{CacheConfig, PackageCache};
 pub use channel::Channel;
-pub use config::Config;
-pub use package::{Package, PackageMetadata, PackageIndex};
+pub use config::{Config, NetworkConfig, PinRule, RepositoryConfig};
+pub use database::{InstalledDatabase, InstalledPackage, InstallReason};
+pub use events::{Event, Events, Observer};
+pub use keyring::{Keyring, KeyRecord, KeyTrust};
+pub use package::{Package, PackageMetadata, PackageIndex, Dependency};
 pub use security::{verify_signature, compute_checksum, generate_keypair};
+pub use signing::{SignaturePolicy, Verification};
 pub use error::{Result, PpmError};
 pub use repository::{Repository, RepositoryManager};
+pub use resolver::{Resolution, Resolver};
+pub use sandbox::{Sandbox, ScriptRun};
+pub use transaction::Transaction;
+pub use version::{Version, VersionReq};
 
 pub use plum_formats::plam;
 
+#[cfg(feature = "cli")]
+pub mod cli;
+
 #[cfg(not(target_os = "none"))]
 pub mod operations;
 #[cfg(not(target_os = "none"))]
 pub use operations::{
     load_config,
     install_package,
+    install_from_plpm,
+    install_from_file,
     remove_package,
+    autoremove,
     update_packages,
     search_packages,
     show_package_info,
     list_packages,
+    find_owners,
+    list_files,
     check_updates,
     clean_cache,
+    import_key,
+    export_key,
+    list_keys,
+    trust_key,
+    distrust_key,
+    revoke_key,
+    build_repository,
+    build_package,
 };
\ No newline at end of file
@@ -0,0 +1,814 @@
+use ed25519_dalek::{Signer, SigningKey};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::keyring::{parse_public_key, Keyring};
+use crate::signing::key_fingerprint;
+use crate::{compute_checksum, verify_signature, Channel, PpmError, Result};
+
+pub const ROOT_FILE: &str = "root.toml";
+pub const TIMESTAMP_FILE: &str = "timestamp.toml";
+pub const SNAPSHOT_FILE: &str = "snapshot.toml";
+pub const TARGETS_FILE: &str = "targets.toml";
+
+const SIGNING_DOMAIN: &str = "PPM-METADATA-V1";
+
+// Every root a repository has published is kept as `<version>.root.toml`,
+// so clients can walk a rotation one version at a time.
+pub fn versioned_root_file(version: u64) -> String {
+    format!("{}.{}", version, ROOT_FILE)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Root,
+    Timestamp,
+    Snapshot,
+    Targets,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Root => "root",
+            Role::Timestamp => "timestamp",
+            Role::Snapshot => "snapshot",
+            Role::Targets => "targets",
+        }
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Role::Root => ROOT_FILE,
+            Role::Timestamp => TIMESTAMP_FILE,
+            Role::Snapshot => SNAPSHOT_FILE,
+            Role::Targets => TARGETS_FILE,
+        }
+    }
+}
+
+pub trait RoleMetadata: Serialize + DeserializeOwned {
+    const ROLE: Role;
+
+    fn version(&self) -> u64;
+    fn expires(&self) -> u64;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoleSignature {
+    pub keyid: String,
+    pub sig: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Signed<T> {
+    pub signed: T,
+    #[serde(default)]
+    pub signatures: Vec<RoleSignature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoleKeys {
+    pub keyids: Vec<String>,
+    pub threshold: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootRoles {
+    pub root: RoleKeys,
+    pub timestamp: RoleKeys,
+    pub snapshot: RoleKeys,
+    pub targets: RoleKeys,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Root {
+    pub version: u64,
+    pub expires: u64,
+    pub keys: BTreeMap<String, String>,
+    pub roles: RootRoles,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetaFile {
+    pub version: u64,
+    pub length: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetFile {
+    pub length: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timestamp {
+    pub version: u64,
+    pub expires: u64,
+    pub snapshot: MetaFile,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u64,
+    pub expires: u64,
+    pub targets: MetaFile,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Targets {
+    pub version: u64,
+    pub expires: u64,
+    pub files: BTreeMap<String, TargetFile>,
+}
+
+macro_rules! role_metadata {
+    ($ty:ty, $role:expr) => {
+        impl RoleMetadata for $ty {
+            const ROLE: Role = $role;
+
+            fn version(&self) -> u64 {
+                self.version
+            }
+
+            fn expires(&self) -> u64 {
+                self.expires
+            }
+        }
+    };
+}
+
+role_metadata!(Root, Role::Root);
+role_metadata!(Timestamp, Role::Timestamp);
+role_metadata!(Snapshot, Role::Snapshot);
+role_metadata!(Targets, Role::Targets);
+
+impl Root {
+    pub fn role_keys(&self, role: Role) -> &RoleKeys {
+        match role {
+            Role::Root => &self.roles.root,
+            Role::Timestamp => &self.roles.timestamp,
+            Role::Snapshot => &self.roles.snapshot,
+            Role::Targets => &self.roles.targets,
+        }
+    }
+}
+
+// Signatures cover the role name and the `signed` table as re-serialized by
+// ppm, so formatting changes to the file on disk do not invalidate them.
+fn canonical_bytes<T: RoleMetadata>(signed: &T) -> Result<Vec<u8>> {
+    let body = toml::to_string(signed).map_err(|e| PpmError::Serialization(e.to_string()))?;
+    Ok(format!("{}\n{}\n{}", SIGNING_DOMAIN, T::ROLE.as_str(), body).into_bytes())
+}
+
+pub fn sign_role<T: RoleMetadata>(signed: T, keys: &[&SigningKey]) -> Result<Signed<T>> {
+    let bytes = canonical_bytes(&signed)?;
+    let signatures = keys
+        .iter()
+        .map(|key| RoleSignature {
+            keyid: key_fingerprint(&key.verifying_key()),
+            sig: hex::encode(key.sign(&bytes).to_bytes()),
+        })
+        .collect();
+    Ok(Signed { signed, signatures })
+}
+
+pub fn encode_role<T: RoleMetadata>(role: &Signed<T>) -> Result<String> {
+    toml::to_string_pretty(role).map_err(|e| PpmError::Serialization(e.to_string()))
+}
+
+pub fn decode_role<T: RoleMetadata>(data: &[u8]) -> Result<Signed<T>> {
+    let text = std::str::from_utf8(data).map_err(|_| {
+        PpmError::SecurityViolation(format!("{} metadata is not valid UTF-8", T::ROLE.as_str()))
+    })?;
+    toml::from_str(text).map_err(|e| {
+        PpmError::SecurityViolation(format!("invalid {} metadata: {}", T::ROLE.as_str(), e))
+    })
+}
+
+fn violation(msg: String) -> PpmError {
+    PpmError::SecurityViolation(msg)
+}
+
+// Counts distinct key ids from `keys` with a valid signature over the role
+// and fails unless the threshold is reached.
+fn check_threshold<T: RoleMetadata>(
+    role: &Signed<T>,
+    keys: &BTreeMap<String, [u8; 32]>,
+    threshold: u32,
+    signer: &str,
+) -> Result<()> {
+    let bytes = canonical_bytes(&role.signed)?;
+    let mut valid = Vec::new();
+    for signature in &role.signatures {
+        if valid.contains(&signature.keyid) {
+            continue;
+        }
+        let Some(key) = keys.get(&signature.keyid) else {
+            continue;
+        };
+        let Ok(sig) = hex::decode(&signature.sig) else {
+            continue;
+        };
+        if verify_signature(&bytes, &sig, key).is_ok() {
+            valid.push(signature.keyid.clone());
+        }
+    }
+    if threshold == 0 || (valid.len() as u64) < threshold as u64 {
+        return Err(violation(format!(
+            "{} metadata has {} valid signature(s) from {}, {} required",
+            T::ROLE.as_str(),
+            valid.len(),
+            signer,
+            threshold.max(1)
+        )));
+    }
+    Ok(())
+}
+
+fn root_role_keys(root: &Root, role: Role) -> Result<BTreeMap<String, [u8; 32]>> {
+    let mut keys = BTreeMap::new();
+    for keyid in &root.role_keys(role).keyids {
+        let hex_key = root.keys.get(keyid).ok_or_else(|| {
+            violation(format!("root lists key {} for {} but does not define it", keyid, role.as_str()))
+        })?;
+        let key = parse_public_key(hex_key)?;
+        if &key_fingerprint(&key) != keyid {
+            return Err(violation(format!("root key {} does not match its fingerprint", keyid)));
+        }
+        keys.insert(keyid.clone(), key.to_bytes());
+    }
+    Ok(keys)
+}
+
+fn check_expiry<T: RoleMetadata>(signed: &T, now: u64) -> Result<()> {
+    if signed.expires() <= now {
+        return Err(violation(format!(
+            "{} metadata version {} expired at {}",
+            T::ROLE.as_str(),
+            signed.version(),
+            signed.expires()
+        )));
+    }
+    Ok(())
+}
+
+fn check_version<T: RoleMetadata>(new: &T, trusted: Option<&T>) -> Result<()> {
+    if let Some(trusted) = trusted {
+        if new.version() < trusted.version() {
+            return Err(violation(format!(
+                "{} metadata version {} is older than trusted version {}",
+                T::ROLE.as_str(),
+                new.version(),
+                trusted.version()
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn check_meta(file: &str, data: &[u8], expected: &MetaFile) -> Result<()> {
+    if data.len() as u64 != expected.length || !compute_checksum(data).eq_ignore_ascii_case(&expected.sha256) {
+        return Err(violation(format!("{} does not match the hash recorded for it", file)));
+    }
+    Ok(())
+}
+
+pub struct TrustedMetadata {
+    dir: PathBuf,
+    root: Option<Signed<Root>>,
+    timestamp: Option<Signed<Timestamp>>,
+    snapshot: Option<Signed<Snapshot>>,
+    targets: Option<Signed<Targets>>,
+}
+
+impl TrustedMetadata {
+    // Files in `dir` were verified before they were stored, so they are
+    // loaded without re-checking signatures or expiry.
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        fn read<T: RoleMetadata>(dir: &Path) -> Result<Option<Signed<T>>> {
+            match fs::read(dir.join(T::ROLE.file_name())) {
+                Ok(data) => decode_role(&data).map(Some),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        }
+        let dir = dir.as_ref().to_path_buf();
+        Ok(Self {
+            root: read(&dir)?,
+            timestamp: read(&dir)?,
+            snapshot: read(&dir)?,
+            targets: read(&dir)?,
+            dir,
+        })
+    }
+
+    pub fn root(&self) -> Option<&Root> {
+        self.root.as_ref().map(|r| &r.signed)
+    }
+
+    pub fn timestamp(&self) -> Option<&Timestamp> {
+        self.timestamp.as_ref().map(|r| &r.signed)
+    }
+
+    pub fn snapshot(&self) -> Option<&Snapshot> {
+        self.snapshot.as_ref().map(|r| &r.signed)
+    }
+
+    pub fn targets(&self) -> Option<&Targets> {
+        self.targets.as_ref().map(|r| &r.signed)
+    }
+
+    fn store(&self, role: Role, data: &[u8]) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(role.file_name());
+        let tmp = path.with_extension("toml.tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(data)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn trusted_root(&self) -> Result<&Root> {
+        self.root()
+            .ok_or_else(|| violation("no trusted root metadata".to_string()))
+    }
+
+    // The first root is accepted when enough of its root keys are trusted in
+    // the local keyring for this repository. Later roots must follow the
+    // trusted one by exactly one version and be signed by a threshold of both
+    // it and themselves; callers walk longer rotations version by version.
+    // Only the root a walk ends on has to be unexpired, which `check_fresh`
+    // enforces.
+    pub fn update_root(
+        &mut self,
+        data: &[u8],
+        keyring: &Keyring,
+        channel: Channel,
+        repository: &str,
+        now: u64,
+    ) -> Result<()> {
+        let new: Signed<Root> = decode_role(data)?;
+        let new_keys = root_role_keys(&new.signed, Role::Root)?;
+        match &self.root {
+            None => {
+                let anchored: BTreeMap<_, _> = new_keys
+                    .iter()
+                    .filter(|(keyid, _)| keyring.trusted_key(keyid, channel, Some(repository)).is_some())
+                    .map(|(keyid, key)| (keyid.clone(), *key))
+                    .collect();
+                check_threshold(&new, &anchored, new.signed.roles.root.threshold, "keyring-trusted root keys")?;
+            }
+            Some(trusted) => {
+                check_version(&new.signed, Some(&trusted.signed))?;
+                if new.signed.version == trusted.signed.version {
+                    if new.signed != trusted.signed {
+                        return Err(violation(format!(
+                            "root metadata version {} differs from the trusted copy",
+                            new.signed.version
+                        )));
+                    }
+                    return check_expiry(&trusted.signed, now);
+                }
+                if new.signed.version != trusted.signed.version + 1 {
+                    return Err(violation(format!(
+                        "root metadata version {} does not follow trusted version {}",
+                        new.signed.version, trusted.signed.version
+                    )));
+                }
+                let old_keys = root_role_keys(&trusted.signed, Role::Root)?;
+                check_threshold(&new, &old_keys, trusted.signed.roles.root.threshold, "the trusted root")?;
+                check_threshold(&new, &new_keys, new.signed.roles.root.threshold, "the new root")?;
+            }
+        }
+        if self.root.is_none() {
+            check_expiry(&new.signed, now)?;
+        }
+
+        let rotated = self.root.as_ref().is_some_and(|old| {
+            old.signed.roles.timestamp != new.signed.roles.timestamp
+                || old.signed.roles.snapshot != new.signed.roles.snapshot
+                || old.signed.roles.targets != new.signed.roles.targets
+        });
+        self.store(Role::Root, data)?;
+        self.root = Some(new);
+        // Rotated role keys invalidate what the old keys signed.
+        if rotated {
+            for role in [Role::Timestamp, Role::Snapshot, Role::Targets] {
+                let _ = fs::remove_file(self.dir.join(role.file_name()));
+            }
+            self.timestamp = None;
+            self.snapshot = None;
+            self.targets = None;
+        }
+        Ok(())
+    }
+
+    fn verify_role<T: RoleMetadata>(&self, data: &[u8], trusted: Option<&Signed<T>>, now: u64) -> Result<Signed<T>> {
+        let root = self.trusted_root()?;
+        let role: Signed<T> = decode_role(data)?;
+        let keys = root_role_keys(root, T::ROLE)?;
+        check_threshold(&role, &keys, root.role_keys(T::ROLE).threshold, "the trusted root")?;
+        check_version(&role.signed, trusted.map(|t| &t.signed))?;
+        check_expiry(&role.signed, now)?;
+        Ok(role)
+    }
+
+    pub fn update_timestamp(&mut self, data: &[u8], now: u64) -> Result<()> {
+        let new = self.verify_role(data, self.timestamp.as_ref(), now)?;
+        if let Some(snapshot) = self.snapshot() {
+            if new.signed.snapshot.version < snapshot.version {
+                return Err(violation(format!(
+                    "timestamp points at snapshot version {} but version {} is trusted",
+                    new.signed.snapshot.version, snapshot.version
+                )));
+            }
+        }
+        self.store(Role::Timestamp, data)?;
+        self.timestamp = Some(new);
+        Ok(())
+    }
+
+    pub fn needs_snapshot(&self) -> bool {
+        match (self.timestamp(), self.snapshot()) {
+            (Some(ts), Some(snapshot)) => ts.snapshot.version != snapshot.version,
+            _ => true,
+        }
+    }
+
+    pub fn update_snapshot(&mut self, data: &[u8], now: u64) -> Result<()> {
+        let expected = self
+            .timestamp()
+            .map(|ts| ts.snapshot.clone())
+            .ok_or_else(|| violation("no trusted timestamp metadata".to_string()))?;
+        check_meta(SNAPSHOT_FILE, data, &expected)?;
+        let new = self.verify_role(data, self.snapshot.as_ref(), now)?;
+        if new.signed.version != expected.version {
+            return Err(violation(format!(
+                "snapshot version {} does not match timestamp ({})",
+                new.signed.version, expected.version
+            )));
+        }
+        if let Some(targets) = self.targets() {
+            if new.signed.targets.version < targets.version {
+                return Err(violation(format!(
+                    "snapshot points at targets version {} but version {} is trusted",
+                    new.signed.targets.version, targets.version
+                )));
+            }
+        }
+        self.store(Role::Snapshot, data)?;
+        self.snapshot = Some(new);
+        Ok(())
+    }
+
+    pub fn needs_targets(&self) -> bool {
+        match (self.snapshot(), self.targets()) {
+            (Some(snapshot), Some(targets)) => snapshot.targets.version != targets.version,
+            _ => true,
+        }
+    }
+
+    pub fn update_targets(&mut self, data: &[u8], now: u64) -> Result<()> {
+        let expected = self
+            .snapshot()
+            .map(|s| s.targets.clone())
+            .ok_or_else(|| violation("no trusted snapshot metadata".to_string()))?;
+        check_meta(TARGETS_FILE, data, &expected)?;
+        let new = self.verify_role(data, self.targets.as_ref(), now)?;
+        if new.signed.version != expected.version {
+            return Err(violation(format!(
+                "targets version {} does not match snapshot ({})",
+                new.signed.version, expected.version
+            )));
+        }
+        self.store(Role::Targets, data)?;
+        self.targets = Some(new);
+        Ok(())
+    }
+
+    // Guards against a mirror that stops updating: every trusted role has to
+    // still be unexpired, not just the ones that changed in this refresh.
+    pub fn check_fresh(&self, now: u64) -> Result<()> {
+        check_expiry(self.trusted_root()?, now)?;
+        for (present, role) in [
+            (self.timestamp.is_some(), Role::Timestamp),
+            (self.snapshot.is_some(), Role::Snapshot),
+            (self.targets.is_some(), Role::Targets),
+        ] {
+            if !present {
+                return Err(violation(format!("no trusted {} metadata", role.as_str())));
+            }
+        }
+        check_expiry(self.timestamp().unwrap(), now)?;
+        check_expiry(self.snapshot().unwrap(), now)?;
+        check_expiry(self.targets().unwrap(), now)
+    }
+
+    pub fn target(&self, name: &str) -> Option<&TargetFile> {
+        self.targets().and_then(|t| t.files.get(name))
+    }
+
+    pub fn verify_target(&self, name: &str, data: &[u8]) -> Result<()> {
+        let target = self
+            .target(name)
+            .ok_or_else(|| violation(format!("{} is not listed in the targets metadata", name)))?;
+        if data.len() as u64 != target.length || !compute_checksum(data).eq_ignore_ascii_case(&target.sha256) {
+            return Err(violation(format!("{} does not match the targets metadata", name)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::TestDir;
+    use crate::keyring::KeyRecord;
+
+    const NOW: u64 = 1_700_000_000;
+    const REPO: &str = "main";
+
+    struct Fixture {
+        dir: TestDir,
+        keyring: Keyring,
+        trusted: TrustedMetadata,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = TestDir::new("metadata", name);
+            Self {
+                keyring: Keyring::open(dir.join("keys")).unwrap(),
+                trusted: TrustedMetadata::load(dir.join("metadata")).unwrap(),
+                dir,
+            }
+        }
+
+        fn trust(&mut self, key: &SigningKey) {
+            let record = KeyRecord::new(&key.verifying_key(), "test");
+            let id = record.fingerprint.clone();
+            self.keyring.import(record).unwrap();
+            self.keyring.trust(&id).unwrap();
+        }
+
+        fn update_root(&mut self, data: &[u8]) -> Result<()> {
+            self.trusted.update_root(data, &self.keyring, Channel::Stable, REPO, NOW)
+        }
+    }
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn id(key: &SigningKey) -> String {
+        key_fingerprint(&key.verifying_key())
+    }
+
+    fn root(version: u64, root_keys: &[&SigningKey], threshold: u32, online: &SigningKey) -> Root {
+        let mut keys = BTreeMap::new();
+        for key in root_keys.iter().chain([&online]) {
+            keys.insert(id(key), hex::encode(key.verifying_key().as_bytes()));
+        }
+        let online = RoleKeys { keyids: vec![id(online)], threshold: 1 };
+        Root {
+            version,
+            expires: NOW + 3600,
+            keys,
+            roles: RootRoles {
+                root: RoleKeys { keyids: root_keys.iter().map(|k| id(k)).collect(), threshold },
+                timestamp: online.clone(),
+                snapshot: online.clone(),
+                targets: online,
+            },
+        }
+    }
+
+    fn encode<T: RoleMetadata>(signed: T, keys: &[&SigningKey]) -> Vec<u8> {
+        encode_role(&sign_role(signed, keys).unwrap()).unwrap().into_bytes()
+    }
+
+    fn meta(version: u64, data: &[u8]) -> MetaFile {
+        MetaFile { version, length: data.len() as u64, sha256: compute_checksum(data) }
+    }
+
+    fn violation(result: Result<()>) -> String {
+        match result {
+            Err(PpmError::SecurityViolation(message)) => message,
+            other => panic!("expected a security violation, got {:?}", other),
+        }
+    }
+
+    // Publishes targets, snapshot and timestamp for `files`, each at `version`.
+    fn publish(online: &SigningKey, version: u64, files: &[(&str, &[u8])]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let targets = encode(
+            Targets {
+                version,
+                expires: NOW + 600,
+                files: files
+                    .iter()
+                    .map(|(name, data)| {
+                        let file = TargetFile { length: data.len() as u64, sha256: compute_checksum(data) };
+                        (name.to_string(), file)
+                    })
+                    .collect(),
+            },
+            &[online],
+        );
+        let snapshot = Snapshot { version, expires: NOW + 600, targets: meta(version, &targets) };
+        let snapshot = encode(snapshot, &[online]);
+        let timestamp = Timestamp { version, expires: NOW + 60, snapshot: meta(version, &snapshot) };
+        let timestamp = encode(timestamp, &[online]);
+        (timestamp, snapshot, targets)
+    }
+
+    #[test]
+    fn anchors_the_first_root_in_a_threshold_of_keyring_keys() {
+        let (k1, k2, online) = (key(1), key(2), key(9));
+        let mut fixture = Fixture::new("anchor");
+        let data = encode(root(1, &[&k1, &k2], 2, &online), &[&k1, &k2]);
+
+        fixture.trust(&k1);
+        let message = violation(fixture.update_root(&data));
+        assert!(
+            message.contains("1 valid signature(s) from keyring-trusted root keys, 2 required"),
+            "{}",
+            message
+        );
+
+        fixture.trust(&k2);
+        fixture.update_root(&data).unwrap();
+        assert_eq!(fixture.trusted.root().unwrap().version, 1);
+        let reloaded = TrustedMetadata::load(fixture.dir.join("metadata")).unwrap();
+        assert_eq!(reloaded.root().unwrap().version, 1);
+    }
+
+    #[test]
+    fn counts_each_key_once_towards_the_threshold() {
+        let (k1, k2, online) = (key(1), key(2), key(9));
+        let mut fixture = Fixture::new("duplicates");
+        fixture.trust(&k1);
+        fixture.trust(&k2);
+        let mut signed = sign_role(root(1, &[&k1, &k2], 2, &online), &[&k1]).unwrap();
+        signed.signatures.push(signed.signatures[0].clone());
+        // A signature from a key the root does not list never counts.
+        signed.signatures.extend(sign_role(signed.signed.clone(), &[&key(3)]).unwrap().signatures);
+        let message = violation(fixture.update_root(encode_role(&signed).unwrap().as_bytes()));
+        assert!(message.contains("1 valid signature(s)"), "{}", message);
+    }
+
+    #[test]
+    fn rotating_the_root_needs_both_the_old_and_new_keys() {
+        let (k1, k2, online) = (key(1), key(2), key(9));
+        let mut fixture = Fixture::new("rotate");
+        fixture.trust(&k1);
+        fixture.update_root(&encode(root(1, &[&k1], 1, &online), &[&k1])).unwrap();
+
+        let rotated = root(2, &[&k2], 1, &online);
+        let message = violation(fixture.update_root(&encode(rotated.clone(), &[&k2])));
+        assert!(message.contains("from the trusted root"), "{}", message);
+        let message = violation(fixture.update_root(&encode(rotated.clone(), &[&k1])));
+        assert!(message.contains("from the new root"), "{}", message);
+
+        fixture.update_root(&encode(rotated, &[&k1, &k2])).unwrap();
+        assert_eq!(fixture.trusted.root().unwrap().version, 2);
+
+        // Neither an older root nor a different root under the same version
+        // is accepted.
+        let message = violation(fixture.update_root(&encode(root(1, &[&k1], 1, &online), &[&k1])));
+        assert!(message.contains("root metadata version 1 is older than trusted version 2"), "{}", message);
+        let message = violation(fixture.update_root(&encode(root(2, &[&k2], 1, &key(8)), &[&k2])));
+        assert!(message.contains("differs from the trusted copy"), "{}", message);
+    }
+
+    #[test]
+    fn walks_root_rotations_one_version_at_a_time() {
+        let (k1, k2, k3, online) = (key(1), key(2), key(3), key(9));
+        let mut fixture = Fixture::new("walk");
+        fixture.trust(&k1);
+        fixture.update_root(&encode(root(1, &[&k1], 1, &online), &[&k1])).unwrap();
+
+        // Version 3 is signed by the keys of version 2, which this client has
+        // never seen, so it cannot be taken directly.
+        let third = encode(root(3, &[&k3], 1, &online), &[&k2, &k3]);
+        let message = violation(fixture.update_root(&third));
+        assert!(message.contains("root metadata version 3 does not follow trusted version 1"), "{}", message);
+
+        // An intermediate root may have expired since it was replaced.
+        let mut expired = root(2, &[&k2], 1, &online);
+        expired.expires = NOW;
+        fixture.update_root(&encode(expired, &[&k1, &k2])).unwrap();
+        fixture.update_root(&third).unwrap();
+        assert_eq!(fixture.trusted.root().unwrap().version, 3);
+    }
+
+    #[test]
+    fn rejects_expired_roots() {
+        let (k1, online) = (key(1), key(9));
+        let mut fixture = Fixture::new("expired-root");
+        fixture.trust(&k1);
+        let mut expired = root(1, &[&k1], 1, &online);
+        expired.expires = NOW;
+        let message = violation(fixture.update_root(&encode(expired, &[&k1])));
+        assert!(message.contains("expired"), "{}", message);
+    }
+
+    #[test]
+    fn verifies_the_chain_from_timestamp_to_targets() {
+        let (k1, online) = (key(1), key(9));
+        let mut fixture = Fixture::new("chain");
+        fixture.trust(&k1);
+        fixture.update_root(&encode(root(1, &[&k1], 1, &online), &[&k1])).unwrap();
+
+        let (timestamp, snapshot, targets) = publish(&online, 1, &[("index.bin", b"index")]);
+        fixture.trusted.update_timestamp(&timestamp, NOW).unwrap();
+        assert!(fixture.trusted.needs_snapshot());
+        fixture.trusted.update_snapshot(&snapshot, NOW).unwrap();
+        assert!(fixture.trusted.needs_targets());
+        fixture.trusted.update_targets(&targets, NOW).unwrap();
+        assert!(!fixture.trusted.needs_snapshot() && !fixture.trusted.needs_targets());
+        fixture.trusted.check_fresh(NOW).unwrap();
+
+        fixture.trusted.verify_target("index.bin", b"index").unwrap();
+        let message = violation(fixture.trusted.verify_target("index.bin", b"tampered"));
+        assert!(message.contains("does not match the targets metadata"), "{}", message);
+        let message = violation(fixture.trusted.verify_target("other.bin", b"index"));
+        assert!(message.contains("is not listed"), "{}", message);
+
+        // A mirror that stops refreshing is caught once the timestamp lapses.
+        let message = violation(fixture.trusted.check_fresh(NOW + 61));
+        assert!(message.contains("timestamp metadata version 1 expired"), "{}", message);
+    }
+
+    #[test]
+    fn refuses_to_roll_back_online_roles() {
+        let (k1, online) = (key(1), key(9));
+        let mut fixture = Fixture::new("rollback");
+        fixture.trust(&k1);
+        fixture.update_root(&encode(root(1, &[&k1], 1, &online), &[&k1])).unwrap();
+        let (old_timestamp, _, _) = publish(&online, 1, &[]);
+        let (timestamp, snapshot, targets) = publish(&online, 2, &[]);
+        fixture.trusted.update_timestamp(&timestamp, NOW).unwrap();
+        fixture.trusted.update_snapshot(&snapshot, NOW).unwrap();
+        fixture.trusted.update_targets(&targets, NOW).unwrap();
+
+        let message = violation(fixture.trusted.update_timestamp(&old_timestamp, NOW));
+        assert!(message.contains("timestamp metadata version 1 is older than trusted version 2"), "{}", message);
+
+        // A fresh timestamp that points back at an older snapshot is refused too.
+        let (_, old_snapshot, _) = publish(&online, 1, &[]);
+        let stale = Timestamp { version: 3, expires: NOW + 60, snapshot: meta(1, &old_snapshot) };
+        let stale = encode(stale, &[&online]);
+        let message = violation(fixture.trusted.update_timestamp(&stale, NOW));
+        assert!(message.contains("points at snapshot version 1 but version 2 is trusted"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_files_that_do_not_match_their_parent_role() {
+        let (k1, online) = (key(1), key(9));
+        let mut fixture = Fixture::new("mismatch");
+        fixture.trust(&k1);
+        fixture.update_root(&encode(root(1, &[&k1], 1, &online), &[&k1])).unwrap();
+        let (timestamp, _, _) = publish(&online, 1, &[]);
+        fixture.trusted.update_timestamp(&timestamp, NOW).unwrap();
+
+        let (_, other_snapshot, _) = publish(&online, 1, &[("extra.bin", b"x")]);
+        let message = violation(fixture.trusted.update_snapshot(&other_snapshot, NOW));
+        assert!(message.contains("snapshot.toml does not match the hash recorded for it"), "{}", message);
+
+        // Signed by a key the root does not assign to the role.
+        let forged = encode(Snapshot { version: 1, expires: NOW + 600, targets: meta(1, b"") }, &[&k1]);
+        let timestamp = Timestamp { version: 2, expires: NOW + 60, snapshot: meta(1, &forged) };
+        let timestamp = encode(timestamp, &[&online]);
+        fixture.trusted.update_timestamp(&timestamp, NOW).unwrap();
+        let message = violation(fixture.trusted.update_snapshot(&forged, NOW));
+        assert!(message.contains("snapshot metadata has 0 valid signature(s)"), "{}", message);
+    }
+
+    #[test]
+    fn rotating_online_keys_drops_what_they_signed() {
+        let (k1, online, replacement) = (key(1), key(9), key(8));
+        let mut fixture = Fixture::new("rotate-online");
+        fixture.trust(&k1);
+        fixture.update_root(&encode(root(1, &[&k1], 1, &online), &[&k1])).unwrap();
+        let (timestamp, snapshot, targets) = publish(&online, 1, &[]);
+        fixture.trusted.update_timestamp(&timestamp, NOW).unwrap();
+        fixture.trusted.update_snapshot(&snapshot, NOW).unwrap();
+        fixture.trusted.update_targets(&targets, NOW).unwrap();
+
+        fixture.update_root(&encode(root(2, &[&k1], 1, &replacement), &[&k1])).unwrap();
+        assert!(fixture.trusted.timestamp().is_none());
+        assert!(fixture.trusted.targets().is_none());
+        assert!(!fixture.dir.join("metadata").join(TIMESTAMP_FILE).exists());
+        let message = violation(fixture.trusted.update_timestamp(&timestamp, NOW));
+        assert!(message.contains("0 valid signature(s)"), "{}", message);
+    }
+}
@@ -1,11 +1,25 @@
 use crate::{
-    Config, Package, Channel, Architecture, RepositoryManager, Repository,
-    Result, PpmError, compute_checksum,
+    Config, Channel, Architecture, RepositoryManager, Repository, Dependency, Resolver, Package,
+    Version, VersionReq, InstalledDatabase, InstalledPackage, InstallReason, Transaction,
+    Verification, Event, Events, Result, PpmError,
 };
-use std::path::Path;
-use tokio::fs;
+use crate::cache::{checksum_file, CacheStats, PackageCache};
+use crate::database::{InstalledFile, Query};
+use crate::builder::{BuiltPackage, PackageBuilder};
+use crate::generator::RepositoryGenerator;
+use crate::keyring::{KeyRecord, Keyring};
+use crate::formats::plpm::PlpmScripts;
+use crate::formats::{PlpmPackage, PlpmReader};
+use crate::package::{PackageMetadata, SandboxConfig};
+use crate::sandbox::{Sandbox, ScriptRun};
+use crate::transaction::{self, Recovery};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-const DEFAULT_REPO_URL: &str = "https:
+const DEFAULT_REPO_URL: &str = "https://repo.plumos.org";
 
 pub async fn load_config() -> Result<Config> {
     let config_path = dirs::config_dir()
@@ -13,7 +27,7 @@ pub async fn load_config() -> Result<Config> {
         .join("ppm/config.toml");
 
     if config_path.exists() {
-        let contents = fs::read_to_string(&config_path).await?;
+        let contents = fs::read_to_string(&config_path)?;
         let config: Config = toml::from_str(&contents)
             .map_err(|e| PpmError::Serialization(e.to_string()))?;
         Ok(config)
@@ -28,101 +42,1463 @@ pub async fn save_config(config: &Config) -> Result<()> {
     let config_dir = dirs::config_dir()
         .unwrap_or_else(|| Path::new("/etc").to_path_buf())
         .join("ppm");
-    fs::create_dir_all(&config_dir).await?;
+    fs::create_dir_all(&config_dir)?;
     let config_path = config_dir.join("config.toml");
     let contents = toml::to_string_pretty(config)
         .map_err(|e| PpmError::Serialization(e.to_string()))?;
-    fs::write(&config_path, contents).await?;
+    fs::write(&config_path, contents)?;
     Ok(())
 }
 
-async fn get_repo_manager(config: &Config) -> Result<RepositoryManager> {
+#[derive(Debug, Clone, Serialize)]
+pub struct InstalledSummary {
+    pub name: String,
+    pub version: Version,
+    pub architecture: Architecture,
+    pub channel: Channel,
+    pub reason: InstallReason,
+    pub repository: Option<String>,
+    pub files: usize,
+    pub signed_by: Option<String>,
+    pub features: Vec<String>,
+    pub replaced: Vec<String>,
+    pub scripts: Vec<ScriptRun>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InstallReport {
+    pub installed: Vec<InstalledSummary>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RemovedSummary {
+    pub name: String,
+    pub version: Version,
+    pub files: usize,
+    pub scripts: Vec<ScriptRun>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RemoveReport {
+    pub removed: Vec<RemovedSummary>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Ownership {
+    pub path: String,
+    // Set when `path` is a directory and `owners` have files below it.
+    pub directory: bool,
+    pub owners: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageFiles {
+    pub name: String,
+    pub version: Version,
+    pub files: Vec<InstalledFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResults {
+    pub query: String,
+    pub channel: Channel,
+    pub matches: Vec<Package>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageDetails {
+    pub package: Package,
+    pub installed: Option<Version>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Upgrade {
+    pub name: String,
+    pub architecture: Architecture,
+    pub installed: Version,
+    pub available: Version,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpgradePlan {
+    pub channel: Channel,
+    pub upgrades: Vec<Upgrade>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CleanReport {
+    pub all: bool,
+    pub removed: usize,
+    pub freed: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexSummary {
+    pub channel: Channel,
+    pub architecture: Architecture,
+    pub packages: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RepositoryBuild {
+    pub scanned: usize,
+    pub reused: usize,
+    pub indexes: Vec<IndexSummary>,
+    pub written: Vec<PathBuf>,
+}
+
+fn open_database(config: &Config, warnings: &mut Vec<String>) -> Result<InstalledDatabase> {
+    let mut db = InstalledDatabase::open(&config.state_dir)?;
+    match transaction::recover(&config.state_dir, &mut db)? {
+        Recovery::RolledBack => warnings.push("Rolled back an interrupted transaction".to_string()),
+        Recovery::RolledForward => warnings.push("Completed an interrupted transaction".to_string()),
+        Recovery::Clean => {}
+    }
+    Ok(db)
+}
+
+fn build_repo_manager(
+    config: &Config,
+    channel: Channel,
+    architecture: Architecture,
+    events: &Events,
+    warnings: &mut Vec<String>,
+) -> RepositoryManager {
     let mut manager = RepositoryManager::new();
-    let architectures = vec![config.architecture];
-    let repo = Repository::new(
-        DEFAULT_REPO_URL.to_string(),
-        "main".to_string(),
-        config.channel,
-        architectures,
-    );
-    manager.add_repository(repo);
+    for rc in config.enabled_repositories() {
+        let url = if rc.url.is_empty() { DEFAULT_REPO_URL.to_string() } else { rc.url };
+        let architectures = if rc.architectures.is_empty() {
+            vec![architecture]
+        } else {
+            rc.architectures
+        };
+        let mut repo = Repository::new(url, rc.name, rc.channel.unwrap_or(channel), architectures)
+            .with_priority(rc.priority)
+            .with_cache_dir(&config.cache_dir)
+            .with_network(config.network.clone())
+            .with_events(events.clone());
+        if rc.signed_metadata {
+            repo = repo.with_signed_metadata(&config.state_dir, &config.keyring_dir);
+        }
+        manager.add_repository(repo);
+    }
+    for pin in &config.pins {
+        if !manager.get_repositories().iter().any(|r| r.name == pin.repository) {
+            warnings.push(format!(
+                "Pin for '{}' names repository '{}', which is not enabled",
+                pin.package, pin.repository
+            ));
+        }
+    }
+    manager.set_pins(config.pins.clone());
+    manager
+}
+
+async fn get_repo_manager(
+    config: &Config,
+    channel: Channel,
+    architecture: Architecture,
+    events: &Events,
+    warnings: &mut Vec<String>,
+) -> Result<RepositoryManager> {
+    let mut manager = build_repo_manager(config, channel, architecture, events, warnings);
+    for repo in manager.get_repositories_mut() {
+        if let Err(e) = repo.refresh().await {
+            // Never paper over a failed metadata check with an older index;
+            // a cached signed index is refused once its metadata expires.
+            if matches!(e, PpmError::SecurityViolation(_)) || !repo.load_cached()? {
+                return Err(e);
+            }
+            warnings.push(format!("{}; using cached index for {}", e, repo.name));
+        }
+    }
     Ok(manager)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn install_package(
     package_name: &str,
-    version: Option<&str>,
+    version: Option<&VersionReq>,
     channel: Option<Channel>,
     arch: Option<Architecture>,
-    _deps: bool,
-    _sandbox: bool,
-    _force: bool,
+    features: &[String],
+    deps: bool,
+    recommends: bool,
+    sandbox: bool,
+    force: bool,
     config: &Config,
-) -> Result<()> {
+    events: &Events,
+) -> Result<InstallReport> {
     let ch = channel.unwrap_or(config.channel);
     let arch = arch.unwrap_or(config.architecture);
-    let manager = get_repo_manager(config).await?;
-    if let Some(pkg) = manager.find_package_across_repos(package_name, version)? {
-        if pkg.channel == ch && pkg.architecture == arch {
-            println!("📥 Installing {}-{} ({})", pkg.name, pkg.version, pkg.file);
-            Ok(())
-        } else {
-            Err(PpmError::PackageNotFound(format!(
-                "No {} package for {} in {} channel", package_name, arch.as_str(), ch.name()
-            )))
+    let mut report = InstallReport::default();
+    let manager = get_repo_manager(config, ch, arch, events, &mut report.warnings).await?;
+    // The lock is held from resolving to commit, so the plan cannot go stale
+    // under another ppm process.
+    let mut db = open_database(config, &mut report.warnings)?;
+
+    events.emit(Event::ResolveStarted { requested: vec![package_name.to_string()] });
+    let mut plan: Vec<(Package, InstallReason, Vec<String>)> = if deps {
+        let request = match version {
+            Some(req) => Dependency::new(package_name, req.clone()),
+            None => Dependency::any(package_name),
         }
+        .with_features(features);
+        let indexes = manager.fetch_indexes().await?;
+        let resolution = Resolver::new(&indexes, arch, ch)
+            .with_installed(db.packages())
+            .with_recommends(recommends)
+            .resolve(std::slice::from_ref(&request))?;
+        // The request may name a virtual package, in which case whichever
+        // provider was picked is the explicit install.
+        let explicit = resolution
+            .get(package_name)
+            .or_else(|| resolution.packages.iter().find(|p| p.relations().satisfies(&request)))
+            .map(|p| p.name.clone());
+        resolution
+            .packages
+            .iter()
+            .map(|pkg| {
+                let reason = if explicit.as_ref() == Some(&pkg.name) {
+                    InstallReason::Explicit
+                } else {
+                    InstallReason::Dependency
+                };
+                (pkg.clone(), reason, resolution.features(&pkg.name).to_vec())
+            })
+            .collect()
     } else {
-        Err(PpmError::PackageNotFound(package_name.to_string()))
+        match manager.find_package_across_repos(package_name, version)? {
+            Some(pkg) => vec![(pkg, InstallReason::Explicit, features.to_vec())],
+            None => {
+                return Err(PpmError::PackageNotFound(format!(
+                    "No {} package for {} in {} channel", package_name, arch.as_str(), ch.name()
+                )))
+            }
+        }
+    };
+    // Nothing is reinstalled; asking for an installed dependency by name
+    // only marks it explicit.
+    for (pkg, reason, features) in std::mem::take(&mut plan) {
+        if !already_installed(&db, &pkg, &features) {
+            plan.push((pkg, reason, features));
+            continue;
+        }
+        if reason == InstallReason::Explicit {
+            report.warnings.push(format!("{}-{} is already installed", pkg.name, pkg.version));
+            if db.get(&pkg.name).is_some_and(|p| p.reason == InstallReason::Dependency) {
+                db.set_reason(&pkg.name, InstallReason::Explicit)?;
+            }
+        }
     }
+    events.emit(Event::ResolveFinished {
+        packages: plan.iter().map(|(pkg, _, _)| format!("{}-{}", pkg.name, pkg.version)).collect(),
+    });
+
+    let packages: Vec<Package> = plan.iter().map(|(pkg, _, _)| pkg.clone()).collect();
+    let fetched = manager.fetch_packages(&packages, config.network.parallel_downloads).await?;
+    let files = plan
+        .into_iter()
+        .zip(fetched)
+        .map(|((pkg, reason, features), (repo, path))| PlannedFile {
+            path,
+            reason,
+            repository: Some(repo.name.clone()),
+            archive: Some(pkg.checksum),
+            features,
+        })
+        .collect();
+    // The resolver already weighed conflicts against the installed set,
+    // including packages later in the plan that upgrade or replace them.
+    report.installed = install_files(files, db, !deps, sandbox, force, config, events, &mut report.warnings)?;
+    evict_cache(config, &mut report.warnings);
+    Ok(report)
+}
+
+// The resolver keeps installed versions that still fit, so its plan can name
+// packages that are already there exactly as asked for.
+fn already_installed(db: &InstalledDatabase, pkg: &Package, features: &[String]) -> bool {
+    db.get(&pkg.name).is_some_and(|installed| {
+        installed.version == pkg.version
+            && installed.architecture == pkg.architecture
+            && features.iter().all(|f| installed.features.contains(f))
+    })
+}
+
+// Package scripts only ever run inside the sandbox. Without `sandbox` they
+// are skipped with a warning rather than executed on the host.
+struct Hooks<'a> {
+    name: &'a str,
+    version: &'a Version,
+    architecture: Architecture,
+    scripts: Option<&'a PlpmScripts>,
+    sandbox: Option<&'a SandboxConfig>,
+    enabled: bool,
 }
 
-pub async fn remove_package(package_name: &str, _force: bool, _config: &Config) -> Result<()> {
-    println!("🗑️ Removing package: {}", package_name);
+impl<'a> Hooks<'a> {
+    fn installed(pkg: &'a InstalledPackage, enabled: bool) -> Self {
+        Hooks {
+            name: &pkg.name,
+            version: &pkg.version,
+            architecture: pkg.architecture,
+            scripts: pkg.scripts.as_ref(),
+            sandbox: pkg.sandbox.as_ref(),
+            enabled,
+        }
+    }
+
+    fn run(
+        &self,
+        hook: &str,
+        config: &Config,
+        events: &Events,
+        runs: &mut Vec<ScriptRun>,
+        warnings: &mut Vec<String>,
+    ) -> Result<()> {
+        let script = self.scripts.and_then(|scripts| match hook {
+            "pre-install" => scripts.pre_install.as_ref(),
+            "post-install" => scripts.post_install.as_ref(),
+            "pre-remove" => scripts.pre_remove.as_ref(),
+            "post-remove" => scripts.post_remove.as_ref(),
+            _ => None,
+        });
+        let Some(script) = script else {
+            return Ok(());
+        };
+        let package = format!("{}-{}", self.name, self.version);
+        // Packages for another architecture are being laid out in a target
+        // root; their scripts have to run on that system, not this one.
+        if self.architecture != Architecture::current() {
+            warnings.push(format!(
+                "Skipped the {} script of {}: {} scripts cannot run on this {} host",
+                hook,
+                package,
+                self.architecture.as_str(),
+                Architecture::current().as_str()
+            ));
+            return Ok(());
+        }
+        if !self.enabled {
+            warnings.push(format!("Skipped the {} script of {}; pass --sandbox to run it", hook, package));
+            return Ok(());
+        }
+
+        events.emit(Event::ScriptStarted { package: package.clone(), script: hook.to_string() });
+        let mut sandbox = Sandbox::new(
+            self.sandbox.cloned().unwrap_or_default(),
+            &config.root,
+            Path::new(&config.state_dir).join("sandbox"),
+        )
+        .with_env("PPM_PACKAGE", self.name)
+        .with_env("PPM_VERSION", &self.version.to_string());
+        if config.script_timeout_secs > 0 {
+            sandbox = sandbox.with_timeout(Duration::from_secs(config.script_timeout_secs));
+        }
+        let result = sandbox.run(hook, script);
+        // Pre-hooks run before anything is touched, so their failure can
+        // still stop the operation; post-hooks can only be reported.
+        let pre = hook.starts_with("pre-");
+        let run = match result {
+            Ok(run) => run,
+            Err(e) if !pre => {
+                warnings.push(format!("the {} script of {} did not run: {}", hook, package, e));
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        events.emit(Event::ScriptFinished {
+            package: package.clone(),
+            script: hook.to_string(),
+            status: run.status,
+        });
+
+        let status = run.status;
+        let stderr = run.stderr.trim().to_string();
+        runs.push(run);
+        if status != 0 {
+            let mut message = format!("the {} script of {} exited with status {}", hook, package, status);
+            if !stderr.is_empty() {
+                message = format!("{}: {}", message, stderr);
+            }
+            if pre {
+                return Err(PpmError::Script(message));
+            }
+            warnings.push(message);
+        }
+        Ok(())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn check_signature(
+    name: &str,
+    version: &Version,
+    digest: &[u8; 32],
+    signature: Option<&str>,
+    channel: Channel,
+    repository: Option<&str>,
+    config: &Config,
+    events: &Events,
+    warnings: &mut Vec<String>,
+) -> Result<Option<String>> {
+    let keyring = Keyring::open(&config.keyring_dir)?;
+    let signed_by = match keyring.verify(digest, signature, config.signature_policy, channel, repository)? {
+        Verification::Signed { key_id } => Some(key_id),
+        Verification::Unsigned => {
+            warnings.push(format!("{} is unsigned; installed because policy allows it", name));
+            None
+        }
+    };
+    events.emit(Event::Verified {
+        package: format!("{}-{}", name, version),
+        signed_by: signed_by.clone(),
+    });
+    Ok(signed_by)
+}
+
+pub async fn install_from_plpm(
+    package: &PlpmPackage,
+    reason: InstallReason,
+    sandbox: bool,
+    force: bool,
+    config: &Config,
+    events: &Events,
+) -> Result<InstallReport> {
+    let mut report = InstallReport::default();
+    let mut db = open_database(config, &mut report.warnings)?;
+    let signed_by = check_signature(
+        &package.metadata.name,
+        &package.metadata.version,
+        &package.digest()?,
+        package.signature.as_deref(),
+        package.header.channel,
+        None,
+        config,
+        events,
+        &mut report.warnings,
+    )?;
+    let hooks = Hooks {
+        name: &package.metadata.name,
+        version: &package.metadata.version,
+        architecture: package.header.architecture,
+        scripts: package.scripts.as_ref(),
+        sandbox: package.metadata.sandbox_config.as_ref(),
+        enabled: sandbox,
+    };
+    let replaced = replaced_packages(&package.metadata, &db, true)?;
+    let mut scripts = Vec::new();
+    hooks.run("pre-install", config, events, &mut scripts, &mut report.warnings)?;
+    replaced_hooks("pre-remove", &replaced, sandbox, config, events, &mut scripts, &mut report.warnings)?;
+    let mut tx = Transaction::begin(&config.state_dir, &config.root)?
+        .with_events(events.clone())
+        .with_overwrite(force);
+    for pkg in &replaced {
+        tx.remove(pkg)?;
+    }
+    tx.install(package, reason, &db)?;
+    tx.commit(&mut db)?;
+    drop(db);
+    replaced_hooks("post-remove", &replaced, sandbox, config, events, &mut scripts, &mut report.warnings)?;
+    hooks.run("post-install", config, events, &mut scripts, &mut report.warnings)?;
+    report.installed.push(InstalledSummary {
+        name: package.metadata.name.clone(),
+        version: package.metadata.version.clone(),
+        architecture: package.header.architecture,
+        channel: package.header.channel,
+        reason,
+        repository: None,
+        files: package.files.len(),
+        signed_by,
+        features: Vec::new(),
+        replaced: replaced.into_iter().map(|p| p.name).collect(),
+        scripts,
+    });
+    Ok(report)
+}
+
+pub async fn install_from_file(
+    path: &Path,
+    reason: InstallReason,
+    sandbox: bool,
+    force: bool,
+    config: &Config,
+    events: &Events,
+) -> Result<InstallReport> {
+    let mut report = InstallReport::default();
+    let file = PlannedFile {
+        path: path.to_path_buf(),
+        reason,
+        repository: None,
+        archive: None,
+        features: Vec::new(),
+    };
+    let db = open_database(config, &mut report.warnings)?;
+    report.installed = install_files(vec![file], db, true, sandbox, force, config, events, &mut report.warnings)?;
+    Ok(report)
+}
+
+// One `.plpm` of an install plan, with what the caller already knows
+// about it.
+struct PlannedFile {
+    path: PathBuf,
+    reason: InstallReason,
+    repository: Option<String>,
+    archive: Option<String>,
+    features: Vec<String>,
+}
+
+struct PreparedFile {
+    reader: PlpmReader<std::io::BufReader<fs::File>>,
+    summary: InstalledSummary,
+    archive: String,
+    feature_deps: Vec<Dependency>,
+    scripts: Option<PlpmScripts>,
+    sandbox: Option<SandboxConfig>,
+    replaced: Vec<InstalledPackage>,
+}
+
+impl PreparedFile {
+    fn hooks(&self, enabled: bool) -> Hooks<'_> {
+        Hooks {
+            name: &self.summary.name,
+            version: &self.summary.version,
+            architecture: self.summary.architecture,
+            scripts: self.scripts.as_ref(),
+            sandbox: self.sandbox.as_ref(),
+            enabled,
+        }
+    }
+}
+
+// Every package of the plan is checked before anything is touched and then
+// staged into a single transaction, so a failure part way through leaves
+// none of the plan installed. `db` is the locked database the plan was
+// made against; it is released once the transaction commits.
+#[allow(clippy::too_many_arguments)]
+fn install_files(
+    plan: Vec<PlannedFile>,
+    mut db: InstalledDatabase,
+    check_conflicts: bool,
+    sandbox: bool,
+    force: bool,
+    config: &Config,
+    events: &Events,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<InstalledSummary>> {
+    let mut prepared = Vec::with_capacity(plan.len());
+    let mut replacing = HashSet::new();
+    for planned in plan {
+        let archive = match planned.archive {
+            Some(checksum) => checksum,
+            None => checksum_file(&planned.path)?,
+        };
+        let file = fs::File::open(&planned.path)?;
+        let reader = PlpmReader::new(std::io::BufReader::new(file))?;
+        let signed_by = check_signature(
+            &reader.metadata().name,
+            &reader.metadata().version,
+            &reader.digest(),
+            reader.signature(),
+            reader.header().channel,
+            planned.repository.as_deref(),
+            config,
+            events,
+            warnings,
+        )?;
+        let metadata = reader.metadata();
+        let mut feature_deps = Vec::new();
+        for feature in &planned.features {
+            let deps = metadata.features.get(feature).ok_or_else(|| {
+                PpmError::DependencyResolution(format!(
+                    "{} {} has no feature '{}'",
+                    metadata.name, metadata.version, feature
+                ))
+            })?;
+            feature_deps.extend(deps.iter().cloned());
+        }
+        let replaced: Vec<InstalledPackage> = replaced_packages(metadata, &db, check_conflicts)?
+            .into_iter()
+            .filter(|p| replacing.insert(p.name.clone()))
+            .collect();
+        prepared.push(PreparedFile {
+            summary: InstalledSummary {
+                name: metadata.name.clone(),
+                version: metadata.version.clone(),
+                architecture: reader.header().architecture,
+                channel: reader.header().channel,
+                reason: planned.reason,
+                repository: planned.repository,
+                files: reader.entries().len(),
+                signed_by,
+                features: planned.features,
+                replaced: Vec::new(),
+                scripts: Vec::new(),
+            },
+            archive,
+            feature_deps,
+            scripts: reader.scripts().cloned(),
+            sandbox: metadata.sandbox_config.clone(),
+            replaced,
+            reader,
+        });
+    }
+    // A package that is part of the plan is upgraded, not replaced.
+    let planned: HashSet<String> = prepared.iter().map(|p| p.summary.name.clone()).collect();
+    for item in &mut prepared {
+        item.replaced.retain(|p| !planned.contains(&p.name));
+    }
+
+    let mut runs: Vec<Vec<ScriptRun>> = Vec::with_capacity(prepared.len());
+    for item in &prepared {
+        let mut scripts = Vec::new();
+        item.hooks(sandbox).run("pre-install", config, events, &mut scripts, warnings)?;
+        runs.push(scripts);
+    }
+    for (item, scripts) in prepared.iter().zip(&mut runs) {
+        replaced_hooks("pre-remove", &item.replaced, sandbox, config, events, scripts, warnings)?;
+    }
+    let mut tx = Transaction::begin(&config.state_dir, &config.root)?
+        .with_events(events.clone())
+        .with_overwrite(force);
+    for item in &mut prepared {
+        for pkg in &item.replaced {
+            tx.remove(pkg)?;
+        }
+        tx.install_streaming(&mut item.reader, item.summary.reason, &db)?;
+        tx.record_archive(&item.summary.name, &item.archive)?;
+        if !item.summary.features.is_empty() {
+            tx.record_features(&item.summary.name, &item.summary.features, &item.feature_deps)?;
+        }
+    }
+    tx.commit(&mut db)?;
+    drop(db);
+
+    let mut installed = Vec::with_capacity(prepared.len());
+    for (item, mut scripts) in prepared.into_iter().zip(runs) {
+        replaced_hooks("post-remove", &item.replaced, sandbox, config, events, &mut scripts, warnings)?;
+        item.hooks(sandbox).run("post-install", config, events, &mut scripts, warnings)?;
+        let mut summary = item.summary;
+        summary.scripts = scripts;
+        summary.replaced = item.replaced.into_iter().map(|p| p.name).collect();
+        installed.push(summary);
+    }
+    Ok(installed)
+}
+
+// Installed packages that the incoming one takes over from. Unless the
+// caller has already resolved conflicts, any other clash with the installed
+// set stops the install.
+fn replaced_packages(
+    metadata: &PackageMetadata,
+    db: &InstalledDatabase,
+    check_conflicts: bool,
+) -> Result<Vec<InstalledPackage>> {
+    let incoming = metadata.relations();
+    let mut replaced = Vec::new();
+    for installed in db.packages().filter(|p| p.name != metadata.name) {
+        if incoming.replaces(&installed.relations()) {
+            replaced.push(installed.clone());
+        } else if check_conflicts && incoming.conflicts_with(&installed.relations()) {
+            return Err(PpmError::DependencyResolution(format!(
+                "{} {} conflicts with installed {} {}",
+                metadata.name, metadata.version, installed.name, installed.version
+            )));
+        }
+    }
+    Ok(replaced)
+}
+
+// Replaced packages are removed in the same transaction as the install that
+// takes over from them; only their remove hooks run around it.
+fn replaced_hooks(
+    hook: &str,
+    replaced: &[InstalledPackage],
+    sandbox: bool,
+    config: &Config,
+    events: &Events,
+    runs: &mut Vec<ScriptRun>,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    for pkg in replaced {
+        Hooks::installed(pkg, sandbox).run(hook, config, events, runs, warnings)?;
+    }
     Ok(())
 }
 
+// The whole plan goes in one transaction, so a failure leaves every
+// package of it installed.
+fn remove_installed(
+    plan: &[InstalledPackage],
+    mut db: InstalledDatabase,
+    sandbox: bool,
+    config: &Config,
+    events: &Events,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<RemovedSummary>> {
+    let mut runs: Vec<Vec<ScriptRun>> = Vec::with_capacity(plan.len());
+    for pkg in plan {
+        let mut scripts = Vec::new();
+        Hooks::installed(pkg, sandbox).run("pre-remove", config, events, &mut scripts, warnings)?;
+        runs.push(scripts);
+    }
+    let mut tx = Transaction::begin(&config.state_dir, &config.root)?.with_events(events.clone());
+    for pkg in plan {
+        tx.remove(pkg)?;
+    }
+    tx.commit(&mut db)?;
+    drop(db);
+
+    let mut removed = Vec::with_capacity(plan.len());
+    for (pkg, mut scripts) in plan.iter().zip(runs) {
+        Hooks::installed(pkg, sandbox).run("post-remove", config, events, &mut scripts, warnings)?;
+        removed.push(RemovedSummary {
+            name: pkg.name.clone(),
+            version: pkg.version.clone(),
+            files: pkg.files.len(),
+            scripts,
+        });
+    }
+    Ok(removed)
+}
+
+// Dependents come before the packages they depend on, so remove hooks
+// still find their dependencies in place.
+fn removal_order(packages: Vec<InstalledPackage>) -> Vec<InstalledPackage> {
+    fn visit(i: usize, packages: &[InstalledPackage], visited: &mut Vec<bool>, order: &mut Vec<usize>) {
+        if std::mem::replace(&mut visited[i], true) {
+            return;
+        }
+        let relations = packages[i].relations();
+        for (j, pkg) in packages.iter().enumerate() {
+            if j != i && pkg.dependencies.iter().chain(&pkg.recommends).any(|dep| relations.satisfies(dep)) {
+                visit(j, packages, visited, order);
+            }
+        }
+        order.push(i);
+    }
+
+    let mut visited = vec![false; packages.len()];
+    let mut order = Vec::with_capacity(packages.len());
+    for i in 0..packages.len() {
+        visit(i, &packages, &mut visited, &mut order);
+    }
+    let mut slots: Vec<Option<InstalledPackage>> = packages.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| slots[i].take()).collect()
+}
+
+// With `cascade`, dependencies that only the removed package kept around
+// go with it; packages that were already orphaned are left to `autoremove`.
+pub async fn remove_package(
+    package_name: &str,
+    cascade: bool,
+    sandbox: bool,
+    force: bool,
+    config: &Config,
+    events: &Events,
+) -> Result<RemoveReport> {
+    let mut report = RemoveReport::default();
+    let db = open_database(config, &mut report.warnings)?;
+    let pkg = db
+        .get(package_name)
+        .cloned()
+        .ok_or_else(|| PpmError::PackageNotFound(package_name.to_string()))?;
+
+    let dependents: Vec<String> = db
+        .required_by(package_name)
+        .iter()
+        .map(|p| format!("{}-{}", p.name, p.version))
+        .collect();
+    if !dependents.is_empty() {
+        if !force {
+            return Err(PpmError::DependencyResolution(format!(
+                "{} is required by {}; pass --force to remove it anyway",
+                package_name,
+                dependents.join(", ")
+            )));
+        }
+        report.warnings.push(format!(
+            "Removed {} although it is still required by {}",
+            package_name,
+            dependents.join(", ")
+        ));
+    }
+
+    let mut plan = vec![pkg];
+    if cascade {
+        let before: HashSet<&str> = db.orphans(&[]).iter().map(|p| p.name.as_str()).collect();
+        let orphaned = db
+            .orphans(&[package_name])
+            .into_iter()
+            .filter(|p| !before.contains(p.name.as_str()))
+            .cloned()
+            .collect();
+        plan.extend(removal_order(orphaned));
+    }
+    report.removed = remove_installed(&plan, db, sandbox, config, events, &mut report.warnings)?;
+    Ok(report)
+}
+
+// Removes every package that was installed as a dependency and that no
+// explicitly installed package needs any more.
+pub async fn autoremove(sandbox: bool, config: &Config, events: &Events) -> Result<RemoveReport> {
+    let mut report = RemoveReport::default();
+    let db = open_database(config, &mut report.warnings)?;
+    let orphans = removal_order(db.orphans(&[]).into_iter().cloned().collect());
+    if !orphans.is_empty() {
+        report.removed = remove_installed(&orphans, db, sandbox, config, events, &mut report.warnings)?;
+    }
+    Ok(report)
+}
+
+fn plan_upgrades(db: &InstalledDatabase, indexes: &[crate::PackageIndex], channel: Channel) -> Vec<(Upgrade, Package)> {
+    let mut resolvers = std::collections::HashMap::new();
+    let mut upgrades = Vec::new();
+    for installed in db.packages().filter(|p| p.channel == channel) {
+        let resolver = resolvers
+            .entry(installed.architecture)
+            .or_insert_with(|| Resolver::new(indexes, installed.architecture, channel));
+        if let Some(latest) = resolver.candidates(&installed.name).first() {
+            if latest.version > installed.version {
+                let upgrade = Upgrade {
+                    name: installed.name.clone(),
+                    architecture: installed.architecture,
+                    installed: installed.version.clone(),
+                    available: latest.version.clone(),
+                };
+                upgrades.push((upgrade, (*latest).clone()));
+            }
+        }
+    }
+    upgrades
+}
+
 pub async fn update_packages(
     package_name: Option<&str>,
     channel: Option<Channel>,
+    sandbox: bool,
+    force: bool,
     config: &Config,
-) -> Result<()> {
+    events: &Events,
+) -> Result<InstallReport> {
     let ch = channel.unwrap_or(config.channel);
-    println!("🔄 Updating packages in {} channel...", ch.name());
+    let mut report = InstallReport::default();
+    // Held until the upgrades are committed, like on install.
+    let db = open_database(config, &mut report.warnings)?;
     if let Some(name) = package_name {
-        println!(" Target: {}", name);
+        if db.get(name).is_none() {
+            return Err(PpmError::PackageNotFound(name.to_string()));
+        }
     }
-    Ok(())
+    let manager = get_repo_manager(config, ch, config.architecture, events, &mut report.warnings).await?;
+    let indexes = manager.fetch_indexes().await?;
+    events.emit(Event::ResolveStarted {
+        requested: match package_name {
+            Some(name) => vec![name.to_string()],
+            None => db.packages().filter(|p| p.channel == ch).map(|p| p.name.clone()).collect(),
+        },
+    });
+    let upgrades: Vec<_> = plan_upgrades(&db, &indexes, ch)
+        .into_iter()
+        .filter(|(upgrade, _)| package_name.is_none_or(|name| name == upgrade.name))
+        .collect();
+    // Features stay enabled across an upgrade as long as the new version
+    // still offers them.
+    let kept_features = |pkg: &Package| -> Vec<String> {
+        db.get(&pkg.name)
+            .map(|installed| installed.features.iter().filter(|f| pkg.features.contains_key(*f)).cloned().collect())
+            .unwrap_or_default()
+    };
+    let mut architectures = Vec::new();
+    for (upgrade, _) in &upgrades {
+        if !architectures.contains(&upgrade.architecture) {
+            architectures.push(upgrade.architecture);
+        }
+    }
+    // The new versions are resolved like an install, so dependencies they add
+    // or tighten come along. Recommendations are left out: an upgrade never
+    // brings back one that was removed.
+    let mut plan: Vec<(Package, Vec<String>)> = Vec::new();
+    for arch in architectures {
+        let requests: Vec<Dependency> = upgrades
+            .iter()
+            .filter(|(upgrade, _)| upgrade.architecture == arch)
+            .map(|(_, pkg)| {
+                Dependency::new(&pkg.name, VersionReq::exact(&pkg.version)).with_features(&kept_features(pkg))
+            })
+            .collect();
+        let resolution = Resolver::new(&indexes, arch, ch)
+            .with_installed(db.packages())
+            .with_recommends(false)
+            .resolve(&requests)?;
+        for pkg in &resolution.packages {
+            let wanted = resolution.features(&pkg.name);
+            if already_installed(&db, pkg, wanted) {
+                continue;
+            }
+            let mut features = wanted.to_vec();
+            for feature in kept_features(pkg) {
+                if !features.contains(&feature) {
+                    features.push(feature);
+                }
+            }
+            plan.push((pkg.clone(), features));
+        }
+    }
+    events.emit(Event::ResolveFinished {
+        packages: plan.iter().map(|(pkg, _)| format!("{}-{}", pkg.name, pkg.version)).collect(),
+    });
+
+    let (packages, features): (Vec<Package>, Vec<Vec<String>>) = plan.into_iter().unzip();
+    let fetched = manager.fetch_packages(&packages, config.network.parallel_downloads).await?;
+    // The transaction keeps an explicit install explicit across upgrades.
+    let files = packages
+        .into_iter()
+        .zip(features)
+        .zip(fetched)
+        .map(|((pkg, features), (repo, path))| PlannedFile {
+            path,
+            reason: InstallReason::Dependency,
+            repository: Some(repo.name.clone()),
+            archive: Some(pkg.checksum),
+            features,
+        })
+        .collect();
+    // As on install, the resolver already weighed conflicts.
+    report.installed = install_files(files, db, false, sandbox, force, config, events, &mut report.warnings)?;
+    evict_cache(config, &mut report.warnings);
+    Ok(report)
 }
 
-pub async fn search_packages(query: &str, channel: Option<Channel>, config: &Config) -> Result<()> {
+pub async fn search_packages(query: &str, channel: Option<Channel>, config: &Config) -> Result<SearchResults> {
     let ch = channel.unwrap_or(config.channel);
-    println!("🔍 Searching for '{}' in {} channel...", query, ch.name());
-    Ok(())
+    let mut warnings = Vec::new();
+    let manager = get_repo_manager(config, ch, config.architecture, &Events::default(), &mut warnings).await?;
+    let needle = query.to_lowercase();
+    let mut found = std::collections::BTreeMap::new();
+    for index in manager.fetch_indexes().await? {
+        for pkg in index.packages {
+            let hit = pkg.name.to_lowercase().contains(&needle)
+                || pkg
+                    .description
+                    .as_ref()
+                    .is_some_and(|d| d.to_lowercase().contains(&needle));
+            if hit && pkg.architecture == config.architecture {
+                let entry = found.entry(pkg.name.clone()).or_insert_with(|| pkg.clone());
+                if pkg.version > entry.version {
+                    *entry = pkg;
+                }
+            }
+        }
+    }
+    Ok(SearchResults {
+        query: query.to_string(),
+        channel: ch,
+        matches: found.into_values().collect(),
+        warnings,
+    })
 }
 
-pub async fn show_package_info(package_name: &str, config: &Config) -> Result<()> {
-    println!("📄 Showing info for package: {}", package_name);
-    Ok(())
+pub async fn show_package_info(package_name: &str, config: &Config) -> Result<PackageDetails> {
+    let mut warnings = Vec::new();
+    let manager = get_repo_manager(config, config.channel, config.architecture, &Events::default(), &mut warnings).await?;
+    let package = manager
+        .find_package_across_repos(package_name, None)?
+        .ok_or_else(|| PpmError::PackageNotFound(package_name.to_string()))?;
+    let installed = InstalledDatabase::open_read_only(&config.state_dir)?
+        .get(package_name)
+        .map(|p| p.version.clone());
+    Ok(PackageDetails { package, installed, warnings })
 }
 
-pub async fn list_packages(channel: Option<Channel>, config: &Config) -> Result<()> {
+pub async fn list_packages(channel: Option<Channel>, config: &Config) -> Result<Vec<InstalledPackage>> {
     let ch = channel.unwrap_or(config.channel);
-    println!("📋 Listing packages in {} channel...", ch.name());
-    Ok(())
+    let db = InstalledDatabase::open_read_only(&config.state_dir)?;
+    let query = Query {
+        channel: Some(ch),
+        ..Query::default()
+    };
+    Ok(db.query(&query).cloned().collect())
+}
+
+// Accepts the path either as recorded in the database or as seen from the
+// host, i.e. prefixed with the install root.
+pub async fn find_owners(path: &Path, config: &Config) -> Result<Ownership> {
+    let absolute = std::path::absolute(path)?;
+    let relative = absolute.strip_prefix(&config.root).unwrap_or(&absolute);
+    let mut parts: Vec<&std::ffi::OsStr> = Vec::new();
+    for component in relative.components() {
+        match component {
+            std::path::Component::Normal(part) => parts.push(part),
+            std::path::Component::ParentDir => {
+                parts.pop();
+            }
+            _ => {}
+        }
+    }
+    let installed = Path::new("/").join(parts.iter().collect::<PathBuf>()).to_string_lossy().into_owned();
+
+    let db = InstalledDatabase::open_read_only(&config.state_dir)?;
+    let (directory, owners) = match db.owners(&installed) {
+        [] => (true, db.owners_under(&installed).into_iter().map(str::to_string).collect::<Vec<_>>()),
+        owners => (false, owners.to_vec()),
+    };
+    if owners.is_empty() {
+        return Err(PpmError::PackageNotFound(format!("No installed package owns {}", installed)));
+    }
+    Ok(Ownership { path: installed, directory, owners })
+}
+
+pub async fn list_files(package_name: &str, config: &Config) -> Result<PackageFiles> {
+    let db = InstalledDatabase::open_read_only(&config.state_dir)?;
+    let pkg = db
+        .get(package_name)
+        .ok_or_else(|| PpmError::PackageNotFound(package_name.to_string()))?;
+    Ok(PackageFiles {
+        name: pkg.name.clone(),
+        version: pkg.version.clone(),
+        files: pkg.files.clone(),
+    })
 }
 
-pub async fn check_updates(channel: Option<Channel>, config: &Config) -> Result<()> {
+pub async fn check_updates(channel: Option<Channel>, config: &Config) -> Result<UpgradePlan> {
     let ch = channel.unwrap_or(config.channel);
-    println!("✅ Checking for updates in {} channel...", ch.name());
-    Ok(())
+    let mut warnings = Vec::new();
+    let db = InstalledDatabase::open_read_only(&config.state_dir)?;
+    let manager = get_repo_manager(config, ch, config.architecture, &Events::default(), &mut warnings).await?;
+    let indexes = manager.fetch_indexes().await?;
+    Ok(UpgradePlan {
+        channel: ch,
+        upgrades: plan_upgrades(&db, &indexes, ch).into_iter().map(|(upgrade, _)| upgrade).collect(),
+        warnings,
+    })
 }
 
-pub async fn clean_cache(all: bool, config: &Config) -> Result<()> {
-    let cache_dir = &config.cache_dir;
-    if all {
-        println!("🧹 Cleaning entire cache at {}", cache_dir);
+// Without `all`, only cached packages that neither a cached repository index
+// nor the installed set refers to are dropped, followed by the configured
+// size and age eviction. Indexes are read from the cache, never refreshed.
+// With `all` every cached package goes, but the cached indexes stay so that
+// searches keep working offline.
+pub async fn clean_cache(all: bool, config: &Config) -> Result<CleanReport> {
+    let stats = if all {
+        PackageCache::open(&config.cache_dir).clear()?
     } else {
-        println!("🧹 Cleaning old cache entries...");
+        prune_cache(config)?
+    };
+    Ok(CleanReport { all, removed: stats.removed, freed: stats.freed })
+}
+
+fn prune_cache(config: &Config) -> Result<CacheStats> {
+    let mut referenced = HashSet::new();
+    let mut warnings = Vec::new();
+    let mut manager = build_repo_manager(config, config.channel, config.architecture, &Events::default(), &mut warnings);
+    for repo in manager.get_repositories_mut() {
+        // An index whose signed metadata has expired no longer keeps
+        // its packages alive.
+        match repo.load_cached() {
+            Ok(true) => {
+                let index = repo.index().expect("index was just loaded");
+                referenced.extend(index.packages.iter().map(|p| p.checksum.to_ascii_lowercase()));
+            }
+            Ok(false) | Err(PpmError::SecurityViolation(_)) => {}
+            Err(e) => return Err(e),
+        }
     }
-    Ok(())
-}
\ No newline at end of file
+    let db = InstalledDatabase::open_read_only(&config.state_dir)?;
+    referenced.extend(db.packages().filter_map(|p| p.archive.clone()));
+    drop(db);
+
+    let cache = PackageCache::open(&config.cache_dir);
+    let mut stats = cache.retain(&referenced)?;
+    let evicted = cache.evict(&config.cache)?;
+    stats.removed += evicted.removed;
+    stats.freed += evicted.freed;
+    Ok(stats)
+}
+
+// Eviction after an install is housekeeping; failing it never fails the
+// install itself. It keeps exactly what `clean` without `--all` would.
+fn evict_cache(config: &Config, warnings: &mut Vec<String>) {
+    if let Err(e) = prune_cache(config) {
+        warnings.push(format!("Cache eviction failed: {}", e));
+    }
+}
+
+pub async fn import_key(
+    path: &Path,
+    owner: Option<&str>,
+    expires_at: Option<u64>,
+    channels: &[Channel],
+    repositories: &[String],
+    trust: bool,
+    config: &Config,
+) -> Result<KeyRecord> {
+    let mut keyring = Keyring::open(&config.keyring_dir)?;
+    let imported = keyring.import_file(path, owner.unwrap_or("unknown"))?.clone();
+    let record = KeyRecord {
+        owner: owner.map(str::to_string).unwrap_or(imported.owner),
+        expires_at: expires_at.or(imported.expires_at),
+        channels: if channels.is_empty() { imported.channels } else { channels.to_vec() },
+        repositories: if repositories.is_empty() { imported.repositories } else { repositories.to_vec() },
+        ..imported
+    };
+    let mut record = keyring.import(record)?;
+    if trust {
+        let fingerprint = record.fingerprint.clone();
+        record = keyring.trust(&fingerprint)?;
+    }
+    Ok(record.clone())
+}
+
+pub async fn export_key(id: &str, config: &Config) -> Result<String> {
+    Keyring::open(&config.keyring_dir)?.export(id)
+}
+
+pub async fn list_keys(config: &Config) -> Result<Vec<KeyRecord>> {
+    Ok(Keyring::open(&config.keyring_dir)?.keys().cloned().collect())
+}
+
+pub async fn trust_key(id: &str, config: &Config) -> Result<KeyRecord> {
+    Ok(Keyring::open(&config.keyring_dir)?.trust(id)?.clone())
+}
+
+pub async fn distrust_key(id: &str, config: &Config) -> Result<KeyRecord> {
+    Ok(Keyring::open(&config.keyring_dir)?.distrust(id)?.clone())
+}
+
+pub async fn revoke_key(id: &str, reason: Option<&str>, config: &Config) -> Result<KeyRecord> {
+    Ok(Keyring::open(&config.keyring_dir)?.revoke(id, reason)?.clone())
+}
+
+pub async fn build_repository(path: &Path, full: bool) -> Result<RepositoryBuild> {
+    let generated = RepositoryGenerator::new(path).incremental(!full).build()?;
+    let mut indexes: Vec<IndexSummary> = generated
+        .indexes
+        .iter()
+        .map(|(&(channel, architecture), index)| IndexSummary {
+            channel,
+            architecture,
+            packages: index.packages.len(),
+        })
+        .collect();
+    indexes.sort_by_key(|i| (i.channel.name(), i.architecture.as_str()));
+    Ok(RepositoryBuild {
+        scanned: generated.scanned,
+        reused: generated.reused,
+        indexes,
+        written: generated.written,
+    })
+}
+
+pub async fn build_package(
+    manifest: &Path,
+    staging: Option<&Path>,
+    output: &Path,
+    architecture: Option<Architecture>,
+    channel: Option<Channel>,
+    config: &Config,
+) -> Result<BuiltPackage> {
+    let mut builder = PackageBuilder::open(manifest)?.with_defaults(config.architecture, config.channel);
+    if let Some(staging) = staging {
+        builder = builder.with_staging(std::env::current_dir()?.join(staging));
+    }
+    if let Some(architecture) = architecture {
+        builder = builder.with_architecture(architecture);
+    }
+    if let Some(channel) = channel {
+        builder = builder.with_channel(channel);
+    }
+    builder.write(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::TestDir;
+    use crate::formats::plpm::PlpmFile;
+    use crate::{compute_checksum, SignaturePolicy};
+
+    struct Fixture {
+        dir: TestDir,
+        config: Config,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = TestDir::new("operations", name);
+            std::fs::create_dir_all(dir.join("repo")).unwrap();
+            std::fs::create_dir_all(dir.join("root")).unwrap();
+            let config = Config {
+                repository_url: format!("file://{}", dir.join("repo").display()),
+                signature_policy: SignaturePolicy::AllowUnsigned,
+                ..Config::default()
+            }
+            .with_root(dir.join("root"));
+            Self { dir, config }
+        }
+
+        // Adds a package to the repository and regenerates its index.
+        fn publish(&self, pkg: Package, files: &[(&str, &str)]) {
+            let files = files
+                .iter()
+                .map(|(path, data)| PlpmFile {
+                    path: path.to_string(),
+                    data: data.as_bytes().to_vec(),
+                    permissions: 0o644,
+                    checksum: compute_checksum(data.as_bytes()),
+                })
+                .collect();
+            let path = self.dir.join("repo/stable").join(format!("{}-{}.plpm", pkg.name, pkg.version));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            PlpmPackage::new(pkg, files).save(path).unwrap();
+            RepositoryGenerator::new(self.dir.join("repo")).build().unwrap();
+        }
+
+        fn installed(&self) -> Vec<String> {
+            let db = InstalledDatabase::open_read_only(&self.config.state_dir).unwrap();
+            db.packages().map(|p| format!("{}-{}", p.name, p.version)).collect()
+        }
+    }
+
+    fn pkg(name: &str, version: &str, dependencies: &[&str]) -> Package {
+        Package {
+            dependencies: dependencies.iter().map(|d| d.parse().unwrap()).collect(),
+            architecture: Architecture::current(),
+            ..Package::for_test(name, version)
+        }
+    }
+
+    async fn install(fixture: &Fixture, name: &str) -> Result<InstallReport> {
+        install_package(name, None, None, None, &[], true, true, false, false, &fixture.config, &Events::default()).await
+    }
+
+    fn names(report: &InstallReport) -> Vec<String> {
+        report.installed.iter().map(|p| format!("{}-{}", p.name, p.version)).collect()
+    }
+
+    async fn remove(fixture: &Fixture, name: &str, cascade: bool) -> Result<Vec<String>> {
+        let report = remove_package(name, cascade, false, false, &fixture.config, &Events::default()).await?;
+        Ok(report.removed.iter().map(|p| format!("{}-{}", p.name, p.version)).collect())
+    }
+
+    #[tokio::test]
+    async fn cascades_and_autoremoves_unneeded_dependencies() {
+        let fixture = Fixture::new("cascade");
+        fixture.publish(pkg("app", "1.0.0", &["lib"]), &[("usr/bin/app", "app")]);
+        fixture.publish(pkg("lib", "1.0.0", &["base"]), &[("usr/lib/liblib.so", "lib")]);
+        fixture.publish(pkg("base", "1.0.0", &[]), &[("usr/lib/libbase.so", "base")]);
+        fixture.publish(pkg("tool", "1.0.0", &["base"]), &[("usr/bin/tool", "tool")]);
+        install(&fixture, "app").await.unwrap();
+        install(&fixture, "tool").await.unwrap();
+
+        let message = match remove(&fixture, "lib", false).await {
+            Err(PpmError::DependencyResolution(message)) => message,
+            other => panic!("expected a dependency error, got {:?}", other),
+        };
+        assert!(message.contains("lib is required by app-1.0.0"), "{}", message);
+
+        // Dependents go first; base stays for tool.
+        assert_eq!(remove(&fixture, "app", true).await.unwrap(), ["app-1.0.0", "lib-1.0.0"]);
+        assert_eq!(fixture.installed(), ["base-1.0.0", "tool-1.0.0"]);
+        assert!(!fixture.dir.join("root/usr/lib/liblib.so").exists());
+
+        assert_eq!(remove(&fixture, "tool", false).await.unwrap(), ["tool-1.0.0"]);
+        let report = autoremove(false, &fixture.config, &Events::default()).await.unwrap();
+        let removed: Vec<&str> = report.removed.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(removed, ["base"]);
+        assert!(fixture.installed().is_empty());
+        assert!(autoremove(false, &fixture.config, &Events::default()).await.unwrap().removed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn installs_leave_satisfied_dependencies_alone() {
+        let fixture = Fixture::new("keep-deps");
+        fixture.publish(pkg("lib", "1.0.0", &[]), &[("usr/lib/libdemo.so", "one")]);
+        fixture.publish(pkg("app", "1.0.0", &["lib >= 1"]), &[("usr/bin/app", "app")]);
+        let report = install(&fixture, "lib").await.unwrap();
+        assert_eq!(names(&report), ["lib-1.0.0"]);
+
+        fixture.publish(pkg("lib", "1.5.0", &[]), &[("usr/lib/libdemo.so", "one and a half")]);
+        let report = install(&fixture, "app").await.unwrap();
+        assert_eq!(names(&report), ["app-1.0.0"]);
+        assert_eq!(fixture.installed(), ["app-1.0.0", "lib-1.0.0"]);
+
+        let report = install(&fixture, "app").await.unwrap();
+        assert!(report.installed.is_empty());
+        assert!(report.warnings.iter().any(|w| w == "app-1.0.0 is already installed"), "{:?}", report.warnings);
+    }
+
+    #[tokio::test]
+    async fn cache_cleanup_keeps_what_is_still_referenced() {
+        let fixture = Fixture::new("clean");
+        fixture.publish(pkg("app", "1.0.0", &[]), &[("usr/bin/app", "app")]);
+        fixture.publish(pkg("tool", "1.0.0", &[]), &[("usr/bin/tool", "tool")]);
+        install(&fixture, "app").await.unwrap();
+        let cache = PackageCache::open(&fixture.config.cache_dir);
+        let (stray, _) = cache.insert(b"no index lists this").unwrap();
+
+        // Housekeeping after an install drops the same entries `clean` would.
+        install(&fixture, "tool").await.unwrap();
+        assert!(!cache.contains(&stray));
+        assert_eq!(cache.entries().unwrap().len(), 2);
+        assert_eq!(clean_cache(false, &fixture.config).await.unwrap().removed, 0);
+
+        let report = clean_cache(true, &fixture.config).await.unwrap();
+        assert_eq!(report.removed, 2);
+        assert!(cache.entries().unwrap().is_empty());
+        // Cached indexes survive, so the repository still answers offline.
+        let mut warnings = Vec::new();
+        let events = Events::default();
+        let config = &fixture.config;
+        let mut manager = build_repo_manager(config, config.channel, config.architecture, &events, &mut warnings);
+        assert!(manager.get_repositories_mut()[0].load_cached().unwrap());
+    }
+
+    #[tokio::test]
+    async fn queries_report_paths_inside_the_install_root() {
+        let fixture = Fixture::new("root-queries");
+        fixture.publish(pkg("app", "1.0.0", &[]), &[("usr/bin/app", "app"), ("usr/share/app/data", "data")]);
+        install(&fixture, "app").await.unwrap();
+        let root = fixture.dir.join("root");
+        assert!(Path::new(&fixture.config.state_dir).starts_with(&root));
+        assert!(root.join("usr/bin/app").is_file());
+
+        let files = list_files("app", &fixture.config).await.unwrap();
+        let paths: Vec<&str> = files.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["/usr/bin/app", "/usr/share/app/data"]);
+
+        // Host paths into the root and paths as seen inside it agree.
+        for path in [root.join("usr/bin/app"), PathBuf::from("/usr/bin/app"), root.join("usr/share/../bin/app")] {
+            let ownership = find_owners(&path, &fixture.config).await.unwrap();
+            assert_eq!(ownership.path, "/usr/bin/app", "{}", path.display());
+            assert_eq!(ownership.owners, ["app"]);
+            assert!(!ownership.directory);
+        }
+    }
+
+    #[tokio::test]
+    async fn finds_owners_of_files_and_directories() {
+        let fixture = Fixture::new("owners");
+        fixture.publish(pkg("app", "1.0.0", &[]), &[("usr/bin/app", "app"), ("usr/share/app/data", "data")]);
+        fixture.publish(pkg("tool", "1.0.0", &[]), &[("usr/bin/tool", "tool"), ("usr/share/tool/data", "data")]);
+        install(&fixture, "app").await.unwrap();
+        install(&fixture, "tool").await.unwrap();
+        let owners = |path: &'static str| find_owners(Path::new(path), &fixture.config);
+
+        let file = owners("/usr/share/tool/data").await.unwrap();
+        assert_eq!((file.directory, file.owners), (false, vec!["tool".to_string()]));
+        let dir = owners("/usr/share/").await.unwrap();
+        assert_eq!(dir.path, "/usr/share");
+        assert_eq!((dir.directory, dir.owners), (true, vec!["app".to_string(), "tool".to_string()]));
+        assert_eq!(owners("/usr/share/app").await.unwrap().owners, ["app"]);
+
+        // Neither a missing file nor a name that merely shares a prefix with
+        // an owned directory belongs to anyone.
+        for path in ["/usr/bin/missing", "/usr/sha", "/etc"] {
+            match owners(path).await {
+                Err(e @ PpmError::PackageNotFound(_)) => {
+                    assert_eq!(e.exit_code(), 3);
+                    assert!(e.to_string().contains(&format!("No installed package owns {}", path)), "{}", e);
+                }
+                other => panic!("expected {} to be unowned, got {:?}", path, other),
+            }
+        }
+        assert!(matches!(list_files("missing", &fixture.config).await, Err(PpmError::PackageNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn reports_install_progress_in_order() {
+        let fixture = Fixture::new("events");
+        fixture.publish(pkg("app", "1.0.0", &["lib"]), &[("usr/bin/app", "app")]);
+        fixture.publish(pkg("lib", "1.0.0", &[]), &[("usr/lib/liblib.so", "lib"), ("usr/share/lib/data", "data")]);
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorder = std::sync::Arc::clone(&seen);
+        let events = Events::new().with_observer(move |event: &Event| recorder.lock().unwrap().push(event.clone()));
+        let config = &fixture.config;
+        install_package("app", None, None, None, &[], true, true, false, false, config, &events).await.unwrap();
+
+        let seen = seen.lock().unwrap();
+        let stages: Vec<&str> = seen
+            .iter()
+            .map(|event| match event {
+                Event::ResolveStarted { .. } | Event::ResolveFinished { .. } => "resolve",
+                Event::DownloadStarted { .. } | Event::DownloadProgress { .. } | Event::DownloadFinished { .. } => {
+                    "download"
+                }
+                Event::Verified { .. } => "verify",
+                Event::ExtractStarted { .. } | Event::FileExtracted { .. } | Event::ExtractFinished { .. } => "extract",
+                Event::ScriptStarted { .. } | Event::ScriptFinished { .. } => "script",
+                Event::TransactionCommitted { .. } => "commit",
+            })
+            .collect();
+        let mut order = stages.clone();
+        order.dedup();
+        assert_eq!(order, ["resolve", "download", "verify", "extract", "commit"], "{:?}", seen);
+
+        let count = |stage: &str| stages.iter().filter(|s| **s == stage).count();
+        assert_eq!(count("verify"), 2);
+        assert!(matches!(&seen[1], Event::ResolveFinished { packages } if packages.len() == 2));
+        let extracted = seen.iter().filter(|e| matches!(e, Event::FileExtracted { .. })).count();
+        assert_eq!(extracted, 3);
+        match seen.last() {
+            Some(Event::TransactionCommitted { installed, removed, .. }) => {
+                assert_eq!(installed.len(), 2);
+                assert!(removed.is_empty());
+            }
+            other => panic!("expected the commit last, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn upgrades_outdated_packages_in_one_transaction() {
+        let fixture = Fixture::new("upgrade");
+        fixture.publish(pkg("lib", "1.0.0", &[]), &[("usr/lib/liblib.so", "lib 1")]);
+        fixture.publish(pkg("app", "1.0.0", &["lib >= 1"]), &[("usr/bin/app", "app 1")]);
+        fixture.publish(pkg("tool", "1.0.0", &[]), &[("usr/bin/tool", "tool 1")]);
+        install(&fixture, "app").await.unwrap();
+        install(&fixture, "tool").await.unwrap();
+
+        fixture.publish(pkg("lib", "2.0.0", &[]), &[("usr/lib/liblib.so", "lib 2")]);
+        fixture.publish(pkg("extra", "1.0.0", &[]), &[("usr/lib/libextra.so", "extra")]);
+        fixture.publish(pkg("app", "2.0.0", &["lib >= 2", "extra"]), &[("usr/bin/app", "app 2")]);
+        let plan = check_updates(None, &fixture.config).await.unwrap();
+        let planned: Vec<String> =
+            plan.upgrades.iter().map(|u| format!("{} {} -> {}", u.name, u.installed, u.available)).collect();
+        assert_eq!(planned, ["app 1.0.0 -> 2.0.0", "lib 1.0.0 -> 2.0.0"]);
+
+        let commits = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = std::sync::Arc::clone(&commits);
+        let events = Events::new().with_observer(move |event: &Event| {
+            if matches!(event, Event::TransactionCommitted { .. }) {
+                counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }
+        });
+        let report = update_packages(None, None, false, false, &fixture.config, &events).await.unwrap();
+        let mut upgraded = names(&report);
+        upgraded.sort();
+        assert_eq!(upgraded, ["app-2.0.0", "extra-1.0.0", "lib-2.0.0"]);
+        assert_eq!(commits.load(std::sync::atomic::Ordering::Relaxed), 1);
+        assert_eq!(fixture.installed(), ["app-2.0.0", "extra-1.0.0", "lib-2.0.0", "tool-1.0.0"]);
+        let root = fixture.dir.join("root");
+        assert_eq!(std::fs::read_to_string(root.join("usr/bin/app")).unwrap(), "app 2");
+        assert_eq!(std::fs::read_to_string(root.join("usr/lib/liblib.so")).unwrap(), "lib 2");
+
+        let db = InstalledDatabase::open_read_only(&fixture.config.state_dir).unwrap();
+        assert_eq!(db.get("app").unwrap().reason, InstallReason::Explicit);
+        assert_eq!(db.get("extra").unwrap().reason, InstallReason::Dependency);
+        drop(db);
+
+        // Everything is current now.
+        let report = update_packages(None, None, false, false, &fixture.config, &Events::default()).await.unwrap();
+        assert!(report.installed.is_empty());
+        assert!(matches!(
+            update_packages(Some("missing"), None, false, false, &fixture.config, &Events::default()).await,
+            Err(PpmError::PackageNotFound(_))
+        ));
+    }
+}
@@ -1,14 +1,29 @@
-use serde::{Deserialize, Serialize};
-use crate::{Architecture, Channel};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use crate::database::InstalledPackage;
+use crate::version::Op;
+use crate::{Architecture, Channel, PpmError, Result, Version, VersionReq};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Package {
     pub name: String,
-    pub version: String,
+    pub version: Version,
     pub description: Option<String>,
     pub author: Option<String>,
     pub license: Option<String>,
-    pub dependencies: Vec<String>,
+    pub dependencies: Vec<Dependency>,
+    #[serde(default)]
+    pub conflicts: Vec<Dependency>,
+    #[serde(default)]
+    pub provides: Vec<Dependency>,
+    #[serde(default)]
+    pub replaces: Vec<Dependency>,
+    #[serde(default)]
+    pub recommends: Vec<Dependency>,
+    #[serde(default)]
+    pub features: BTreeMap<String, Vec<Dependency>>,
     pub architecture: Architecture,
     pub channel: Channel,
     pub file: String,
@@ -21,11 +36,21 @@ pub struct Package {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PackageMetadata {
     pub name: String,
-    pub version: String,
+    pub version: Version,
     pub description: Option<String>,
     pub author: Option<String>,
     pub license: Option<String>,
-    pub dependencies: Vec<String>,
+    pub dependencies: Vec<Dependency>,
+    #[serde(default)]
+    pub conflicts: Vec<Dependency>,
+    #[serde(default)]
+    pub provides: Vec<Dependency>,
+    #[serde(default)]
+    pub replaces: Vec<Dependency>,
+    #[serde(default)]
+    pub recommends: Vec<Dependency>,
+    #[serde(default)]
+    pub features: BTreeMap<String, Vec<Dependency>>,
     pub architectures: Vec<Architecture>,
     pub channels: Vec<Channel>,
     pub build_script: Option<String>,
@@ -33,7 +58,7 @@ pub struct PackageMetadata {
     pub sandbox_config: Option<SandboxConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SandboxConfig {
     pub allowed_paths: Vec<String>,
     pub network_access: bool,
@@ -45,4 +70,246 @@ pub struct PackageIndex {
     pub packages: Vec<Package>,
     pub generated: String,
     pub channel: Channel,
-}
\ No newline at end of file
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub name: String,
+    pub req: VersionReq,
+    // Optional features of the target to enable, written `name[a,b]`.
+    pub features: Vec<String>,
+}
+
+impl Dependency {
+    pub fn new(name: &str, req: VersionReq) -> Self {
+        Self {
+            name: name.to_string(),
+            req,
+            features: Vec::new(),
+        }
+    }
+
+    pub fn any(name: &str) -> Self {
+        Self::new(name, VersionReq::STAR)
+    }
+
+    pub fn with_features(mut self, features: &[String]) -> Self {
+        for feature in features {
+            if !self.features.contains(feature) {
+                self.features.push(feature.clone());
+            }
+        }
+        self
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.req.matches(version)
+    }
+
+    // A `provides` entry is versioned only when pinned with `=`; an
+    // unversioned one satisfies nothing but unversioned dependencies.
+    pub fn provided_version(&self) -> Option<Version> {
+        match self.req.comparators.as_slice() {
+            [c] if c.op == Op::Exact => Some(c.version()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.features.is_empty() {
+            write!(f, "[{}]", self.features.join(","))?;
+        }
+        if !self.req.is_any() {
+            write!(f, " {}", self.req)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Dependency {
+    type Err = PpmError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+')))
+            .unwrap_or(s.len());
+        let (name, rest) = s.split_at(split);
+        if name.is_empty() {
+            return Err(PpmError::DependencyResolution(format!(
+                "Invalid dependency specification: '{}'", s
+            )));
+        }
+        let mut rest = rest.trim();
+        let mut features = Vec::new();
+        if let Some(list) = rest.strip_prefix('[') {
+            let end = list.find(']').ok_or_else(|| {
+                PpmError::DependencyResolution(format!("Unclosed feature list in '{}'", s))
+            })?;
+            for feature in list[..end].split(',').map(str::trim).filter(|f| !f.is_empty()) {
+                if !feature.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_')) {
+                    return Err(PpmError::DependencyResolution(format!(
+                        "Invalid feature '{}' in '{}'", feature, s
+                    )));
+                }
+                features.push(feature.to_string());
+            }
+            rest = list[end + 1..].trim();
+        }
+        let rest = rest.trim_start_matches('(').trim_end_matches(')');
+        Ok(Self::new(name, rest.parse()?).with_features(&features))
+    }
+}
+
+impl Serialize for Dependency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Dependency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// The parts of a package that decide whether it can sit next to another
+// one, shared by index entries, archive metadata and installed records.
+#[derive(Debug, Clone, Copy)]
+pub struct Relations<'a> {
+    pub name: &'a str,
+    pub version: &'a Version,
+    pub provides: &'a [Dependency],
+    pub conflicts: &'a [Dependency],
+    pub replaces: &'a [Dependency],
+}
+
+impl Relations<'_> {
+    pub fn satisfies(&self, dep: &Dependency) -> bool {
+        if dep.name == self.name {
+            return dep.matches(self.version);
+        }
+        self.provides.iter().any(|p| {
+            p.name == dep.name
+                && (dep.req.is_any() || p.provided_version().is_some_and(|v| dep.matches(&v)))
+        })
+    }
+
+    pub fn replaces(&self, other: &Relations) -> bool {
+        self.name != other.name && self.replaces.iter().any(|dep| other.satisfies(dep))
+    }
+
+    // A package may conflict with a virtual name it provides itself, which
+    // is how one implementation of an interface excludes all the others.
+    pub fn conflicts_with(&self, other: &Relations) -> bool {
+        self.name != other.name
+            && (self.conflicts.iter().any(|dep| other.satisfies(dep))
+                || other.conflicts.iter().any(|dep| self.satisfies(dep))
+                || self.replaces(other)
+                || other.replaces(self))
+    }
+}
+
+impl Package {
+    pub fn relations(&self) -> Relations<'_> {
+        Relations {
+            name: &self.name,
+            version: &self.version,
+            provides: &self.provides,
+            conflicts: &self.conflicts,
+            replaces: &self.replaces,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Package {
+    // An x86_64 stable package with nothing but a name and version, for
+    // tests to fill in what they need.
+    pub(crate) fn for_test(name: &str, version: &str) -> Self {
+        Package {
+            name: name.to_string(),
+            version: version.parse().unwrap(),
+            description: None,
+            author: None,
+            license: None,
+            dependencies: Vec::new(),
+            conflicts: Vec::new(),
+            provides: Vec::new(),
+            replaces: Vec::new(),
+            recommends: Vec::new(),
+            features: BTreeMap::new(),
+            architecture: Architecture::X86_64,
+            channel: Channel::Stable,
+            file: format!("{}-{}.plpm", name, version),
+            checksum: String::new(),
+            signature: None,
+            size: 0,
+            install_size: 0,
+        }
+    }
+}
+
+// A scratch directory for one test, named after its module, the test and this
+// process. It starts out absent and is removed again on drop.
+#[cfg(test)]
+pub(crate) struct TestDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    pub(crate) fn new(module: &str, name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("ppm-{}-{}-{}", module, name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Self(dir)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDir {
+    type Target = std::path::Path;
+
+    fn deref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<std::path::Path> for TestDir {
+    fn as_ref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+impl PackageMetadata {
+    pub fn relations(&self) -> Relations<'_> {
+        Relations {
+            name: &self.name,
+            version: &self.version,
+            provides: &self.provides,
+            conflicts: &self.conflicts,
+            replaces: &self.replaces,
+        }
+    }
+}
+
+impl InstalledPackage {
+    pub fn relations(&self) -> Relations<'_> {
+        Relations {
+            name: &self.name,
+            version: &self.version,
+            provides: &self.provides,
+            conflicts: &self.conflicts,
+            replaces: &self.replaces,
+        }
+    }
+}
@@ -1,12 +1,61 @@
-use crate::{Package, Channel, Architecture, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+#[cfg(any(feature = "cli", feature = "http"))]
+use tokio::sync::Semaphore;
+#[cfg(any(feature = "cli", feature = "http"))]
+use tokio::task::JoinSet;
+
+use crate::formats::index::{decode_index, IndexFormat};
+use crate::keyring::Keyring;
+use crate::metadata::{
+    versioned_root_file, TrustedMetadata, ROOT_FILE, SNAPSHOT_FILE, TARGETS_FILE, TIMESTAMP_FILE,
+};
+use crate::config::{NetworkConfig, PinRule};
+use crate::formats::writer::normalize_path;
+use crate::{Package, Channel, Architecture, Event, Events, PpmError, Result, VersionReq};
+use crate::cache::PackageCache;
 use crate::package::PackageIndex;
 
+const MAX_ROOT_ROTATIONS: usize = 1024;
+
 #[derive(Debug, Clone)]
 pub struct Repository {
     pub url: String,
     pub name: String,
     pub channel: Channel,
     pub architectures: Vec<Architecture>,
+    pub priority: i32,
+    cache_dir: Option<PathBuf>,
+    network: NetworkConfig,
+    metadata: Option<MetadataTrust>,
+    events: Events,
+    index: Option<Arc<PackageIndex>>,
+}
+
+#[derive(Debug, Clone)]
+struct MetadataTrust {
+    state_dir: PathBuf,
+    keyring_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexValidators {
+    pub url: String,
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+}
+
+enum Location {
+    Local(PathBuf),
+    #[cfg(feature = "http")]
+    Remote(String),
 }
 
 impl Repository {
@@ -16,55 +65,805 @@ impl Repository {
             name,
             channel,
             architectures,
+            priority: 0,
+            cache_dir: None,
+            network: NetworkConfig::default(),
+            metadata: None,
+            events: Events::default(),
+            index: None,
         }
     }
 
-    pub async fn fetch_index(&self) -> Result<PackageIndex> {
-        Ok(PackageIndex {
-            packages: Vec::new(),
-            generated: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-                .to_string(),
-            channel: self.channel,
+    pub fn with_cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    // Requires signed root/timestamp/snapshot/targets metadata for this
+    // repository. Trusted copies live under `<state_dir>/repos`.
+    pub fn with_signed_metadata(
+        mut self,
+        state_dir: impl Into<PathBuf>,
+        keyring_dir: impl Into<PathBuf>,
+    ) -> Self {
+        self.metadata = Some(MetadataTrust {
+            state_dir: state_dir.into(),
+            keyring_dir: keyring_dir.into(),
+        });
+        self
+    }
+
+    pub fn with_network(mut self, network: NetworkConfig) -> Self {
+        self.network = network;
+        self
+    }
+
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    fn location(&self) -> Result<Location> {
+        if let Some(path) = self.url.strip_prefix("file://") {
+            return Ok(Location::Local(PathBuf::from(path)));
+        }
+        #[cfg(feature = "http")]
+        if self.url.starts_with("http://") || self.url.starts_with("https://") {
+            return Ok(Location::Remote(self.url.trim_end_matches('/').to_string()));
+        }
+        match self.url.split_once("://") {
+            Some((scheme, _)) => Err(PpmError::Repository(format!(
+                "{}: unsupported URL scheme '{}'", self.name, scheme
+            ))),
+            None => Ok(Location::Local(PathBuf::from(&self.url))),
+        }
+    }
+
+    // Repositories are laid out as `<url>/<channel>/index.{bin,toml}`, with
+    // package files stored relative to the channel directory.
+    fn channel_path(&self, relative: &str) -> Result<Location> {
+        Ok(match self.location()? {
+            Location::Local(root) => Location::Local(root.join(self.channel.name()).join(relative)),
+            #[cfg(feature = "http")]
+            Location::Remote(base) => Location::Remote(format!("{}/{}/{}", base, self.channel.name(), relative)),
         })
     }
 
+    pub fn index_cache_path(&self) -> Option<PathBuf> {
+        self.cache_dir.as_ref().map(|dir| {
+            dir.join("repos")
+                .join(&self.name)
+                .join(self.channel.name())
+                .join("index")
+        })
+    }
+
+    fn validators_path(&self) -> Option<PathBuf> {
+        self.index_cache_path().map(|path| path.with_file_name("validators.toml"))
+    }
+
+    #[cfg(feature = "http")]
+    fn cached_validators(&self) -> Option<IndexValidators> {
+        let contents = fs::read_to_string(self.validators_path()?).ok()?;
+        toml::from_str(&contents).ok()
+    }
+
+    async fn fetch_file(&self, relative: &str) -> Result<Option<Vec<u8>>> {
+        match self.channel_path(relative)? {
+            Location::Local(path) => match fs::read(&path) {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            #[cfg(feature = "http")]
+            Location::Remote(url) => {
+                match crate::http::HttpClient::new(&self.network)?.get(&url, None).await? {
+                    crate::http::Fetched::Modified { body, .. } => Ok(Some(body)),
+                    crate::http::Fetched::Missing => Ok(None),
+                    crate::http::Fetched::NotModified => Err(PpmError::Repository(format!(
+                        "{} answered an unconditional request with 304", url
+                    ))),
+                }
+            }
+        }
+    }
+
+    async fn fetch_required(&self, relative: &str) -> Result<Vec<u8>> {
+        self.fetch_file(relative).await?.ok_or_else(|| {
+            PpmError::Repository(format!("{}: {} is missing from {}", self.name, relative, self.url))
+        })
+    }
+
+    // Walks root -> timestamp -> snapshot -> targets and only then accepts an
+    // index whose hash the targets role vouches for. A cached index that still
+    // matches is reused without downloading it again.
+    async fn fetch_verified_index(&self, trust: &MetadataTrust) -> Result<PackageIndex> {
+        let now = crate::database::now_timestamp();
+        let mut trusted = TrustedMetadata::load(self.metadata_dir(trust))?;
+        let keyring = Keyring::open(&trust.keyring_dir)?;
+
+        // A rotation is applied one root at a time, each one vouched for by
+        // the root before it; `root.toml` then has to match where it ends.
+        if let Some(mut version) = trusted.root().map(|root| root.version) {
+            for _ in 0..MAX_ROOT_ROTATIONS {
+                version += 1;
+                let Some(root) = self.fetch_file(&versioned_root_file(version)).await? else {
+                    break;
+                };
+                trusted.update_root(&root, &keyring, self.channel, &self.name, now)?;
+            }
+        }
+        let root = self.fetch_required(ROOT_FILE).await?;
+        trusted.update_root(&root, &keyring, self.channel, &self.name, now)?;
+        let timestamp = self.fetch_required(TIMESTAMP_FILE).await?;
+        trusted.update_timestamp(&timestamp, now)?;
+        if trusted.needs_snapshot() {
+            let snapshot = self.fetch_required(SNAPSHOT_FILE).await?;
+            trusted.update_snapshot(&snapshot, now)?;
+        }
+        if trusted.needs_targets() {
+            let targets = self.fetch_required(TARGETS_FILE).await?;
+            trusted.update_targets(&targets, now)?;
+        }
+        trusted.check_fresh(now)?;
+
+        let name = self.index_target(&trusted)?;
+        let cached = self
+            .index_cache_path()
+            .and_then(|path| fs::read(path).ok())
+            .filter(|data| trusted.verify_target(name, data).is_ok());
+        let data = match cached {
+            Some(data) => data,
+            None => {
+                let data = self.fetch_required(name).await?;
+                trusted.verify_target(name, &data)?;
+                data
+            }
+        };
+        self.accept_index(&data, None)
+    }
+
+    fn metadata_dir(&self, trust: &MetadataTrust) -> PathBuf {
+        trust.state_dir.join("repos").join(&self.name).join(self.channel.name())
+    }
+
+    fn index_target(&self, trusted: &TrustedMetadata) -> Result<&'static str> {
+        [IndexFormat::Binary, IndexFormat::Toml]
+            .into_iter()
+            .map(|f| f.file_name())
+            .find(|name| trusted.target(name).is_some())
+            .ok_or_else(|| {
+                PpmError::SecurityViolation(format!("{}: targets metadata lists no index", self.name))
+            })
+    }
+
+    fn accept_index(&self, data: &[u8], validators: Option<IndexValidators>) -> Result<PackageIndex> {
+        let index = decode_index(data)?;
+        if index.channel != self.channel {
+            return Err(PpmError::Repository(format!(
+                "{}: index is for the {} channel, expected {}",
+                self.name,
+                index.channel.name(),
+                self.channel.name()
+            )));
+        }
+        if let (Some(path), Some(meta)) = (self.index_cache_path(), self.validators_path()) {
+            write_atomic(&path, data)?;
+            match validators {
+                Some(validators) => {
+                    let contents = toml::to_string(&validators)
+                        .map_err(|e| PpmError::Serialization(e.to_string()))?;
+                    write_atomic(&meta, contents.as_bytes())?;
+                }
+                None => match fs::remove_file(&meta) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                },
+            }
+        }
+        Ok(index)
+    }
+
+    pub async fn fetch_index(&self) -> Result<PackageIndex> {
+        if let Some(trust) = &self.metadata {
+            return self.fetch_verified_index(trust).await;
+        }
+        let mut fetched = None;
+        for format in [IndexFormat::Binary, IndexFormat::Toml] {
+            match self.channel_path(format.file_name())? {
+                Location::Local(path) => match fs::read(&path) {
+                    Ok(bytes) => fetched = Some((bytes, None)),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e.into()),
+                },
+                #[cfg(feature = "http")]
+                Location::Remote(url) => {
+                    let client = crate::http::HttpClient::new(&self.network)?;
+                    let cached = self.cached_validators();
+                    match client.get(&url, cached.as_ref()).await? {
+                        crate::http::Fetched::Modified { body, validators } => {
+                            fetched = Some((body, Some(validators)))
+                        }
+                        crate::http::Fetched::NotModified => {
+                            if let Some(index) = self.cached_index()? {
+                                return Ok(index);
+                            }
+                            // The validators outlived the cached index, so
+                            // fetch the index again without them.
+                            if let Some(path) = self.validators_path() {
+                                let _ = fs::remove_file(path);
+                            }
+                            return Box::pin(self.fetch_index()).await;
+                        }
+                        crate::http::Fetched::Missing => continue,
+                    }
+                }
+            }
+            break;
+        }
+        let (data, validators) = fetched.ok_or_else(|| {
+            PpmError::Repository(format!("{}: no index found at {}", self.name, self.url))
+        })?;
+
+        self.accept_index(&data, validators)
+    }
+
+    pub async fn refresh(&mut self) -> Result<&PackageIndex> {
+        let index = self.fetch_index().await?;
+        Ok(self.index.insert(Arc::new(index)))
+    }
+
+    // A cached signed index is only used while the metadata that vouched for
+    // it is still fresh; a mirror that withholds updates must not be able to
+    // keep it alive forever.
+    pub fn cached_index(&self) -> Result<Option<PackageIndex>> {
+        let Some(path) = self.index_cache_path() else {
+            return Ok(None);
+        };
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(trust) = &self.metadata {
+            let trusted = TrustedMetadata::load(self.metadata_dir(trust))?;
+            trusted.check_fresh(crate::database::now_timestamp())?;
+            trusted.verify_target(self.index_target(&trusted)?, &data)?;
+        }
+        decode_index(&data).map(Some)
+    }
+
+    pub fn load_cached(&mut self) -> Result<bool> {
+        self.index = None;
+        self.index = self.cached_index()?.map(Arc::new);
+        Ok(self.index.is_some())
+    }
+
+    pub fn index(&self) -> Option<&PackageIndex> {
+        self.index.as_deref()
+    }
+
     pub fn supported_architectures(&self) -> &[Architecture] {
         &self.architectures
     }
 
-    pub fn find_package(&self, _name: &str, _version: Option<&str>) -> Result<Option<Package>> {
-        Ok(None)
+    pub fn find_package(&self, name: &str, version: Option<&VersionReq>) -> Result<Option<Package>> {
+        self.find_package_where(name, |p| version.is_none_or(|req| req.matches(&p.version)))
+    }
+
+    pub fn find_package_where(
+        &self,
+        name: &str,
+        filter: impl Fn(&Package) -> bool,
+    ) -> Result<Option<Package>> {
+        let cached;
+        let index = match self.index.as_deref() {
+            Some(index) => index,
+            None => match self.cached_index()? {
+                Some(index) => {
+                    cached = index;
+                    &cached
+                }
+                None => return Ok(None),
+            },
+        };
+        Ok(index
+            .packages
+            .iter()
+            .filter(|p| p.name == name && p.channel == self.channel)
+            .filter(|p| self.architectures.contains(&p.architecture))
+            .filter(|p| filter(p))
+            .max_by(|a, b| a.version.cmp(&b.version))
+            .cloned())
+    }
+
+    pub fn provides(&self, pkg: &Package) -> bool {
+        self.index.as_ref().is_some_and(|index| {
+            index.packages.iter().any(|p| {
+                p.name == pkg.name && p.version == pkg.version && p.architecture == pkg.architecture
+            })
+        })
+    }
+
+    // Stores the package in the content-addressed cache under its index
+    // checksum; local files may be used in place when there is no cache.
+    pub async fn fetch_package(&self, pkg: &Package) -> Result<PathBuf> {
+        let relative = normalize_path(&pkg.file)?;
+        let location = self.channel_path(&relative)?;
+        let cache = match (&self.cache_dir, &location) {
+            (Some(dir), _) => PackageCache::open(dir),
+            (None, Location::Local(source)) => {
+                verify_file(source, pkg)?;
+                return Ok(source.clone());
+            }
+            #[cfg(feature = "http")]
+            (None, Location::Remote(url)) => {
+                return Err(PpmError::Repository(format!(
+                    "cannot download {} without a cache directory", url
+                )));
+            }
+        };
+        let package = format!("{}-{}", pkg.name, pkg.version);
+        if let Some(cached) = cache.get(&pkg.checksum)? {
+            self.events.emit(Event::DownloadFinished { package, bytes: pkg.size, cached: true });
+            return Ok(cached);
+        }
+
+        let (tmp, bytes, _lock) = match &location {
+            Location::Local(source) => {
+                self.events.emit(Event::DownloadStarted {
+                    package: package.clone(),
+                    source: source.display().to_string(),
+                    total: Some(pkg.size),
+                });
+                let tmp = cache.temp_path()?;
+                let bytes = fs::copy(source, &tmp)?;
+                (tmp, bytes, None::<File>)
+            }
+            #[cfg(feature = "http")]
+            Location::Remote(url) => {
+                // Another process already downloading the same package holds
+                // the lock on its partial file; fall back to a private one
+                // rather than interleave writes with it. The lock is kept
+                // until the file has been moved into the cache.
+                let mut partial = cache.partial_path(&pkg.checksum)?;
+                let mut file = File::options().create(true).truncate(false).write(true).open(&partial)?;
+                if file.try_lock().is_err() {
+                    partial = cache.temp_path()?;
+                    file = File::create(&partial)?;
+                }
+                self.events.emit(Event::DownloadStarted {
+                    package: package.clone(),
+                    source: url.clone(),
+                    total: Some(pkg.size),
+                });
+                let progress = |bytes, total: Option<u64>| {
+                    self.events.emit(Event::DownloadProgress {
+                        package: package.clone(),
+                        bytes,
+                        total: total.or(Some(pkg.size)),
+                    })
+                };
+                let bytes = match crate::http::HttpClient::new(&self.network)?
+                    .download_resume(url, &mut file, Some(pkg.size), progress)
+                    .await
+                {
+                    Ok(bytes) => bytes,
+                    Err(e @ PpmError::SecurityViolation(_)) => {
+                        let _ = fs::remove_file(&partial);
+                        return Err(e);
+                    }
+                    Err(e) => return Err(e),
+                };
+                (partial, bytes, Some(file))
+            }
+        };
+        self.events.emit(Event::DownloadFinished { package, bytes, cached: false });
+        if let Err(e) = verify_file(&tmp, pkg) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+        cache.insert_file(&pkg.checksum, &tmp)
     }
 }
 
+fn verify_file(path: &Path, pkg: &Package) -> Result<()> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    if size != pkg.size {
+        return Err(PpmError::SecurityViolation(format!(
+            "size mismatch for {}-{}: expected {} bytes, got {}",
+            pkg.name, pkg.version, pkg.size, size
+        )));
+    }
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    let actual = format!("{:x}", hasher.finalize());
+    if !actual.eq_ignore_ascii_case(&pkg.checksum) {
+        return Err(PpmError::SecurityViolation(format!(
+            "checksum mismatch for {}-{}: expected {}, got {}",
+            pkg.name, pkg.version, pkg.checksum, actual
+        )));
+    }
+    Ok(())
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    {
+        let mut file = File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[derive(Default)]
 pub struct RepositoryManager {
     repositories: Vec<Repository>,
+    pins: Vec<PinRule>,
 }
 
 impl RepositoryManager {
     pub fn new() -> Self {
         Self {
             repositories: Vec::new(),
+            pins: Vec::new(),
         }
     }
 
+    // Repositories are kept in descending priority order; ties keep the
+    // order in which they were added.
     pub fn add_repository(&mut self, repo: Repository) {
-        self.repositories.push(repo);
+        let at = self
+            .repositories
+            .iter()
+            .position(|r| r.priority < repo.priority)
+            .unwrap_or(self.repositories.len());
+        self.repositories.insert(at, repo);
     }
 
     pub fn get_repositories(&self) -> &[Repository] {
         &self.repositories
     }
 
-    pub fn find_package_across_repos(&self, name: &str, version: Option<&str>) -> Result<Option<Package>> {
+    pub fn get_repositories_mut(&mut self) -> &mut [Repository] {
+        &mut self.repositories
+    }
+
+    pub fn set_pins(&mut self, pins: Vec<PinRule>) {
+        self.pins = pins;
+    }
+
+    pub fn pins(&self) -> &[PinRule] {
+        &self.pins
+    }
+
+    // A package covered by pin rules may only come from a repository named by
+    // one of them, and only in a version that rule allows.
+    pub fn allows(&self, repo: &Repository, pkg: &Package) -> bool {
+        let mut pins = self.pins.iter().filter(|pin| pin.applies_to(&pkg.name)).peekable();
+        if pins.peek().is_none() {
+            return true;
+        }
+        pins.any(|pin| {
+            pin.repository == repo.name
+                && pin.version.as_ref().is_none_or(|req| req.matches(&pkg.version))
+        })
+    }
+
+    // Each repository's index is narrowed to the packages it may supply: pins
+    // are applied first, then every package name is served only by the
+    // highest-priority repositories that still offer it.
+    pub async fn fetch_indexes(&self) -> Result<Vec<PackageIndex>> {
+        let mut fetched = Vec::with_capacity(self.repositories.len());
         for repo in &self.repositories {
-            if let Some(pkg) = repo.find_package(name, version)? {
-                return Ok(Some(pkg));
+            let index = match repo.index() {
+                Some(index) => index.clone(),
+                None => repo.fetch_index().await?,
+            };
+            fetched.push((repo, index));
+        }
+
+        let mut top: HashMap<String, i32> = HashMap::new();
+        for (repo, index) in &fetched {
+            for pkg in index.packages.iter().filter(|p| self.allows(repo, p)) {
+                let best = top.entry(pkg.name.clone()).or_insert(repo.priority);
+                *best = (*best).max(repo.priority);
             }
         }
-        Ok(None)
+        Ok(fetched
+            .into_iter()
+            .map(|(repo, mut index)| {
+                index.packages.retain(|p| {
+                    self.allows(repo, p) && top.get(&p.name) == Some(&repo.priority)
+                });
+                index
+            })
+            .collect())
     }
-}
\ No newline at end of file
+
+    pub fn find_package_across_repos(&self, name: &str, version: Option<&VersionReq>) -> Result<Option<Package>> {
+        let mut best: Option<(i32, Package)> = None;
+        for repo in &self.repositories {
+            let found = repo.find_package_where(name, |p| {
+                version.is_none_or(|req| req.matches(&p.version)) && self.allows(repo, p)
+            })?;
+            if let Some(pkg) = found {
+                let better = best.as_ref().is_none_or(|(priority, b)| {
+                    repo.priority > *priority || (repo.priority == *priority && pkg.version > b.version)
+                });
+                if better {
+                    best = Some((repo.priority, pkg));
+                }
+            }
+        }
+        Ok(best.map(|(_, pkg)| pkg))
+    }
+
+    fn source_of(&self, pkg: &Package) -> Result<&Repository> {
+        self.repositories
+            .iter()
+            .find(|r| r.provides(pkg) && self.allows(r, pkg))
+            .ok_or_else(|| PpmError::PackageNotFound(format!("{}-{}", pkg.name, pkg.version)))
+    }
+
+    pub async fn fetch_package(&self, pkg: &Package) -> Result<(&Repository, PathBuf)> {
+        let repo = self.source_of(pkg)?;
+        Ok((repo, repo.fetch_package(pkg).await?))
+    }
+
+    // Fetches each package on its own task, at most `limit` at a time, and
+    // returns them in the order given. The first failure cancels the rest;
+    // whatever they had downloaded stays in the cache to be resumed.
+    #[cfg(any(feature = "cli", feature = "http"))]
+    pub async fn fetch_packages(&self, pkgs: &[Package], limit: usize) -> Result<Vec<(&Repository, PathBuf)>> {
+        let repos = pkgs.iter().map(|pkg| self.source_of(pkg)).collect::<Result<Vec<_>>>()?;
+        let permits = Arc::new(Semaphore::new(limit.max(1)));
+        let mut tasks = JoinSet::new();
+        for (i, (repo, pkg)) in repos.iter().zip(pkgs).enumerate() {
+            let (repo, pkg, permits) = ((*repo).clone(), pkg.clone(), Arc::clone(&permits));
+            tasks.spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                (i, repo.fetch_package(&pkg).await)
+            });
+        }
+
+        let mut paths = vec![None; pkgs.len()];
+        while let Some(joined) = tasks.join_next().await {
+            let (i, path) = joined.map_err(|e| PpmError::Repository(format!("download task failed: {}", e)))?;
+            paths[i] = Some(path?);
+        }
+        Ok(repos.into_iter().zip(paths.into_iter().flatten()).collect())
+    }
+
+    // Without a tokio runtime to spawn on, packages are fetched one at a time.
+    #[cfg(not(any(feature = "cli", feature = "http")))]
+    pub async fn fetch_packages(&self, pkgs: &[Package], _limit: usize) -> Result<Vec<(&Repository, PathBuf)>> {
+        let mut fetched = Vec::with_capacity(pkgs.len());
+        for pkg in pkgs {
+            fetched.push(self.fetch_package(pkg).await?);
+        }
+        Ok(fetched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::TestDir;
+    use crate::formats::index::encode_index;
+    use crate::metadata::{
+        encode_role, sign_role, MetaFile, RoleKeys, RoleMetadata, Root, RootRoles, Snapshot, TargetFile,
+        Targets, Timestamp,
+    };
+    use crate::signing::key_fingerprint;
+    use crate::{compute_checksum, database::now_timestamp};
+    use ed25519_dalek::SigningKey;
+    use std::collections::BTreeMap;
+
+    struct Fixture {
+        dir: TestDir,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = TestDir::new("repository", name);
+            fs::create_dir_all(&dir).unwrap();
+            Self { dir }
+        }
+    }
+
+    fn index(packages: Vec<Package>) -> PackageIndex {
+        PackageIndex {
+            packages,
+            generated: "0".to_string(),
+            channel: Channel::Stable,
+        }
+    }
+
+    fn store<T: RoleMetadata>(dir: &Path, signed: T, key: &SigningKey) -> Vec<u8> {
+        let data = encode_role(&sign_role(signed, &[key]).unwrap()).unwrap().into_bytes();
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(T::ROLE.file_name()), &data).unwrap();
+        data
+    }
+
+    // Writes trusted metadata for `index_data` as a past refresh would have
+    // left it, with the timestamp expiring at `timestamp_expires`.
+    fn trust_index(dir: &Path, index_data: &[u8], timestamp_expires: u64) {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let id = key_fingerprint(&key.verifying_key());
+        let role = RoleKeys { keyids: vec![id.clone()], threshold: 1 };
+        let expires = now_timestamp() + 3600;
+        let root = Root {
+            version: 1,
+            expires,
+            keys: BTreeMap::from([(id, hex::encode(key.verifying_key().as_bytes()))]),
+            roles: RootRoles {
+                root: role.clone(),
+                timestamp: role.clone(),
+                snapshot: role.clone(),
+                targets: role,
+            },
+        };
+        store(dir, root, &key);
+        let file = TargetFile { length: index_data.len() as u64, sha256: compute_checksum(index_data) };
+        let files = BTreeMap::from([(IndexFormat::Toml.file_name().to_string(), file)]);
+        let targets = store(dir, Targets { version: 1, expires, files }, &key);
+        let meta = |data: &[u8]| MetaFile { version: 1, length: data.len() as u64, sha256: compute_checksum(data) };
+        let snapshot = store(dir, Snapshot { version: 1, expires, targets: meta(&targets) }, &key);
+        store(dir, Timestamp { version: 1, expires: timestamp_expires, snapshot: meta(&snapshot) }, &key);
+    }
+
+    #[test]
+    fn refuses_cached_signed_indexes_once_their_metadata_expires() {
+        let fixture = Fixture::new("freeze");
+        let state = fixture.dir.join("state");
+        // The mirror is gone, so every refresh fails without a security error.
+        let mut repo = Repository::new(
+            fixture.dir.join("missing").display().to_string(),
+            "main".to_string(),
+            Channel::Stable,
+            vec![Architecture::X86_64],
+        )
+        .with_cache_dir(fixture.dir.join("cache"))
+        .with_signed_metadata(&state, fixture.dir.join("keys"));
+        let data = encode_index(&index(Vec::new()), IndexFormat::Toml).unwrap();
+        write_atomic(&repo.index_cache_path().unwrap(), &data).unwrap();
+        let metadata = state.join("repos/main/stable");
+
+        trust_index(&metadata, &data, now_timestamp() + 600);
+        assert!(repo.load_cached().unwrap());
+
+        trust_index(&metadata, &data, now_timestamp() - 1);
+        match repo.load_cached() {
+            Err(PpmError::SecurityViolation(message)) => assert!(message.contains("expired"), "{}", message),
+            other => panic!("expected a security violation, got {:?}", other),
+        }
+        assert!(repo.find_package("demo", None).is_err());
+
+        // Nor is a cached index the targets role does not vouch for.
+        trust_index(&metadata, b"another index", now_timestamp() + 600);
+        assert!(matches!(repo.load_cached(), Err(PpmError::SecurityViolation(_))));
+    }
+
+    // Publishes `app` with a package file to an unsigned repository at `root`.
+    fn publish(root: &Path, data: &[u8]) -> Package {
+        let pkg = Package {
+            file: "pool/app-1.0.0.plpm".to_string(),
+            checksum: compute_checksum(data),
+            size: data.len() as u64,
+            ..Package::for_test("app", "1.0.0")
+        };
+        let channel = root.join(Channel::Stable.name());
+        fs::create_dir_all(channel.join("pool")).unwrap();
+        fs::write(channel.join(&pkg.file), data).unwrap();
+        fs::write(channel.join(IndexFormat::Toml.file_name()), encode_index(&index(vec![pkg.clone()]), IndexFormat::Toml).unwrap())
+            .unwrap();
+        pkg
+    }
+
+    #[tokio::test]
+    async fn reads_local_repositories_by_path_and_file_url() {
+        let fixture = Fixture::new("local");
+        let root = fixture.dir.join("repo");
+        let pkg = publish(&root, b"package bytes");
+        for url in [root.display().to_string(), format!("file://{}", root.display())] {
+            let mut repo = Repository::new(url.clone(), "main".to_string(), Channel::Stable, vec![pkg.architecture]);
+            repo.refresh().await.unwrap();
+            let found = repo.find_package("app", None).unwrap().expect(&url);
+            assert_eq!(found.checksum, pkg.checksum);
+            // Without a cache the package is verified and used in place.
+            assert_eq!(repo.fetch_package(&found).await.unwrap(), root.join("stable/pool/app-1.0.0.plpm"));
+
+            let cached = repo.with_cache_dir(fixture.dir.join("cache"));
+            let path = cached.fetch_package(&found).await.unwrap();
+            assert!(path.starts_with(fixture.dir.join("cache")), "{}", path.display());
+            assert_eq!(fs::read(path).unwrap(), b"package bytes");
+        }
+
+        let tampered = Package { size: pkg.size + 1, ..pkg };
+        let repo = Repository::new(root.display().to_string(), "main".to_string(), Channel::Stable, Vec::new());
+        assert!(matches!(repo.fetch_package(&tampered).await, Err(PpmError::SecurityViolation(_))));
+        let repo = Repository::new("ftp://mirror/repo".to_string(), "main".to_string(), Channel::Stable, Vec::new());
+        assert!(matches!(repo.fetch_index().await, Err(PpmError::Repository(m)) if m.contains("unsupported URL scheme")));
+    }
+
+    async fn serve(fixture: &Fixture, name: &str, priority: i32, packages: &[(&str, &str)]) -> Repository {
+        let root = fixture.dir.join(name);
+        let packages = packages.iter().map(|(name, version)| Package::for_test(name, version)).collect();
+        let channel = root.join(Channel::Stable.name());
+        fs::create_dir_all(&channel).unwrap();
+        fs::write(channel.join(IndexFormat::Toml.file_name()), encode_index(&index(packages), IndexFormat::Toml).unwrap())
+            .unwrap();
+        let mut repo = Repository::new(root.display().to_string(), name.to_string(), Channel::Stable, vec![Architecture::X86_64])
+            .with_priority(priority);
+        repo.refresh().await.unwrap();
+        repo
+    }
+
+    fn pin(package: &str, repository: &str, version: Option<&str>) -> PinRule {
+        PinRule {
+            package: package.to_string(),
+            repository: repository.to_string(),
+            version: version.map(|v| v.parse().unwrap()),
+        }
+    }
+
+    fn best(manager: &RepositoryManager, name: &str) -> Option<String> {
+        manager.find_package_across_repos(name, None).unwrap().map(|p| p.version.to_string())
+    }
+
+    #[tokio::test]
+    async fn prefers_higher_priority_repositories_over_newer_versions() {
+        let fixture = Fixture::new("priority");
+        let mut manager = RepositoryManager::new();
+        manager.add_repository(serve(&fixture, "extra", 0, &[("app", "2.0.0"), ("tool", "1.0.0")]).await);
+        manager.add_repository(serve(&fixture, "main", 10, &[("app", "1.0.0"), ("app", "1.1.0")]).await);
+        let names: Vec<_> = manager.get_repositories().iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["main", "extra"]);
+
+        assert_eq!(best(&manager, "app").as_deref(), Some("1.1.0"));
+        assert_eq!(best(&manager, "tool").as_deref(), Some("1.0.0"));
+        let indexes = manager.fetch_indexes().await.unwrap();
+        let listed = |index: &PackageIndex| {
+            index.packages.iter().map(|p| format!("{}-{}", p.name, p.version)).collect::<Vec<_>>()
+        };
+        assert_eq!(listed(&indexes[0]), ["app-1.0.0", "app-1.1.0"]);
+        // The lower-priority repository still serves what only it offers.
+        assert_eq!(listed(&indexes[1]), ["tool-1.0.0"]);
+        assert_eq!(manager.source_of(&Package::for_test("tool", "1.0.0")).unwrap().name, "extra");
+    }
+
+    #[tokio::test]
+    async fn pins_override_priority_and_newer_versions() {
+        let fixture = Fixture::new("pins");
+        let mut manager = RepositoryManager::new();
+        manager.add_repository(serve(&fixture, "main", 10, &[("app", "1.0.0"), ("app-data", "1.0.0")]).await);
+        manager.add_repository(serve(&fixture, "extra", 0, &[("app", "2.0.0"), ("app", "3.0.0")]).await);
+
+        manager.set_pins(vec![pin("app", "extra", Some("<3"))]);
+        assert_eq!(best(&manager, "app").as_deref(), Some("2.0.0"));
+        let indexes = manager.fetch_indexes().await.unwrap();
+        assert!(indexes[0].packages.iter().all(|p| p.name != "app"));
+        assert_eq!(indexes[1].packages.len(), 1);
+        assert_eq!(best(&manager, "app-data").as_deref(), Some("1.0.0"));
+
+        // A prefix pin covers every matching name, and nothing outside the
+        // pinned repository can satisfy it.
+        manager.set_pins(vec![pin("app*", "extra", None)]);
+        assert_eq!(best(&manager, "app").as_deref(), Some("3.0.0"));
+        assert_eq!(best(&manager, "app-data"), None);
+        assert!(!manager.allows(&manager.get_repositories()[0], &Package::for_test("app", "1.0.0")));
+    }
+}
@@ -0,0 +1,699 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::package::{Dependency, PackageIndex};
+use crate::{Architecture, Channel, InstalledPackage, Package, PpmError, Result};
+
+const MAX_RESOLUTION_STEPS: usize = 100_000;
+
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub packages: Vec<Package>,
+    // Optional features enabled on each selected package.
+    pub features: HashMap<String, Vec<String>>,
+    // Installed packages that a selected package replaces.
+    pub replaced: Vec<String>,
+}
+
+impl Resolution {
+    pub fn get(&self, name: &str) -> Option<&Package> {
+        self.packages.iter().find(|p| p.name == name)
+    }
+
+    pub fn features(&self, name: &str) -> &[String] {
+        self.features.get(name).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Pending {
+    dependency: Dependency,
+    chain: Vec<String>,
+    // Recommendations are pulled in when they fit and dropped otherwise.
+    weak: bool,
+}
+
+impl Pending {
+    fn describe_chain(&self) -> String {
+        if self.chain.is_empty() {
+            "requested".to_string()
+        } else {
+            format!("required by {}", self.chain.join(" -> "))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Selected {
+    package: Package,
+    chain: Vec<String>,
+    features: BTreeSet<String>,
+}
+
+pub struct Resolver {
+    candidates: HashMap<String, Vec<Package>>,
+    providers: HashMap<String, Vec<Package>>,
+    installed: Vec<InstalledPackage>,
+    recommends: bool,
+}
+
+impl Resolver {
+    pub fn new(indexes: &[PackageIndex], architecture: Architecture, channel: Channel) -> Self {
+        let mut candidates: HashMap<String, Vec<Package>> = HashMap::new();
+        for index in indexes {
+            for pkg in &index.packages {
+                if pkg.architecture != architecture || pkg.channel != channel {
+                    continue;
+                }
+                let versions = candidates.entry(pkg.name.clone()).or_default();
+                if !versions.iter().any(|p| p.version == pkg.version) {
+                    versions.push(pkg.clone());
+                }
+            }
+        }
+        for versions in candidates.values_mut() {
+            versions.sort_by(|a, b| b.version.cmp(&a.version));
+        }
+
+        let mut providers: HashMap<String, Vec<Package>> = HashMap::new();
+        for pkg in candidates.values().flatten() {
+            for provide in pkg.provides.iter().filter(|p| p.name != pkg.name) {
+                providers.entry(provide.name.clone()).or_default().push(pkg.clone());
+            }
+        }
+        for versions in providers.values_mut() {
+            versions.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| b.version.cmp(&a.version)));
+        }
+
+        Self {
+            candidates,
+            providers,
+            installed: Vec::new(),
+            recommends: true,
+        }
+    }
+
+    // Installed packages are checked for conflicts, keep their own
+    // dependencies satisfied unless they are upgraded or replaced too, and
+    // satisfy dependencies with the version already there when it fits.
+    pub fn with_installed<'a>(mut self, installed: impl IntoIterator<Item = &'a InstalledPackage>) -> Self {
+        self.installed = installed.into_iter().cloned().collect();
+        self
+    }
+
+    pub fn with_recommends(mut self, recommends: bool) -> Self {
+        self.recommends = recommends;
+        self
+    }
+
+    pub fn candidates(&self, name: &str) -> &[Package] {
+        self.candidates.get(name).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn providers(&self, name: &str) -> &[Package] {
+        self.providers.get(name).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn resolve(&self, requests: &[Dependency]) -> Result<Resolution> {
+        let queue: Vec<Pending> = requests
+            .iter()
+            .rev()
+            .map(|dependency| Pending {
+                dependency: dependency.clone(),
+                chain: Vec::new(),
+                weak: false,
+            })
+            .collect();
+
+        let mut steps = 0;
+        let selected = self
+            .solve(queue, HashMap::new(), &mut steps)
+            .map_err(PpmError::DependencyResolution)?;
+
+        let replaced = self
+            .installed
+            .iter()
+            .filter(|installed| {
+                !selected.contains_key(&installed.name)
+                    && selected
+                        .values()
+                        .any(|s| s.package.relations().replaces(&installed.relations()))
+            })
+            .map(|installed| installed.name.clone())
+            .collect();
+        Ok(Resolution {
+            packages: install_order(requests, &selected),
+            features: selected
+                .iter()
+                .filter(|(_, s)| !s.features.is_empty())
+                .map(|(name, s)| (name.clone(), s.features.iter().cloned().collect()))
+                .collect(),
+            replaced,
+        })
+    }
+
+    fn solve(
+        &self,
+        mut queue: Vec<Pending>,
+        mut selected: HashMap<String, Selected>,
+        steps: &mut usize,
+    ) -> std::result::Result<HashMap<String, Selected>, String> {
+        *steps += 1;
+        if *steps > MAX_RESOLUTION_STEPS {
+            return Err(format!(
+                "gave up after {} steps; the dependency graph is too ambiguous",
+                MAX_RESOLUTION_STEPS
+            ));
+        }
+
+        // Dependencies already satisfied by an earlier pick don't open a new
+        // choice point, so drain them here instead of recursing.
+        let pending = loop {
+            let pending = match queue.pop() {
+                Some(pending) => pending,
+                None => return self.check_installed(&selected).map(|()| selected),
+            };
+            if let Some(existing) = selected.get(&pending.dependency.name) {
+                if !pending.dependency.matches(&existing.package.version) {
+                    if pending.weak {
+                        continue;
+                    }
+                    let picked_by = if existing.chain.is_empty() {
+                        "requested directly".to_string()
+                    } else {
+                        format!("selected via {}", existing.chain.join(" -> "))
+                    };
+                    return Err(format!(
+                        "conflict on '{}': '{}' ({}) cannot be met because {} {} was {}",
+                        pending.dependency.name,
+                        pending.dependency,
+                        pending.describe_chain(),
+                        existing.package.name,
+                        existing.package.version,
+                        picked_by
+                    ));
+                }
+            }
+            if let Some(name) = satisfier(&pending.dependency, &selected) {
+                let name = name.to_string();
+                match enable_features(&pending, &name, &mut selected, &mut queue) {
+                    Ok(()) => continue,
+                    Err(_) if pending.weak => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+            if self.installed_provider(&pending, &selected) {
+                continue;
+            }
+            break pending;
+        };
+        let dep = &pending.dependency;
+
+        let real = self.candidates(&dep.name);
+        let providers = self.providers(&dep.name);
+        if real.is_empty() && providers.is_empty() {
+            if pending.weak {
+                return self.solve(queue, selected, steps);
+            }
+            return Err(format!("package '{}' not found ({})", dep.name, pending.describe_chain()));
+        }
+
+        let mut candidates: Vec<&Package> = real
+            .iter()
+            .filter(|p| dep.matches(&p.version))
+            .chain(providers.iter().filter(|p| p.relations().satisfies(dep)))
+            .collect();
+        // A request picks the newest version; a dependency first tries the
+        // one already installed.
+        if !pending.chain.is_empty() {
+            let installed = candidates
+                .iter()
+                .position(|p| self.installed.iter().any(|installed| keeps(installed, p)));
+            if let Some(at) = installed {
+                let kept = candidates.remove(at);
+                candidates.insert(0, kept);
+            }
+        }
+        let mut last_error = None;
+        for candidate in candidates {
+            if let Err(e) = self.check_conflicts(candidate, &selected) {
+                last_error = Some(e);
+                continue;
+            }
+            let mut chain = pending.chain.clone();
+            chain.push(format!("{} {}", candidate.name, candidate.version));
+
+            let mut next_queue = queue.clone();
+            if self.recommends {
+                for dependency in candidate.recommends.iter().rev() {
+                    next_queue.push(Pending {
+                        dependency: dependency.clone(),
+                        chain: chain.clone(),
+                        weak: true,
+                    });
+                }
+            }
+            for dependency in candidate.dependencies.iter().rev() {
+                next_queue.push(Pending {
+                    dependency: dependency.clone(),
+                    chain: chain.clone(),
+                    weak: false,
+                });
+            }
+
+            let mut next_selected = selected.clone();
+            next_selected.insert(
+                candidate.name.clone(),
+                Selected {
+                    package: candidate.clone(),
+                    chain: pending.chain.clone(),
+                    features: BTreeSet::new(),
+                },
+            );
+            if let Err(e) = enable_features(&pending, &candidate.name, &mut next_selected, &mut next_queue) {
+                last_error = Some(e);
+                continue;
+            }
+
+            match self.solve(next_queue, next_selected, steps) {
+                Ok(done) => return Ok(done),
+                Err(e) => {
+                    if *steps > MAX_RESOLUTION_STEPS {
+                        return Err(e);
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        if pending.weak {
+            return self.solve(queue, selected, steps);
+        }
+        Err(last_error.unwrap_or_else(|| {
+            let mut available: Vec<String> = real.iter().map(|p| p.version.to_string()).collect();
+            available.extend(providers.iter().map(|p| format!("{} {}", p.name, p.version)));
+            format!(
+                "no version of '{}' satisfies '{}' ({}); available: {}",
+                dep.name,
+                dep,
+                pending.describe_chain(),
+                available.join(", ")
+            )
+        }))
+    }
+
+    fn check_conflicts(
+        &self,
+        candidate: &Package,
+        selected: &HashMap<String, Selected>,
+    ) -> std::result::Result<(), String> {
+        let relations = candidate.relations();
+        // Only reachable through `provides`: a direct match on the name was
+        // settled before the candidates were listed.
+        if let Some(existing) = selected.get(&candidate.name) {
+            return Err(format!(
+                "{} {} cannot be selected because {} {} already was",
+                candidate.name, candidate.version, existing.package.name, existing.package.version
+            ));
+        }
+        for other in selected.values() {
+            if relations.conflicts_with(&other.package.relations()) {
+                return Err(format!(
+                    "{} {} conflicts with {} {}",
+                    candidate.name, candidate.version, other.package.name, other.package.version
+                ));
+            }
+        }
+        for installed in &self.installed {
+            let theirs = installed.relations();
+            if installed.name == candidate.name
+                || relations.replaces(&theirs)
+                || self.displaced(installed, selected)
+            {
+                continue;
+            }
+            if relations.conflicts_with(&theirs) {
+                return Err(format!(
+                    "{} {} conflicts with installed {} {}",
+                    candidate.name, candidate.version, installed.name, installed.version
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // An installed package is on its way out once a selection upgrades,
+    // downgrades or replaces it; selecting the installed version keeps it.
+    fn displaced(&self, installed: &InstalledPackage, selected: &HashMap<String, Selected>) -> bool {
+        selected.get(&installed.name).is_some_and(|s| !keeps(installed, &s.package))
+            || selected
+                .values()
+                .any(|s| s.package.relations().replaces(&installed.relations()))
+    }
+
+    // Providers satisfy a dependency as installed. So does the installed
+    // package of that name once the index no longer lists its version;
+    // otherwise it is selected from the index like any other candidate.
+    fn installed_provider(&self, pending: &Pending, selected: &HashMap<String, Selected>) -> bool {
+        let dep = &pending.dependency;
+        self.installed.iter().any(|installed| {
+            if self.displaced(installed, selected) {
+                return false;
+            }
+            if installed.name != dep.name {
+                return dep.features.is_empty() && installed.relations().satisfies(dep);
+            }
+            !pending.chain.is_empty()
+                && dep.matches(&installed.version)
+                && dep.features.iter().all(|f| installed.features.contains(f))
+                && !self.candidates(&dep.name).iter().any(|p| keeps(installed, p))
+        })
+    }
+
+    // Every installed package that stays must still find its dependencies,
+    // either in the selection or among the installed packages that stay.
+    fn check_installed(&self, selected: &HashMap<String, Selected>) -> std::result::Result<(), String> {
+        let staying: Vec<&InstalledPackage> =
+            self.installed.iter().filter(|i| !self.displaced(i, selected)).collect();
+        for installed in &staying {
+            for dep in &installed.dependencies {
+                let touched = selected.contains_key(&dep.name)
+                    || self
+                        .installed
+                        .iter()
+                        .any(|i| i.relations().satisfies(dep) && self.displaced(i, selected));
+                if !touched
+                    || selected.values().any(|s| s.package.relations().satisfies(dep))
+                    || staying.iter().any(|i| i.relations().satisfies(dep))
+                {
+                    continue;
+                }
+                let instead = match selected.get(&dep.name) {
+                    Some(s) => format!("{} {} was selected", s.package.name, s.package.version),
+                    None => format!("nothing left provides '{}'", dep.name),
+                };
+                return Err(format!(
+                    "installed {} {} requires '{}', but {}",
+                    installed.name, installed.version, dep, instead
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn keeps(installed: &InstalledPackage, pkg: &Package) -> bool {
+    installed.name == pkg.name && installed.version == pkg.version && installed.architecture == pkg.architecture
+}
+
+fn satisfier<'a>(dep: &Dependency, selected: &'a HashMap<String, Selected>) -> Option<&'a str> {
+    if let Some(existing) = selected.get(&dep.name) {
+        return Some(&existing.package.name);
+    }
+    selected
+        .values()
+        .filter(|s| s.package.relations().satisfies(dep))
+        .map(|s| s.package.name.as_str())
+        .min()
+}
+
+// Turns on the features `pending` asks of the selected package `name`,
+// queueing the dependencies each newly enabled one brings along.
+fn enable_features(
+    pending: &Pending,
+    name: &str,
+    selected: &mut HashMap<String, Selected>,
+    queue: &mut Vec<Pending>,
+) -> std::result::Result<(), String> {
+    let entry = selected.get_mut(name).expect("features are enabled on a selected package");
+    for feature in &pending.dependency.features {
+        if entry.features.contains(feature) {
+            continue;
+        }
+        let deps = entry.package.features.get(feature).ok_or_else(|| {
+            format!(
+                "{} {} has no feature '{}' ({})",
+                entry.package.name,
+                entry.package.version,
+                feature,
+                pending.describe_chain()
+            )
+        })?;
+        let mut chain = entry.chain.clone();
+        chain.push(format!("{}[{}] {}", entry.package.name, feature, entry.package.version));
+        for dependency in deps.iter().rev() {
+            queue.push(Pending {
+                dependency: dependency.clone(),
+                chain: chain.clone(),
+                weak: false,
+            });
+        }
+        entry.features.insert(feature.clone());
+    }
+    Ok(())
+}
+
+fn install_order(requests: &[Dependency], selected: &HashMap<String, Selected>) -> Vec<Package> {
+    fn visit(
+        name: &str,
+        selected: &HashMap<String, Selected>,
+        visited: &mut HashSet<String>,
+        order: &mut Vec<Package>,
+    ) {
+        if !visited.insert(name.to_string()) {
+            return;
+        }
+        let Some(entry) = selected.get(name) else {
+            return;
+        };
+        let pkg = &entry.package;
+        let optional = entry.features.iter().filter_map(|f| pkg.features.get(f)).flatten();
+        for dep in pkg.dependencies.iter().chain(optional).chain(&pkg.recommends) {
+            if let Some(target) = satisfier(dep, selected) {
+                visit(target, selected, visited, order);
+            }
+        }
+        order.push(pkg.clone());
+    }
+
+    let mut visited = HashSet::new();
+    let mut order = Vec::with_capacity(selected.len());
+    for request in requests {
+        if let Some(target) = satisfier(request, selected) {
+            visit(target, selected, &mut visited, &mut order);
+        }
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InstallReason;
+
+    fn deps(specs: &[&str]) -> Vec<Dependency> {
+        specs.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    fn pkg(name: &str, version: &str, dependencies: &[&str]) -> Package {
+        Package {
+            dependencies: deps(dependencies),
+            ..Package::for_test(name, version)
+        }
+    }
+
+    fn installed(pkg: &Package) -> InstalledPackage {
+        InstalledPackage {
+            name: pkg.name.clone(),
+            version: pkg.version.clone(),
+            architecture: pkg.architecture,
+            channel: pkg.channel,
+            reason: InstallReason::Explicit,
+            installed_at: 0,
+            dependencies: pkg.dependencies.clone(),
+            recommends: pkg.recommends.clone(),
+            conflicts: pkg.conflicts.clone(),
+            provides: pkg.provides.clone(),
+            replaces: pkg.replaces.clone(),
+            features: Vec::new(),
+            files: Vec::new(),
+            archive: None,
+            scripts: None,
+            sandbox: None,
+        }
+    }
+
+    fn resolver(packages: Vec<Package>) -> Resolver {
+        let index = PackageIndex {
+            packages,
+            generated: "0".to_string(),
+            channel: Channel::Stable,
+        };
+        Resolver::new(&[index], Architecture::X86_64, Channel::Stable)
+    }
+
+    fn resolve(resolver: &Resolver, requests: &[&str]) -> Result<Vec<String>> {
+        let resolution = resolver.resolve(&deps(requests))?;
+        Ok(resolution.packages.iter().map(|p| format!("{}-{}", p.name, p.version)).collect())
+    }
+
+    fn error(result: Result<Vec<String>>) -> String {
+        match result {
+            Err(PpmError::DependencyResolution(message)) => message,
+            other => panic!("expected a resolution error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn installs_dependencies_first() {
+        let r = resolver(vec![
+            pkg("app", "1.0.0", &["lib >= 1"]),
+            pkg("lib", "1.2.0", &["base"]),
+            pkg("base", "1.0.0", &[]),
+        ]);
+        assert_eq!(resolve(&r, &["app"]).unwrap(), ["base-1.0.0", "lib-1.2.0", "app-1.0.0"]);
+    }
+
+    #[test]
+    fn prefers_the_newest_matching_version() {
+        let r = resolver(vec![pkg("lib", "1.0.0", &[]), pkg("lib", "1.5.0", &[]), pkg("lib", "2.0.0", &[])]);
+        assert_eq!(resolve(&r, &["lib ^1"]).unwrap(), ["lib-1.5.0"]);
+    }
+
+    #[test]
+    fn backtracks_out_of_a_dead_end() {
+        let r = resolver(vec![
+            pkg("app", "2.0.0", &["lib >= 2"]),
+            pkg("app", "1.0.0", &["lib >= 1"]),
+            pkg("tool", "1.0.0", &["lib < 2"]),
+            pkg("lib", "2.0.0", &[]),
+            pkg("lib", "1.0.0", &[]),
+        ]);
+        let mut resolved = resolve(&r, &["app", "tool"]).unwrap();
+        resolved.sort();
+        assert_eq!(resolved, ["app-1.0.0", "lib-1.0.0", "tool-1.0.0"]);
+    }
+
+    #[test]
+    fn keeps_installed_packages_working() {
+        let (lib, tool) = (pkg("lib", "1.0.0", &[]), pkg("tool", "1.0.0", &["lib < 2"]));
+        let r = resolver(vec![
+            pkg("app", "2.0.0", &["lib >= 2"]),
+            pkg("app", "1.0.0", &["lib >= 1"]),
+            lib.clone(),
+            pkg("lib", "2.0.0", &[]),
+            tool.clone(),
+        ])
+        .with_installed([&installed(&lib), &installed(&tool)]);
+        assert_eq!(resolve(&r, &["app"]).unwrap(), ["lib-1.0.0", "app-1.0.0"]);
+
+        let message = error(resolve(&r, &["app >= 2"]));
+        assert!(
+            message.contains("installed tool 1.0.0 requires 'lib <2', but lib 2.0.0 was selected"),
+            "{}",
+            message
+        );
+
+        // Upgrading the dependent along with the library lifts its old limit.
+        let r = resolver(vec![
+            pkg("app", "2.0.0", &["lib >= 2"]),
+            pkg("lib", "2.0.0", &[]),
+            pkg("tool", "2.0.0", &["lib >= 2"]),
+        ])
+        .with_installed([&installed(&lib), &installed(&tool)]);
+        let mut resolved = resolve(&r, &["app", "tool = 2.0.0"]).unwrap();
+        resolved.sort();
+        assert_eq!(resolved, ["app-2.0.0", "lib-2.0.0", "tool-2.0.0"]);
+    }
+
+    #[test]
+    fn prefers_installed_versions_of_dependencies() {
+        let lib = pkg("lib", "1.0.0", &[]);
+        let r = resolver(vec![pkg("app", "1.0.0", &["lib >= 1"]), lib.clone(), pkg("lib", "1.5.0", &[])])
+            .with_installed([&installed(&lib)]);
+        assert_eq!(resolve(&r, &["app"]).unwrap(), ["lib-1.0.0", "app-1.0.0"]);
+        // A request still asks for the newest version.
+        assert_eq!(resolve(&r, &["lib"]).unwrap(), ["lib-1.5.0"]);
+
+        // A version the index has dropped stays as it is.
+        let old = pkg("lib", "0.9.0", &[]);
+        let r = resolver(vec![pkg("app", "1.0.0", &["lib"]), pkg("lib", "1.5.0", &[])])
+            .with_installed([&installed(&old)]);
+        assert_eq!(resolve(&r, &["app"]).unwrap(), ["app-1.0.0"]);
+    }
+
+    #[test]
+    fn explains_unsatisfiable_requirements() {
+        let r = resolver(vec![pkg("app", "1.0.0", &["lib >= 2"]), pkg("lib", "1.0.0", &[])]);
+        let message = error(resolve(&r, &["app"]));
+        assert!(message.contains("no version of 'lib' satisfies 'lib >=2'"), "{}", message);
+        assert!(message.contains("required by app 1.0.0"), "{}", message);
+        assert!(message.contains("available: 1.0.0"), "{}", message);
+
+        let message = error(resolve(&r, &["missing"]));
+        assert!(message.contains("package 'missing' not found (requested)"), "{}", message);
+    }
+
+    #[test]
+    fn refuses_conflicting_selections() {
+        let mut app = pkg("app", "1.0.0", &[]);
+        app.conflicts = deps(&["tool"]);
+        let r = resolver(vec![app, pkg("tool", "1.0.0", &[])]);
+        let message = error(resolve(&r, &["app", "tool"]));
+        assert!(message.contains("conflicts with app 1.0.0"), "{}", message);
+    }
+
+    #[test]
+    fn refuses_conflicts_with_installed_packages_unless_replacing_them() {
+        let old = pkg("sendmail", "1.0.0", &[]);
+        let mut conflicting = pkg("postfix", "1.0.0", &[]);
+        conflicting.conflicts = deps(&["sendmail"]);
+        let r = resolver(vec![conflicting.clone()]).with_installed([&installed(&old)]);
+        let message = error(resolve(&r, &["postfix"]));
+        assert!(message.contains("conflicts with installed sendmail 1.0.0"), "{}", message);
+
+        let mut replacing = conflicting;
+        replacing.replaces = deps(&["sendmail"]);
+        let r = resolver(vec![replacing]).with_installed([&installed(&old)]);
+        let resolution = r.resolve(&deps(&["postfix"])).unwrap();
+        assert_eq!(resolution.replaced, ["sendmail"]);
+    }
+
+    #[test]
+    fn satisfies_virtual_packages_through_providers() {
+        let mut postfix = pkg("postfix", "3.0.0", &[]);
+        postfix.provides = deps(&["mta"]);
+        let r = resolver(vec![pkg("mailer", "1.0.0", &["mta"]), postfix.clone()]);
+        assert_eq!(resolve(&r, &["mailer"]).unwrap(), ["postfix-3.0.0", "mailer-1.0.0"]);
+
+        // An installed provider is good enough and is not reinstalled.
+        let mut exim = pkg("exim", "4.0.0", &[]);
+        exim.provides = deps(&["mta"]);
+        let r = resolver(vec![pkg("mailer", "1.0.0", &["mta"]), postfix]).with_installed([&installed(&exim)]);
+        assert_eq!(resolve(&r, &["mailer"]).unwrap(), ["mailer-1.0.0"]);
+    }
+
+    #[test]
+    fn enables_requested_features_and_their_dependencies() {
+        let mut curl = pkg("curl", "8.0.0", &[]);
+        curl.features.insert("tls".to_string(), deps(&["openssl"]));
+        let r = resolver(vec![pkg("app", "1.0.0", &["curl[tls]"]), curl, pkg("openssl", "3.0.0", &[])]);
+        let resolution = r.resolve(&deps(&["app"])).unwrap();
+        let names: Vec<&str> = resolution.packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["openssl", "curl", "app"]);
+        assert_eq!(resolution.features("curl"), ["tls"]);
+
+        let message = error(resolve(&r, &["curl[http3]"]));
+        assert!(message.contains("curl 8.0.0 has no feature 'http3'"), "{}", message);
+    }
+
+    #[test]
+    fn drops_recommendations_that_do_not_fit() {
+        let mut app = pkg("app", "1.0.0", &[]);
+        app.recommends = deps(&["docs", "extras >= 2"]);
+        let r = resolver(vec![app, pkg("docs", "1.0.0", &[]), pkg("extras", "1.0.0", &[])]);
+        assert_eq!(resolve(&r, &["app"]).unwrap(), ["docs-1.0.0", "app-1.0.0"]);
+
+        let r = r.with_recommends(false);
+        assert_eq!(resolve(&r, &["app"]).unwrap(), ["app-1.0.0"]);
+    }
+}
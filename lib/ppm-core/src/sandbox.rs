@@ -0,0 +1,644 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::package::SandboxConfig;
+use crate::{PpmError, Result};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptRun {
+    pub script: String,
+    pub status: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+// Read-only inside every sandbox so that a shell and its libraries can start.
+// Everything else has to be listed in the package's `allowed_paths`. Both
+// come from the install root, so scripts see the system being assembled.
+#[cfg(target_os = "linux")]
+const SYSTEM_PATHS: &[&str] = &["/bin", "/sbin", "/usr", "/lib", "/lib32", "/lib64", "/etc/ld.so.cache"];
+
+#[cfg(target_os = "linux")]
+const DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/random", "/dev/urandom"];
+
+// What a shell needs to run ordinary commands. None of these reach past the
+// mount and network namespaces; `mount`, `ptrace`, `bpf` and friends stay
+// blocked unless a package lists them in `system_calls`. `clone` is further
+// limited to flags that create no namespaces, and `clone3`, whose flags
+// seccomp cannot see, always fails with ENOSYS so libc falls back to `clone`.
+// `kill` only takes single processes: a process group always includes the
+// helper waiting outside the PID namespace.
+#[cfg(target_os = "linux")]
+const BASE_SYSCALLS: &[&str] = &[
+    "read", "write", "readv", "writev", "pread64", "pwrite64", "lseek", "openat", "close",
+    "close_range", "fstat", "newfstatat", "statx", "statfs", "fstatfs", "faccessat", "faccessat2",
+    "getdents64", "getcwd", "chdir", "fchdir", "mkdirat", "unlinkat", "renameat", "renameat2",
+    "symlinkat", "linkat", "readlinkat", "fchmod", "fchmodat", "fchown", "fchownat", "umask",
+    "utimensat", "truncate", "ftruncate", "fsync", "fdatasync", "ioctl", "fcntl", "dup", "dup3",
+    "pipe2", "ppoll", "pselect6", "epoll_create1", "epoll_ctl", "epoll_pwait", "eventfd2", "brk",
+    "mmap", "munmap", "mremap", "mprotect", "madvise", "membarrier", "rt_sigaction",
+    "rt_sigprocmask", "rt_sigreturn", "rt_sigsuspend", "sigaltstack", "kill", "tgkill", "clone",
+    "execve", "execveat", "wait4", "waitid", "exit", "exit_group", "set_tid_address",
+    "set_robust_list", "rseq", "futex", "sched_yield", "sched_getaffinity", "getrandom",
+    "getpid", "getppid", "gettid", "getuid", "geteuid", "getgid", "getegid", "getgroups",
+    "getpgid", "setpgid", "getrlimit", "prlimit64", "getrusage", "uname", "sysinfo",
+    "clock_gettime", "clock_getres", "clock_nanosleep", "gettimeofday", "nanosleep",
+];
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const ARCH_SYSCALLS: &[&str] = &[
+    "open", "stat", "lstat", "access", "readlink", "getdents", "mkdir", "rmdir", "unlink", "rename",
+    "symlink", "link", "chmod", "chown", "lchown", "pipe", "dup2", "poll", "select", "epoll_wait",
+    "fork", "vfork", "arch_prctl", "getpgrp", "alarm", "time",
+];
+
+#[cfg(all(target_os = "linux", not(target_arch = "x86_64")))]
+const ARCH_SYSCALLS: &[&str] = &[];
+
+#[cfg(target_os = "linux")]
+const NETWORK_SYSCALLS: &[&str] = &[
+    "socket", "socketpair", "connect", "bind", "listen", "accept", "accept4", "sendto",
+    "recvfrom", "sendmsg", "recvmsg", "shutdown", "getsockname", "getpeername", "setsockopt",
+    "getsockopt",
+];
+
+#[cfg(target_os = "linux")]
+const NAMESPACE_FLAGS: libc::c_int = libc::CLONE_NEWUSER
+    | libc::CLONE_NEWNS
+    | libc::CLONE_NEWNET
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWCGROUP;
+
+const SCRIPT_PATH: &str = ".ppm-script";
+
+// Runs package scripts in fresh user, mount, PID, IPC, UTS and (unless the
+// package asks for network access) network namespaces. The script sees a read-only
+// root holding the system directories above, `/dev/null` and friends, a
+// private `/tmp`, and the package's `allowed_paths` bound read-write from
+// `root`. Allowed paths that do not exist yet are left out of the view.
+// With a timeout, a script still running when it expires is killed along
+// with everything it started.
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    config: SandboxConfig,
+    root: PathBuf,
+    work_dir: PathBuf,
+    env: Vec<(String, String)>,
+    timeout: Option<Duration>,
+}
+
+impl Sandbox {
+    pub fn new(config: SandboxConfig, root: impl Into<PathBuf>, work_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            config,
+            root: root.into(),
+            work_dir: work_dir.into(),
+            env: Vec::new(),
+            timeout: None,
+        }
+    }
+
+    pub fn with_env(mut self, key: &str, value: &str) -> Self {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn run(&self, name: &str, script: &str) -> Result<ScriptRun> {
+        use std::os::unix::process::{CommandExt, ExitStatusExt};
+        use std::process::{Command, Stdio};
+
+        let filters = self.seccomp_filters()?;
+        let dir = scratch_dir(&self.work_dir)?;
+        let result = (|| {
+            let setup = self.prepare(&dir, script, filters)?;
+            let mut command = if script.starts_with("#!") {
+                Command::new(format!("/{}", SCRIPT_PATH))
+            } else {
+                let mut command = Command::new("/bin/sh");
+                command.arg(format!("/{}", SCRIPT_PATH));
+                command
+            };
+            command
+                .env_clear()
+                .env("PATH", "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin")
+                .env("PPM_SCRIPT", name)
+                .envs(self.env.iter().map(|(k, v)| (k, v)))
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .process_group(0);
+            // SAFETY: `enter` only makes raw system calls on data prepared
+            // before the fork; it neither allocates nor takes locks.
+            unsafe {
+                command.pre_exec(move || linux::enter(&setup));
+            }
+            let mut child = command
+                .spawn()
+                .map_err(|e| PpmError::SecurityViolation(format!("cannot start the script sandbox: {}", e)))?;
+            let stdout = linux::drain(child.stdout.take());
+            let stderr = linux::drain(child.stderr.take());
+            let status = match self.timeout {
+                None => child.wait()?,
+                Some(timeout) => match linux::wait_timeout(&mut child, timeout)? {
+                    Some(status) => status,
+                    None => {
+                        return Err(PpmError::Script(format!(
+                            "the {} script was killed after running for {:?}",
+                            name, timeout
+                        )));
+                    }
+                },
+            };
+            Ok(ScriptRun {
+                script: name.to_string(),
+                status: status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0)),
+                stdout: String::from_utf8_lossy(&stdout.join().unwrap_or_default()).into_owned(),
+                stderr: String::from_utf8_lossy(&stderr.join().unwrap_or_default()).into_owned(),
+            })
+        })();
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn run(&self, name: &str, _script: &str) -> Result<ScriptRun> {
+        Err(PpmError::SecurityViolation(format!(
+            "cannot run the {} script: package scripts need the Linux sandbox", name
+        )))
+    }
+
+    // Two filters: one that turns `clone3` into ENOSYS and lets everything
+    // else through, then the allow list. The first must go in first since the
+    // allow list blocks `seccomp` itself; the kernel ranks an errno above an
+    // allow, so the allow list passing `clone3` does not undo it.
+    #[cfg(target_os = "linux")]
+    fn seccomp_filters(&self) -> Result<Vec<seccompiler::BpfProgram>> {
+        let mut names = std::collections::BTreeSet::from(["clone3".to_string()]);
+        names.extend(BASE_SYSCALLS.iter().chain(ARCH_SYSCALLS).map(|s| s.to_string()));
+        if self.config.network_access {
+            names.extend(NETWORK_SYSCALLS.iter().map(|s| s.to_string()));
+        }
+        for name in &self.config.system_calls {
+            if name.is_empty() || !name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_') {
+                return Err(PpmError::InvalidPackage(format!("invalid system call name '{}'", name)));
+            }
+            names.insert(name.clone());
+        }
+
+        // Anything off the list fails with EPERM rather than killing the
+        // script, so it can report what it was not allowed to do. Listing
+        // `clone` or `kill` in `system_calls` does not lift their checks.
+        let rules: Vec<String> = names
+            .iter()
+            .map(|n| match n.as_str() {
+                "clone" => format!(
+                    "{{\"syscall\":\"clone\",\"args\":[{{\"index\":0,\"type\":\"qword\",\"op\":{{\"masked_eq\":{}}},\"val\":0}}]}}",
+                    NAMESPACE_FLAGS
+                ),
+                "kill" => "{\"syscall\":\"kill\",\"args\":[\
+                           {\"index\":0,\"type\":\"dword\",\"op\":{\"masked_eq\":2147483648},\"val\":0},\
+                           {\"index\":0,\"type\":\"dword\",\"op\":\"ne\",\"val\":0}]}"
+                    .to_string(),
+                _ => format!("{{\"syscall\":\"{}\"}}", n),
+            })
+            .collect();
+        let json = format!(
+            "{{\"script\":{{\"mismatch_action\":{{\"errno\":{}}},\"match_action\":\"allow\",\"filter\":[{}]}},\
+             \"clone3\":{{\"mismatch_action\":\"allow\",\"match_action\":{{\"errno\":{}}},\"filter\":[{{\"syscall\":\"clone3\"}}]}}}}",
+            libc::EPERM,
+            rules.join(","),
+            libc::ENOSYS
+        );
+        let arch = std::env::consts::ARCH
+            .try_into()
+            .map_err(|e| PpmError::SecurityViolation(format!("no seccomp support for this architecture: {}", e)))?;
+        let mut filters = seccompiler::compile_from_json(json.as_bytes(), arch)
+            .map_err(|e| PpmError::InvalidPackage(format!("cannot build the seccomp filter: {}", e)))?;
+        ["clone3", "script"]
+            .into_iter()
+            .map(|name| {
+                filters
+                    .remove(name)
+                    .ok_or_else(|| PpmError::SecurityViolation("seccomp filter missing".to_string()))
+            })
+            .collect()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn prepare(&self, dir: &Path, script: &str, filters: Vec<seccompiler::BpfProgram>) -> Result<linux::Setup> {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let script_path = dir.join(SCRIPT_PATH);
+        fs::write(&script_path, script)?;
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755))?;
+        fs::create_dir(dir.join("tmp"))?;
+
+        let mut binds = Vec::new();
+        let mut add = |source: PathBuf, relative: &str, read_only: bool| -> Result<()> {
+            let Ok(meta) = fs::metadata(&source) else {
+                return Ok(());
+            };
+            binds.push(linux::Bind::new(&source, dir, Path::new(relative), meta.is_dir(), read_only)?);
+            Ok(())
+        };
+        for path in SYSTEM_PATHS {
+            let relative = path.trim_start_matches('/');
+            add(self.root.join(relative), relative, true)?;
+        }
+        for path in DEVICES {
+            add(PathBuf::from(path), path.trim_start_matches('/'), false)?;
+        }
+        for path in &self.config.allowed_paths {
+            let relative = crate::formats::writer::normalize_path(path)?;
+            add(self.root.join(&relative), &relative, false)?;
+        }
+        linux::Setup::new(dir, binds, !self.config.network_access, filters)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn scratch_dir(work_dir: &Path) -> Result<PathBuf> {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let dir = work_dir.join(format!("{}-{}", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ffi::{CStr, CString};
+    use std::io::{self, Read};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::process::{Child, ExitStatus};
+    use std::thread::JoinHandle;
+    use std::time::{Duration, Instant};
+
+    use crate::{PpmError, Result};
+
+    pub struct Bind {
+        source: CString,
+        target: CString,
+        // Mount points are made inside the namespace, once earlier mounts
+        // (the private /tmp in particular) are in place.
+        dirs: Vec<CString>,
+        file: bool,
+        // Flags the remount has to repeat; dropping ones inherited from a
+        // more privileged namespace makes the kernel refuse it.
+        remount: Option<libc::c_ulong>,
+    }
+
+    impl Bind {
+        pub fn new(source: &Path, root: &Path, relative: &Path, is_dir: bool, read_only: bool) -> Result<Self> {
+            let source = c_path(source)?;
+            let remount = if read_only { Some(mount_flags(&source)? | libc::MS_RDONLY) } else { None };
+            let mut dirs: Vec<&Path> = relative.ancestors().filter(|p| !p.as_os_str().is_empty()).collect();
+            if !is_dir {
+                dirs.remove(0);
+            }
+            Ok(Self {
+                source,
+                target: c_path(&root.join(relative))?,
+                dirs: dirs.iter().rev().map(|d| c_path(&root.join(d))).collect::<Result<_>>()?,
+                file: !is_dir,
+                remount,
+            })
+        }
+    }
+
+    pub struct Setup {
+        root: CString,
+        root_flags: libc::c_ulong,
+        tmp: CString,
+        binds: Vec<Bind>,
+        namespaces: libc::c_int,
+        uid_map: Vec<u8>,
+        gid_map: Vec<u8>,
+        filters: Vec<seccompiler::BpfProgram>,
+    }
+
+    impl Setup {
+        pub fn new(
+            dir: &Path,
+            binds: Vec<Bind>,
+            isolate_network: bool,
+            filters: Vec<seccompiler::BpfProgram>,
+        ) -> Result<Self> {
+            let root = c_path(dir)?;
+            let mut namespaces =
+                libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWPID | libc::CLONE_NEWIPC | libc::CLONE_NEWUTS;
+            if isolate_network {
+                namespaces |= libc::CLONE_NEWNET;
+            }
+            // SAFETY: getuid and getgid cannot fail.
+            let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+            Ok(Self {
+                root_flags: mount_flags(&root)? | libc::MS_RDONLY,
+                tmp: c_path(&dir.join("tmp"))?,
+                root,
+                binds,
+                namespaces,
+                uid_map: format!("0 {} 1", uid).into_bytes(),
+                gid_map: format!("0 {} 1", gid).into_bytes(),
+                filters,
+            })
+        }
+    }
+
+    // Runs in the forked child right before exec.
+    pub fn enter(setup: &Setup) -> io::Result<()> {
+        // SAFETY: plain system calls on NUL-terminated strings owned by
+        // `setup`, which outlives them.
+        unsafe {
+            check(libc::unshare(setup.namespaces))?;
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/uid_map", &setup.uid_map)?;
+            write_file(c"/proc/self/gid_map", &setup.gid_map)?;
+
+            // Only children join the new PID namespace, so the script runs in
+            // one, as its PID 1, while this process waits outside and exits
+            // with its status. The copy of std's exec error pipe held here
+            // has to go, or `spawn` would not return until the script ends.
+            let pid = libc::fork();
+            check(pid)?;
+            if pid > 0 {
+                if libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0) < 0 {
+                    for fd in 3..1024 {
+                        libc::close(fd);
+                    }
+                }
+                let mut status = 0;
+                while libc::waitpid(pid, &mut status, 0) < 0 && *libc::__errno_location() == libc::EINTR {}
+                libc::_exit(if libc::WIFEXITED(status) {
+                    libc::WEXITSTATUS(status)
+                } else {
+                    128 + libc::WTERMSIG(status)
+                });
+            }
+
+            let none = std::ptr::null::<libc::c_char>();
+            check(libc::mount(none, c"/".as_ptr(), none, libc::MS_REC | libc::MS_PRIVATE, std::ptr::null()))?;
+            check(libc::mount(setup.root.as_ptr(), setup.root.as_ptr(), none, libc::MS_BIND, std::ptr::null()))?;
+            let flags = libc::MS_NOSUID | libc::MS_NODEV;
+            check(libc::mount(c"tmpfs".as_ptr(), setup.tmp.as_ptr(), c"tmpfs".as_ptr(), flags, c"mode=1777".as_ptr().cast()))?;
+            for bind in &setup.binds {
+                // Failures here surface as the mount below failing.
+                for dir in &bind.dirs {
+                    libc::mkdir(dir.as_ptr(), 0o755);
+                }
+                if bind.file {
+                    let fd = libc::open(bind.target.as_ptr(), libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC, 0o644);
+                    if fd >= 0 {
+                        libc::close(fd);
+                    }
+                }
+                let flags = libc::MS_BIND | libc::MS_REC;
+                check(libc::mount(bind.source.as_ptr(), bind.target.as_ptr(), none, flags, std::ptr::null()))?;
+                if let Some(extra) = bind.remount {
+                    let flags = libc::MS_REMOUNT | libc::MS_BIND | extra;
+                    check(libc::mount(none, bind.target.as_ptr(), none, flags, std::ptr::null()))?;
+                }
+            }
+            let flags = libc::MS_REMOUNT | libc::MS_BIND | setup.root_flags;
+            check(libc::mount(none, setup.root.as_ptr(), none, flags, std::ptr::null()))?;
+
+            check(libc::chdir(setup.root.as_ptr()))?;
+            check(libc::syscall(libc::SYS_pivot_root, c".".as_ptr(), c".".as_ptr()) as libc::c_int)?;
+            check(libc::umount2(c".".as_ptr(), libc::MNT_DETACH))?;
+            check(libc::chdir(c"/".as_ptr()))?;
+
+            // Root inside the namespace would otherwise get every capability
+            // back on exec, including the ones needed to undo the mounts.
+            for cap in 0..64 {
+                if libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) < 0 {
+                    break;
+                }
+            }
+        }
+        for filter in &setup.filters {
+            seccompiler::apply_filter(filter).map_err(|_| io::Error::last_os_error())?;
+        }
+        Ok(())
+    }
+
+    pub fn drain(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+        std::thread::spawn(move || {
+            let mut data = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut data);
+            }
+            data
+        })
+    }
+
+    // Polls rather than blocks so the deadline can be enforced. On expiry the
+    // whole process group goes, since the script's children outlive it
+    // otherwise, and `None` is returned.
+    pub fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(status));
+            }
+            if Instant::now() >= deadline {
+                // SAFETY: kill has no memory-safety preconditions; the child
+                // leads its own process group.
+                unsafe {
+                    libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                }
+                child.wait()?;
+                return Ok(None);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn check(rc: libc::c_int) -> io::Result<()> {
+        if rc < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    unsafe fn write_file(path: &CStr, data: &[u8]) -> io::Result<()> {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        check(fd)?;
+        let written = libc::write(fd, data.as_ptr().cast(), data.len());
+        libc::close(fd);
+        if written < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn c_path(path: &Path) -> Result<CString> {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| PpmError::InvalidPackage(format!("invalid path {}", path.display())))
+    }
+
+    fn mount_flags(path: &CStr) -> Result<libc::c_ulong> {
+        // SAFETY: `stat` is a plain output buffer for statvfs to fill in.
+        let stat = unsafe {
+            let mut stat: libc::statvfs = std::mem::zeroed();
+            if libc::statvfs(path.as_ptr(), &mut stat) < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            stat
+        };
+        let map = [
+            (libc::ST_NOSUID, libc::MS_NOSUID),
+            (libc::ST_NODEV, libc::MS_NODEV),
+            (libc::ST_NOEXEC, libc::MS_NOEXEC),
+            (libc::ST_NOATIME, libc::MS_NOATIME),
+            (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+            (libc::ST_RELATIME, libc::MS_RELATIME),
+        ];
+        Ok(map
+            .iter()
+            .filter(|(st, _)| stat.f_flag & st != 0)
+            .fold(0, |flags, (_, ms)| flags | ms))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::package::TestDir;
+    use std::fs;
+    use std::time::Instant;
+
+    struct Fixture {
+        dir: TestDir,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = TestDir::new("sandbox", name);
+            fs::create_dir_all(&dir).unwrap();
+            Self { dir }
+        }
+
+        // Scripts run against the host's own system directories.
+        fn sandbox(&self, config: SandboxConfig) -> Sandbox {
+            Sandbox::new(config, "/", self.dir.join("work"))
+        }
+
+        // User namespaces are often disabled in containers and CI; without
+        // them no script can run and there is nothing to check.
+        fn available(&self) -> bool {
+            match self.sandbox(SandboxConfig::default()).run("probe", "exit 0") {
+                Ok(run) => run.status == 0,
+                Err(e) => {
+                    eprintln!("skipping sandbox test: {}", e);
+                    false
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn confines_scripts_to_allowed_paths() {
+        let fixture = Fixture::new("paths");
+        if !fixture.available() {
+            return;
+        }
+        let allowed = fixture.dir.join("allowed");
+        let hidden = fixture.dir.join("hidden");
+        fs::create_dir_all(&allowed).unwrap();
+        fs::create_dir_all(&hidden).unwrap();
+        fs::write(hidden.join("secret"), "secret").unwrap();
+        let config = SandboxConfig { allowed_paths: vec![allowed.display().to_string()], ..Default::default() };
+        let sandbox = fixture.sandbox(config);
+
+        // Forks and pipes still work with `clone` restricted.
+        let run = sandbox.run("post-install", &format!("echo written | cat > {}/out", allowed.display())).unwrap();
+        assert_eq!(run.status, 0, "{}", run.stderr);
+        assert_eq!(fs::read_to_string(allowed.join("out")).unwrap(), "written\n");
+
+        let run = sandbox.run("post-install", &format!("cat {}/secret", hidden.display())).unwrap();
+        assert_ne!(run.status, 0);
+        assert!(!run.stdout.contains("secret"));
+        let run = sandbox.run("post-install", "touch /usr/ppm-sandbox-test").unwrap();
+        assert_ne!(run.status, 0);
+        assert!(!Path::new("/usr/ppm-sandbox-test").exists());
+    }
+
+    #[test]
+    fn blocks_system_calls_outside_the_allow_list() {
+        let fixture = Fixture::new("syscalls");
+        if !fixture.available() || !Path::new("/usr/bin/unshare").exists() {
+            return;
+        }
+        let script = "unshare --user true";
+        let run = fixture.sandbox(SandboxConfig::default()).run("post-install", script).unwrap();
+        assert_ne!(run.status, 0);
+        assert!(run.stderr.contains("Operation not permitted"), "{}", run.stderr);
+
+        let config = SandboxConfig { system_calls: vec!["unshare".to_string()], ..Default::default() };
+        let run = fixture.sandbox(config).run("post-install", script).unwrap();
+        assert_eq!(run.status, 0, "{}", run.stderr);
+
+        // Nor can `clone` create namespaces; with the check missing, both the
+        // parent and the child exit 1.
+        #[cfg(target_arch = "x86_64")]
+        if Path::new("/usr/bin/perl").exists() {
+            let script = format!(
+                "perl -e 'exit(syscall({}, {} | 17, 0, 0, 0, 0) < 0 && $!{{EPERM}} ? 0 : 1)'",
+                libc::SYS_clone,
+                libc::CLONE_NEWUSER
+            );
+            let config = SandboxConfig { system_calls: vec!["clone".to_string()], ..Default::default() };
+            let run = fixture.sandbox(config).run("post-install", &script).unwrap();
+            assert_eq!(run.status, 0, "{}", run.stderr);
+        }
+
+        let config = SandboxConfig { system_calls: vec!["Mount!".to_string()], ..Default::default() };
+        assert!(matches!(fixture.sandbox(config).run("post-install", "true"), Err(PpmError::InvalidPackage(_))));
+    }
+
+    #[test]
+    fn cannot_signal_processes_outside_the_sandbox() {
+        let fixture = Fixture::new("signals");
+        if !fixture.available() {
+            return;
+        }
+        let sandbox = fixture.sandbox(SandboxConfig::default());
+        let run = sandbox.run("post-install", "kill -0 $PPID").unwrap();
+        assert_ne!(run.status, 0);
+        let run = sandbox.run("post-install", &format!("kill -0 {}", std::process::id())).unwrap();
+        assert_ne!(run.status, 0);
+
+        // The script's own children are still fair game.
+        let run = sandbox.run("post-install", "sleep 30 & kill $! && wait $!; [ $? -gt 128 ]").unwrap();
+        assert_eq!(run.status, 0, "{}", run.stderr);
+    }
+
+    #[test]
+    fn kills_scripts_that_outlive_their_timeout() {
+        let fixture = Fixture::new("timeout");
+        if !fixture.available() {
+            return;
+        }
+        let sandbox = fixture.sandbox(SandboxConfig::default()).with_timeout(Duration::from_millis(300));
+        let started = Instant::now();
+        match sandbox.run("post-install", "sleep 30 & sleep 30") {
+            Err(PpmError::Script(message)) => assert!(message.contains("killed"), "{}", message),
+            other => panic!("expected the script to be killed, got {:?}", other),
+        }
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(sandbox.run("post-install", "echo done").unwrap().stdout, "done\n");
+    }
+}
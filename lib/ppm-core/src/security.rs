@@ -31,7 +31,7 @@ pub fn generate_keypair() -> (SigningKey, VerifyingKey) {
 
     #[cfg(not(target_os = "none"))]
     {
-        let mut rng = rand::thread_rng();
+        let mut rng = rand::rng();
         rng.fill_bytes(&mut secret_key_bytes);
     }
 
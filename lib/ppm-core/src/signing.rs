@@ -0,0 +1,321 @@
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::formats::plpm::{PlpmEntry, PlpmHeader, PlpmMetadataBlock, PlpmSections};
+use crate::formats::writer::normalize_path;
+use crate::formats::{PlpmPackage, PlpmReader};
+use crate::{compute_checksum, verify_signature, PpmError, Result};
+
+pub const SIGNATURE_SCHEME: &str = "ed25519";
+const DIGEST_DOMAIN: &[u8] = b"PLPM-SIGNATURE-V1\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SignaturePolicy {
+    #[default]
+    #[serde(rename = "require")]
+    Require,
+    #[serde(rename = "allow-unsigned")]
+    AllowUnsigned,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    Signed { key_id: String },
+    Unsigned,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageSignature {
+    pub key_id: String,
+    pub signature: [u8; 64],
+}
+
+impl fmt::Display for PackageSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", SIGNATURE_SCHEME, self.key_id, hex::encode(self.signature))
+    }
+}
+
+impl FromStr for PackageSignature {
+    type Err = PpmError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let malformed = || PpmError::SecurityViolation(format!("malformed package signature '{}'", s));
+        let mut parts = s.trim().splitn(3, ':');
+        let (Some(scheme), Some(key_id), Some(sig)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(malformed());
+        };
+        if scheme != SIGNATURE_SCHEME {
+            return Err(PpmError::SecurityViolation(format!(
+                "unsupported signature scheme '{}'", scheme
+            )));
+        }
+        let signature = hex::decode(sig)
+            .ok()
+            .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+            .ok_or_else(malformed)?;
+        Ok(Self {
+            key_id: key_id.to_string(),
+            signature,
+        })
+    }
+}
+
+pub fn key_fingerprint(key: &VerifyingKey) -> String {
+    compute_checksum(key.as_bytes())[..32].to_string()
+}
+
+// The signed digest covers the header with its section table zeroed, the
+// metadata block exactly as stored, and every file's path, permissions, size
+// and content checksum. Offsets and stored sizes are left out: each file is
+// verified against its checksum on extraction anyway.
+pub fn package_digest(header: &PlpmHeader, metadata_block: &[u8], entries: &[PlpmEntry]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(DIGEST_DOMAIN);
+    hasher.update(header.to_bytes(&PlpmSections::default()));
+    hasher.update((metadata_block.len() as u64).to_le_bytes());
+    hasher.update(metadata_block);
+    hasher.update((entries.len() as u64).to_le_bytes());
+    for entry in entries {
+        hasher.update((entry.path.len() as u64).to_le_bytes());
+        hasher.update(entry.path.as_bytes());
+        hasher.update(entry.permissions.to_le_bytes());
+        hasher.update(entry.size.to_le_bytes());
+        hasher.update(entry.checksum.as_bytes());
+    }
+    hasher.finalize().into()
+}
+
+pub fn sign_digest(digest: &[u8; 32], key: &SigningKey) -> PackageSignature {
+    PackageSignature {
+        key_id: key_fingerprint(&key.verifying_key()),
+        signature: key.sign(digest).to_bytes(),
+    }
+}
+
+impl PlpmPackage {
+    pub fn digest(&self) -> Result<[u8; 32]> {
+        let block = toml::to_string(&PlpmMetadataBlock {
+            metadata: self.metadata.clone(),
+            scripts: self.scripts.clone(),
+        })
+        .map_err(|e| PpmError::Serialization(e.to_string()))?;
+        let entries = self
+            .files
+            .iter()
+            .map(|f| {
+                Ok(PlpmEntry {
+                    path: normalize_path(&f.path)?,
+                    permissions: f.permissions,
+                    offset: 0,
+                    size: f.data.len() as u64,
+                    stored_size: 0,
+                    checksum: compute_checksum(&f.data),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(package_digest(&self.header, block.as_bytes(), &entries))
+    }
+
+    pub fn sign(&mut self, key: &SigningKey) -> Result<()> {
+        let digest = self.digest()?;
+        self.signature = Some(sign_digest(&digest, key).to_string());
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> PlpmReader<R> {
+    pub fn digest(&self) -> [u8; 32] {
+        package_digest(self.header(), self.metadata_block(), self.entries())
+    }
+}
+
+// Replaces the trailing signature block of a `.plpm` file in place.
+pub fn sign_package_file(path: impl AsRef<Path>, key: &SigningKey) -> Result<PackageSignature> {
+    let path = path.as_ref();
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let (header, mut sections, digest) = {
+        let reader = PlpmReader::new(&mut file)?;
+        (reader.header().clone(), *reader.sections(), reader.digest())
+    };
+    if sections.signature.end() != Some(fs::metadata(path)?.len()) {
+        return Err(PpmError::InvalidPackage(
+            "signature block is not at the end of the file".to_string(),
+        ));
+    }
+
+    let signature = sign_digest(&digest, key);
+    let encoded = signature.to_string();
+    file.set_len(sections.signature.offset)?;
+    file.seek(SeekFrom::Start(sections.signature.offset))?;
+    file.write_all(encoded.as_bytes())?;
+    sections.signature.length = encoded.len() as u64;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header.to_bytes(&sections))?;
+    file.sync_all()?;
+    Ok(signature)
+}
+
+pub fn verify_digest(
+    digest: &[u8; 32],
+    signature: Option<&str>,
+    trusted: &[VerifyingKey],
+    policy: SignaturePolicy,
+) -> Result<Verification> {
+    let Some(signature) = signature else {
+        return match policy {
+            SignaturePolicy::AllowUnsigned => Ok(Verification::Unsigned),
+            SignaturePolicy::Require => Err(PpmError::SecurityViolation(
+                "package is not signed and unsigned packages are not allowed".to_string(),
+            )),
+        };
+    };
+
+    let signature: PackageSignature = signature.parse()?;
+    let key = trusted
+        .iter()
+        .find(|k| key_fingerprint(k) == signature.key_id)
+        .ok_or_else(|| {
+            PpmError::SecurityViolation(format!(
+                "package is signed by key {} which is not in the trusted keyring",
+                signature.key_id
+            ))
+        })?;
+    verify_signature(digest, &signature.signature, key.as_bytes()).map_err(|_| {
+        PpmError::SecurityViolation(format!(
+            "signature by key {} does not match the package contents",
+            signature.key_id
+        ))
+    })?;
+    Ok(Verification::Signed {
+        key_id: signature.key_id,
+    })
+}
+
+pub fn verify_package<R: Read + Seek>(
+    reader: &PlpmReader<R>,
+    trusted: &[VerifyingKey],
+    policy: SignaturePolicy,
+) -> Result<Verification> {
+    verify_digest(&reader.digest(), reader.signature(), trusted, policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::TestDir;
+    use crate::formats::plpm::PlpmFile;
+    use crate::{Channel, Package};
+    use std::io::BufReader;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn package() -> PlpmPackage {
+        let files = vec![PlpmFile {
+            path: "usr/bin/demo".to_string(),
+            data: b"demo".to_vec(),
+            permissions: 0o755,
+            checksum: compute_checksum(b"demo"),
+        }];
+        PlpmPackage::new(Package::for_test("demo", "1.0.0"), files)
+    }
+
+    fn violation(result: Result<Verification>) -> String {
+        match result {
+            Err(PpmError::SecurityViolation(message)) => message,
+            other => panic!("expected a security violation, got {:?}", other),
+        }
+    }
+
+    fn verify(package: &PlpmPackage, trusted: &[VerifyingKey]) -> Result<Verification> {
+        verify_digest(&package.digest()?, package.signature.as_deref(), trusted, SignaturePolicy::Require)
+    }
+
+    #[test]
+    fn signs_and_verifies_package_files() {
+        let dir = TestDir::new("signing", "sign");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("demo.plpm");
+        package().save(&path).unwrap();
+        let signer = key(1);
+        let signature = sign_package_file(&path, &signer).unwrap();
+        assert_eq!(signature.key_id, key_fingerprint(&signer.verifying_key()));
+
+        let reader = PlpmReader::new(BufReader::new(fs::File::open(&path).unwrap())).unwrap();
+        assert_eq!(reader.signature(), Some(signature.to_string().as_str()));
+        let trusted = [key(2).verifying_key(), signer.verifying_key()];
+        let verified = verify_package(&reader, &trusted, SignaturePolicy::Require);
+        assert_eq!(verified.unwrap(), Verification::Signed { key_id: signature.key_id });
+        // The signed file still reads back in full.
+        assert_eq!(PlpmPackage::load(&path).unwrap().files[0].data, b"demo");
+    }
+
+    #[test]
+    fn rejects_tampered_packages() {
+        let signer = key(1);
+        let mut signed = package();
+        signed.sign(&signer).unwrap();
+        verify(&signed, &[signer.verifying_key()]).unwrap();
+
+        let mut payload = signed.clone();
+        payload.files[0].data = b"evil".to_vec();
+        let message = violation(verify(&payload, &[signer.verifying_key()]));
+        assert!(message.contains("does not match the package contents"), "{}", message);
+
+        let mut header = signed.clone();
+        header.header.channel = Channel::Testing;
+        let message = violation(verify(&header, &[signer.verifying_key()]));
+        assert!(message.contains("does not match the package contents"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_untrusted_and_forged_key_ids() {
+        let (signer, other) = (key(1), key(2));
+        let mut signed = package();
+        signed.sign(&signer).unwrap();
+        let message = violation(verify(&signed, &[other.verifying_key()]));
+        assert!(message.contains("which is not in the trusted keyring"), "{}", message);
+
+        // Claiming a trusted key id does not make someone else's signature its.
+        let mut forged: PackageSignature = signed.signature.as_deref().unwrap().parse().unwrap();
+        forged.key_id = key_fingerprint(&other.verifying_key());
+        signed.signature = Some(forged.to_string());
+        let message = violation(verify(&signed, &[other.verifying_key()]));
+        assert!(message.contains("does not match the package contents"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_malformed_signatures() {
+        let digest = package().digest().unwrap();
+        let trusted = [key(1).verifying_key()];
+        for malformed in ["ed25519", "ed25519:abcd", "ed25519:abcd:not-hex", "ed25519:abcd:00ff"] {
+            let message = violation(verify_digest(&digest, Some(malformed), &trusted, SignaturePolicy::Require));
+            assert!(message.contains("malformed package signature"), "{}: {}", malformed, message);
+        }
+        let message = violation(verify_digest(&digest, Some("rsa:abcd:00"), &trusted, SignaturePolicy::Require));
+        assert!(message.contains("unsupported signature scheme 'rsa'"), "{}", message);
+    }
+
+    #[test]
+    fn applies_the_unsigned_package_policy() {
+        let digest = package().digest().unwrap();
+        let message = violation(verify_digest(&digest, None, &[], SignaturePolicy::Require));
+        assert!(message.contains("unsigned packages are not allowed"), "{}", message);
+        assert_eq!(
+            verify_digest(&digest, None, &[], SignaturePolicy::AllowUnsigned).unwrap(),
+            Verification::Unsigned
+        );
+        // The policy never excuses a bad signature.
+        let bad = sign_digest(&[0; 32], &key(1)).to_string();
+        let trusted = [key(1).verifying_key()];
+        violation(verify_digest(&digest, Some(&bad), &trusted, SignaturePolicy::AllowUnsigned));
+    }
+}
@@ -0,0 +1,989 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use crate::database::{check_package_name, now_timestamp, InstalledFile};
+use crate::formats::plpm::{PlpmEntry, PlpmHeader, PlpmScripts};
+use crate::formats::reader::{entry_error, set_permissions};
+use crate::formats::writer::normalize_path;
+use crate::formats::{PlpmPackage, PlpmReader};
+use crate::{
+    Dependency, Event, Events, InstallReason, InstalledDatabase, InstalledPackage, PackageMetadata, PpmError, Result,
+};
+
+const JOURNAL_FILE: &str = "journal.toml";
+const MAX_LISTED_CONFLICTS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum JournalState {
+    #[serde(rename = "pending")]
+    Pending,
+    #[serde(rename = "committed")]
+    Committed,
+}
+
+// `staged` is the new content waiting next to `target`; `backup` is where the
+// previous content is parked while the transaction is in flight. Both live in
+// the target's directory so every swap is a same-filesystem rename.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileAction {
+    target: PathBuf,
+    existed: bool,
+    staged: Option<PathBuf>,
+    backup: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Journal {
+    id: String,
+    state: JournalState,
+    #[serde(default)]
+    root: PathBuf,
+    #[serde(default)]
+    created_dirs: Vec<PathBuf>,
+    #[serde(default)]
+    actions: Vec<FileAction>,
+    #[serde(default)]
+    installs: Vec<InstalledPackage>,
+    #[serde(default)]
+    removals: Vec<String>,
+    // Other packages' records, rewritten because files moved out of them.
+    #[serde(default)]
+    updates: Vec<InstalledPackage>,
+    // Directories that may be left empty once the transaction commits.
+    #[serde(default)]
+    prune: BTreeSet<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    Clean,
+    RolledBack,
+    RolledForward,
+}
+
+pub struct Transaction {
+    state_dir: PathBuf,
+    journal: Journal,
+    events: Events,
+    overwrite: bool,
+    finished: bool,
+}
+
+impl Transaction {
+    pub fn begin(state_dir: impl AsRef<Path>, root: impl AsRef<Path>) -> Result<Self> {
+        let state_dir = state_dir.as_ref().to_path_buf();
+        fs::create_dir_all(&state_dir)?;
+        if journal_path(&state_dir).exists() {
+            return Err(PpmError::Transaction(
+                "an unfinished transaction is pending; run recovery first".to_string(),
+            ));
+        }
+
+        let tx = Self {
+            state_dir,
+            journal: Journal {
+                id: format!("{}-{}", now_timestamp(), std::process::id()),
+                state: JournalState::Pending,
+                root: root.as_ref().to_path_buf(),
+                created_dirs: Vec::new(),
+                actions: Vec::new(),
+                installs: Vec::new(),
+                removals: Vec::new(),
+                updates: Vec::new(),
+                prune: BTreeSet::new(),
+            },
+            events: Events::default(),
+            overwrite: false,
+            finished: false,
+        };
+        tx.write_journal()?;
+        Ok(tx)
+    }
+
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    // Lets an install take over files that another package owns or that
+    // exist on disk without an owner.
+    pub fn with_overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.journal.id
+    }
+
+    pub fn install(
+        &mut self,
+        package: &PlpmPackage,
+        reason: InstallReason,
+        db: &InstalledDatabase,
+    ) -> Result<()> {
+        let entries: Vec<PlpmEntry> = package
+            .files
+            .iter()
+            .map(|f| PlpmEntry {
+                path: f.path.clone(),
+                permissions: f.permissions,
+                offset: 0,
+                size: f.data.len() as u64,
+                stored_size: f.data.len() as u64,
+                checksum: f.checksum.clone(),
+            })
+            .collect();
+        let scripts = package.scripts.as_ref();
+        self.stage_package(&package.header, &package.metadata, scripts, reason, db, &entries, |i, out| {
+            write_staged(out, &entries[i], &mut package.files[i].data.as_slice(), true)
+        })
+    }
+
+    // Stages files straight out of a `.plpm` on disk without buffering any
+    // payload in memory; the entry reader verifies each checksum as it goes.
+    pub fn install_streaming<R: Read + Seek>(
+        &mut self,
+        reader: &mut PlpmReader<R>,
+        reason: InstallReason,
+        db: &InstalledDatabase,
+    ) -> Result<()> {
+        let header = reader.header().clone();
+        let metadata = reader.metadata().clone();
+        let entries = reader.entries().to_vec();
+        let scripts = reader.scripts().cloned();
+        self.stage_package(&header, &metadata, scripts.as_ref(), reason, db, &entries, |i, out| {
+            let mut source = reader.entry_reader(i)?;
+            write_staged(out, &entries[i], &mut source, false)
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn stage_package(
+        &mut self,
+        header: &PlpmHeader,
+        metadata: &PackageMetadata,
+        scripts: Option<&PlpmScripts>,
+        reason: InstallReason,
+        db: &InstalledDatabase,
+        entries: &[PlpmEntry],
+        mut write: impl FnMut(usize, &Path) -> Result<u64>,
+    ) -> Result<()> {
+        let name = &metadata.name;
+        // Caught only by the database insert, a bad name would fail the
+        // transaction after it was committed, where recovery cannot get past it.
+        check_package_name(name)?;
+        let previous = db.get(name);
+        let reason = match previous {
+            Some(prev) if prev.reason == InstallReason::Explicit => InstallReason::Explicit,
+            _ => reason,
+        };
+
+        let package = format!("{}-{}", name, metadata.version);
+        let mut manifest = Vec::with_capacity(entries.len());
+        let mut targets = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let target = self.target_path(&entry.path)?;
+            manifest.push(InstalledFile {
+                path: self.installed_path(&target),
+                checksum: entry.checksum.clone(),
+                size: entry.size,
+                permissions: entry.permissions,
+            });
+            targets.push(target);
+        }
+
+        // Nothing is extracted over a file that belongs to another package,
+        // is already staged by another package in this transaction, or is on
+        // disk without an owner, unless overwriting is allowed; the paths
+        // taken over are then dropped from their previous owners.
+        let staged: BTreeMap<String, String> = self
+            .journal
+            .installs
+            .iter()
+            .filter(|p| &p.name != name)
+            .flat_map(|p| p.files.iter().map(|f| (f.path.clone(), p.name.clone())))
+            .collect();
+        let mut conflicts = Vec::new();
+        let mut taken: BTreeMap<&str, HashSet<&str>> = BTreeMap::new();
+        let mut taken_staged: BTreeMap<&str, HashSet<&str>> = BTreeMap::new();
+        for (file, target) in manifest.iter().zip(&targets) {
+            let existing = target.symlink_metadata().ok();
+            if existing.as_ref().is_some_and(|m| m.is_dir()) {
+                return Err(PpmError::FileConflict(format!(
+                    "{} ships {}, which is a directory on disk", package, file.path
+                )));
+            }
+            let owners = db.owners(&file.path);
+            // Packages removed by this same transaction give their files up.
+            let others: Vec<&str> = owners
+                .iter()
+                .map(String::as_str)
+                .filter(|o| o != name && !self.journal.removals.iter().any(|r| r == o))
+                .collect();
+            if let Some(owner) = staged.get(&file.path) {
+                conflicts.push(format!("{} (also shipped by {})", file.path, owner));
+                taken_staged.entry(owner).or_default().insert(&file.path);
+            } else if !others.is_empty() {
+                conflicts.push(format!("{} (owned by {})", file.path, others.join(", ")));
+                for owner in others {
+                    taken.entry(owner).or_default().insert(&file.path);
+                }
+            } else if existing.is_some() && owners.is_empty() {
+                conflicts.push(format!("{} (exists on disk)", file.path));
+            }
+        }
+        if !conflicts.is_empty() && !self.overwrite {
+            let shown = conflicts.len().min(MAX_LISTED_CONFLICTS);
+            let mut message = format!("{} would overwrite {}", package, conflicts[..shown].join(", "));
+            if conflicts.len() > shown {
+                message = format!("{} and {} more", message, conflicts.len() - shown);
+            }
+            return Err(PpmError::FileConflict(format!("{}; pass --force to overwrite", message)));
+        }
+        for (owner, paths) in taken {
+            let mut record = match self.journal.updates.iter().position(|p| p.name == owner) {
+                Some(i) => self.journal.updates.remove(i),
+                None => match db.get(owner) {
+                    Some(pkg) => pkg.clone(),
+                    None => continue,
+                },
+            };
+            record.files.retain(|f| !paths.contains(f.path.as_str()));
+            self.journal.updates.push(record);
+        }
+        for install in self.journal.installs.iter_mut() {
+            if let Some(paths) = taken_staged.get(install.name.as_str()) {
+                install.files.retain(|f| !paths.contains(f.path.as_str()));
+            }
+        }
+
+        // Files owned by the version being replaced that the new one no
+        // longer ships are removed as part of the same transaction.
+        let keep: HashSet<&str> = manifest.iter().map(|f| f.path.as_str()).collect();
+        let stale: Vec<PathBuf> = previous
+            .map(|prev| {
+                prev.files
+                    .iter()
+                    .filter(|f| !keep.contains(f.path.as_str()))
+                    .map(|f| self.target_path(&f.path))
+                    .collect::<Result<_>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        let first_action = self.journal.actions.len();
+        for target in &targets {
+            self.plan_dirs(target);
+            let n = self.journal.actions.len();
+            self.journal.actions.push(FileAction {
+                existed: target.symlink_metadata().is_ok(),
+                staged: Some(self.sidecar(target, n, "new")),
+                backup: Some(self.sidecar(target, n, "old")),
+                target: target.clone(),
+            });
+        }
+        for target in stale {
+            self.plan_prune(&target);
+            let n = self.journal.actions.len();
+            self.journal.actions.push(FileAction {
+                existed: true,
+                staged: None,
+                backup: Some(self.sidecar(&target, n, "old")),
+                target,
+            });
+        }
+        self.write_journal()?;
+
+        for dir in &self.journal.created_dirs {
+            fs::create_dir_all(dir)?;
+        }
+        self.events.emit(Event::ExtractStarted { package: package.clone(), files: entries.len() });
+        for (i, target) in targets.iter().enumerate() {
+            let action = &self.journal.actions[first_action + i];
+            let staged = action.staged.as_ref().expect("install actions are staged");
+            manifest[i].size = write(i, staged).map_err(|e| match e {
+                PpmError::InvalidPackage(msg) => {
+                    PpmError::InvalidPackage(format!("{}: {}", target.display(), msg))
+                }
+                other => other,
+            })?;
+            self.events.emit(Event::FileExtracted {
+                package: package.clone(),
+                path: entries[i].path.clone(),
+                index: i + 1,
+                total: entries.len(),
+            });
+        }
+        self.events.emit(Event::ExtractFinished { package });
+
+        self.journal.removals.retain(|n| n != name);
+        self.journal.installs.retain(|p| &p.name != name);
+        self.journal.installs.push(InstalledPackage {
+            name: name.clone(),
+            version: metadata.version.clone(),
+            architecture: header.architecture,
+            channel: header.channel,
+            reason,
+            installed_at: now_timestamp(),
+            dependencies: metadata.dependencies.clone(),
+            recommends: metadata.recommends.clone(),
+            conflicts: metadata.conflicts.clone(),
+            provides: metadata.provides.clone(),
+            replaces: metadata.replaces.clone(),
+            features: Vec::new(),
+            files: manifest,
+            archive: None,
+            scripts: scripts.cloned(),
+            sandbox: metadata.sandbox_config.clone(),
+        });
+        self.write_journal()
+    }
+
+    pub fn record_archive(&mut self, name: &str, checksum: &str) -> Result<()> {
+        let install = self
+            .journal
+            .installs
+            .iter_mut()
+            .find(|p| p.name == name)
+            .ok_or_else(|| PpmError::Transaction(format!("{} is not being installed", name)))?;
+        install.archive = Some(checksum.to_string());
+        self.write_journal()
+    }
+
+    pub fn record_features(&mut self, name: &str, features: &[String], dependencies: &[Dependency]) -> Result<()> {
+        let install = self
+            .journal
+            .installs
+            .iter_mut()
+            .find(|p| p.name == name)
+            .ok_or_else(|| PpmError::Transaction(format!("{} is not being installed", name)))?;
+        install.features = features.to_vec();
+        for dep in dependencies {
+            if !install.dependencies.contains(dep) {
+                install.dependencies.push(dep.clone());
+            }
+        }
+        self.write_journal()
+    }
+
+    // Paths that a package staged in this transaction ships are left alone;
+    // they already belong to the newcomer.
+    pub fn remove(&mut self, package: &InstalledPackage) -> Result<()> {
+        let staged: HashSet<&str> = self
+            .journal
+            .installs
+            .iter()
+            .filter(|p| p.name != package.name)
+            .flat_map(|p| p.files.iter().map(|f| f.path.as_str()))
+            .collect();
+        let targets: Vec<PathBuf> = package
+            .files
+            .iter()
+            .filter(|f| !staged.contains(f.path.as_str()))
+            .map(|f| self.target_path(&f.path))
+            .collect::<Result<_>>()?;
+        for target in targets {
+            self.plan_prune(&target);
+            let n = self.journal.actions.len();
+            self.journal.actions.push(FileAction {
+                existed: true,
+                staged: None,
+                backup: Some(self.sidecar(&target, n, "old")),
+                target,
+            });
+        }
+        self.journal.installs.retain(|p| p.name != package.name);
+        self.journal.updates.retain(|p| p.name != package.name);
+        self.journal.removals.push(package.name.clone());
+        self.write_journal()
+    }
+
+    pub fn commit(mut self, db: &mut InstalledDatabase) -> Result<()> {
+        if let Err(e) = self.apply() {
+            self.finished = true;
+            rollback(&self.journal)?;
+            fs::remove_file(journal_path(&self.state_dir))?;
+            return Err(e);
+        }
+
+        self.journal.state = JournalState::Committed;
+        self.write_journal()?;
+        self.finished = true;
+        roll_forward(&self.state_dir, &self.journal, db)?;
+        self.events.emit(Event::TransactionCommitted {
+            id: self.journal.id.clone(),
+            installed: self.journal.installs.iter().map(|p| format!("{}-{}", p.name, p.version)).collect(),
+            removed: self.journal.removals.clone(),
+        });
+        Ok(())
+    }
+
+    pub fn abort(mut self) -> Result<()> {
+        self.finished = true;
+        rollback(&self.journal)?;
+        fs::remove_file(journal_path(&self.state_dir))?;
+        Ok(())
+    }
+
+    fn apply(&self) -> Result<()> {
+        for action in &self.journal.actions {
+            if let Some(backup) = &action.backup {
+                match fs::rename(&action.target, backup) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            if let Some(staged) = &action.staged {
+                fs::rename(staged, &action.target)?;
+            }
+        }
+        sync_dirs(&self.journal);
+        Ok(())
+    }
+
+    // Symlinked directories on the way are followed only while they stay
+    // inside the install root, and the path returned is the one they lead
+    // to, so nothing done with it later can follow a link out of the root.
+    fn target_path(&self, path: &str) -> Result<PathBuf> {
+        let relative = normalize_path(path)?;
+        let root = &self.journal.root;
+        let mut target = root.clone();
+        let mut parts = Path::new(&relative).components().peekable();
+        while let Some(part) = parts.next() {
+            target.push(part);
+            if parts.peek().is_none() || !target.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink()) {
+                continue;
+            }
+            let resolved = fs::canonicalize(root).and_then(|real_root| {
+                let real = fs::canonicalize(&target)?;
+                Ok(real.strip_prefix(&real_root).map(|rest| root.join(rest)).ok())
+            });
+            target = match resolved {
+                Ok(Some(inside)) => inside,
+                _ => {
+                    return Err(PpmError::SecurityViolation(format!(
+                        "{} leads outside the install root through {}",
+                        path,
+                        target.display()
+                    )))
+                }
+            };
+        }
+        Ok(target)
+    }
+
+    fn installed_path(&self, target: &Path) -> String {
+        installed_path(&self.journal.root, target)
+    }
+
+    fn plan_prune(&mut self, target: &Path) {
+        let mut dir = target.parent();
+        while let Some(d) = dir {
+            if d == self.journal.root || !d.starts_with(&self.journal.root) {
+                break;
+            }
+            self.journal.prune.insert(d.to_path_buf());
+            dir = d.parent();
+        }
+    }
+
+    fn plan_dirs(&mut self, target: &Path) {
+        let mut missing = Vec::new();
+        let mut dir = target.parent();
+        while let Some(d) = dir {
+            if d.as_os_str().is_empty() || d.exists() || self.journal.created_dirs.iter().any(|c| c == d) {
+                break;
+            }
+            missing.push(d.to_path_buf());
+            dir = d.parent();
+        }
+        self.journal.created_dirs.extend(missing.into_iter().rev());
+    }
+
+    fn sidecar(&self, target: &Path, n: usize, suffix: &str) -> PathBuf {
+        let file_name = target
+            .file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        target.with_file_name(format!(".{}.ppm-{}-{}.{}", file_name, self.journal.id, n, suffix))
+    }
+
+    fn write_journal(&self) -> Result<()> {
+        write_journal(&self.state_dir, &self.journal)
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if !self.finished && rollback(&self.journal).is_ok() {
+            let _ = fs::remove_file(journal_path(&self.state_dir));
+        }
+    }
+}
+
+pub fn recover(state_dir: impl AsRef<Path>, db: &mut InstalledDatabase) -> Result<Recovery> {
+    let state_dir = state_dir.as_ref();
+    let path = journal_path(state_dir);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Recovery::Clean),
+        Err(e) => return Err(e.into()),
+    };
+    // Journal writes are atomic renames, so an unreadable journal is corrupt
+    // or from another ppm version, possibly with files already swapped. It
+    // is the only record of how to undo them and stays for manual recovery.
+    let journal: Journal = toml::from_str(&contents).map_err(|e| {
+        PpmError::Transaction(format!(
+            "cannot read the transaction journal {}: {}; resolve it by hand before running ppm again",
+            path.display(),
+            e
+        ))
+    })?;
+
+    match journal.state {
+        JournalState::Pending => {
+            rollback(&journal)?;
+            fs::remove_file(&path)?;
+            Ok(Recovery::RolledBack)
+        }
+        JournalState::Committed => {
+            roll_forward(state_dir, &journal, db)?;
+            Ok(Recovery::RolledForward)
+        }
+    }
+}
+
+fn rollback(journal: &Journal) -> Result<()> {
+    for action in journal.actions.iter().rev() {
+        let backup_present = action.backup.as_ref().is_some_and(|b| b.exists());
+        let staged_present = action.staged.as_ref().is_some_and(|s| s.exists());
+
+        if backup_present {
+            fs::rename(action.backup.as_ref().unwrap(), &action.target)?;
+        } else if !action.existed && action.staged.is_some() && !staged_present {
+            // The new file may have been swapped in and there is nothing to
+            // restore in its place.
+            remove_if_exists(&action.target)?;
+        }
+        if let Some(staged) = &action.staged {
+            remove_if_exists(staged)?;
+        }
+    }
+    for dir in journal.created_dirs.iter().rev() {
+        let _ = fs::remove_dir(dir);
+    }
+    sync_dirs(journal);
+    Ok(())
+}
+
+fn roll_forward(state_dir: &Path, journal: &Journal, db: &mut InstalledDatabase) -> Result<()> {
+    for name in &journal.removals {
+        db.remove(name)?;
+    }
+    for pkg in &journal.updates {
+        db.insert(pkg.clone())?;
+    }
+    for pkg in &journal.installs {
+        db.insert(pkg.clone())?;
+    }
+    for action in &journal.actions {
+        if let Some(backup) = &action.backup {
+            remove_if_exists(backup)?;
+        }
+    }
+    // Shared directories stay for as long as any package has files in them;
+    // `remove_dir` also refuses while anything else is inside.
+    for dir in journal.prune.iter().rev() {
+        if db.owners_under(&installed_path(&journal.root, dir)).is_empty() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+    fs::remove_file(journal_path(state_dir))?;
+    Ok(())
+}
+
+// Recorded relative to the install root, so a database built into a sysroot
+// stays valid once that sysroot is booted.
+fn installed_path(root: &Path, target: &Path) -> String {
+    Path::new("/")
+        .join(target.strip_prefix(root).unwrap_or(target))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn write_staged(path: &Path, entry: &PlpmEntry, source: &mut impl Read, verify: bool) -> Result<u64> {
+    // Never through a link planted under the sidecar's name.
+    let mut file = File::options().write(true).create_new(true).open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let n = source.read(&mut buf).map_err(entry_error)?;
+        if n == 0 {
+            break;
+        }
+        if verify {
+            hasher.update(&buf[..n]);
+        }
+        file.write_all(&buf[..n])?;
+        size += n as u64;
+    }
+    if verify && !entry.checksum.is_empty() && format!("{:x}", hasher.finalize()) != entry.checksum {
+        return Err(PpmError::InvalidPackage("checksum mismatch".to_string()));
+    }
+
+    set_permissions(&file, entry.permissions)?;
+    file.sync_all()?;
+    Ok(size)
+}
+
+fn write_journal(state_dir: &Path, journal: &Journal) -> Result<()> {
+    let contents = toml::to_string_pretty(journal)
+        .map_err(|e| PpmError::Serialization(e.to_string()))?;
+    let path = journal_path(state_dir);
+    let tmp = path.with_extension("toml.tmp");
+    {
+        let mut file = File::create(&tmp)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, &path)?;
+    if let Ok(dir) = File::open(state_dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+fn sync_dirs(journal: &Journal) {
+    let dirs: HashSet<&Path> = journal.actions.iter().filter_map(|a| a.target.parent()).collect();
+    for dir in dirs {
+        if let Ok(d) = File::open(dir) {
+            let _ = d.sync_all();
+        }
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn journal_path(state_dir: &Path) -> PathBuf {
+    state_dir.join(JOURNAL_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::TestDir;
+    use crate::formats::plpm::PlpmFile;
+    use crate::{compute_checksum, Package};
+
+    struct Fixture {
+        dir: TestDir,
+        state: PathBuf,
+        root: PathBuf,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = TestDir::new("transaction", name);
+            fs::create_dir_all(&dir).unwrap();
+            Self { state: dir.join("state"), root: dir.join("root"), dir }
+        }
+
+        fn begin(&self) -> Transaction {
+            Transaction::begin(&self.state, &self.root).unwrap()
+        }
+
+        fn db(&self) -> InstalledDatabase {
+            InstalledDatabase::open(&self.state).unwrap()
+        }
+
+        fn read(&self, path: &str) -> Option<String> {
+            fs::read_to_string(self.root.join(path)).ok()
+        }
+
+        // Nothing but the committed files may be left behind: no sidecars
+        // and no journal.
+        fn leftovers(&self) -> Vec<PathBuf> {
+            fn walk(dir: &Path, out: &mut Vec<PathBuf>) {
+                for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+                    let path = entry.path();
+                    if path.is_dir() {
+                        walk(&path, out);
+                    } else if entry.file_name().to_string_lossy().contains(".ppm-") {
+                        out.push(path);
+                    }
+                }
+            }
+            let mut out = Vec::new();
+            walk(&self.root, &mut out);
+            out.extend(Some(journal_path(&self.state)).filter(|p| p.exists()));
+            out
+        }
+    }
+
+    fn package(name: &str, version: &str, files: &[(&str, &str)]) -> PlpmPackage {
+        let pkg = Package::for_test(name, version);
+        let files = files
+            .iter()
+            .map(|(path, data)| PlpmFile {
+                path: path.to_string(),
+                data: data.as_bytes().to_vec(),
+                permissions: 0o644,
+                checksum: compute_checksum(data.as_bytes()),
+            })
+            .collect();
+        PlpmPackage::new(pkg, files)
+    }
+
+    fn install(fixture: &Fixture, db: &mut InstalledDatabase, packages: &[PlpmPackage]) {
+        let mut tx = fixture.begin();
+        for package in packages {
+            tx.install(package, InstallReason::Explicit, db).unwrap();
+        }
+        tx.commit(db).unwrap();
+    }
+
+    fn conflict(result: Result<()>) -> String {
+        match result {
+            Err(PpmError::FileConflict(message)) => message,
+            other => panic!("expected a file conflict, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn upgrades_drop_files_the_new_version_no_longer_ships() {
+        let fixture = Fixture::new("upgrade");
+        let mut db = fixture.db();
+        let v1 = package("demo", "1.0.0", &[("usr/bin/demo", "v1"), ("usr/share/demo/old", "old")]);
+        install(&fixture, &mut db, &[v1]);
+        assert_eq!(fixture.read("usr/share/demo/old").as_deref(), Some("old"));
+
+        install(&fixture, &mut db, &[package("demo", "2.0.0", &[("usr/bin/demo", "v2")])]);
+        assert_eq!(fixture.read("usr/bin/demo").as_deref(), Some("v2"));
+        assert!(!fixture.root.join("usr/share/demo").exists());
+        assert_eq!(db.get("demo").unwrap().version.to_string(), "2.0.0");
+        assert!(db.owners("/usr/share/demo/old").is_empty());
+        assert!(fixture.leftovers().is_empty());
+    }
+
+    #[test]
+    fn rolls_back_a_partial_install() {
+        let fixture = Fixture::new("partial");
+        let mut db = fixture.db();
+        fs::create_dir_all(fixture.root.join("etc")).unwrap();
+        fs::write(fixture.root.join("etc/tool.conf"), "local").unwrap();
+
+        let mut tx = fixture.begin();
+        tx.install(&package("lib", "1.0.0", &[("usr/lib/libdemo.so", "lib")]), InstallReason::Dependency, &db)
+            .unwrap();
+        let message = conflict(tx.install(
+            &package("tool", "1.0.0", &[("usr/bin/tool", "tool"), ("etc/tool.conf", "shipped")]),
+            InstallReason::Explicit,
+            &db,
+        ));
+        assert!(message.contains("/etc/tool.conf (exists on disk)"), "{}", message);
+        tx.abort().unwrap();
+
+        assert!(!fixture.root.join("usr").exists());
+        assert_eq!(fixture.read("etc/tool.conf").as_deref(), Some("local"));
+        assert!(db.get("lib").is_none());
+        assert!(fixture.leftovers().is_empty());
+        // The journal is gone, so the next transaction may start.
+        install(&fixture, &mut db, &[package("lib", "1.0.0", &[("usr/lib/libdemo.so", "lib")])]);
+    }
+
+    #[test]
+    fn refuses_paths_staged_by_another_package_in_the_transaction() {
+        let fixture = Fixture::new("staged-conflict");
+        let mut db = fixture.db();
+        let first = package("first", "1.0.0", &[("usr/bin/shared", "first"), ("usr/bin/first", "first")]);
+        let second = package("second", "1.0.0", &[("usr/bin/shared", "second")]);
+
+        let mut tx = fixture.begin();
+        tx.install(&first, InstallReason::Explicit, &db).unwrap();
+        let message = conflict(tx.install(&second, InstallReason::Explicit, &db));
+        assert!(message.contains("/usr/bin/shared (also shipped by first)"), "{}", message);
+        drop(tx);
+        assert!(!fixture.root.join("usr").exists());
+
+        // With overwriting allowed the later package takes the path over.
+        let mut tx = fixture.begin().with_overwrite(true);
+        tx.install(&first, InstallReason::Explicit, &db).unwrap();
+        tx.install(&second, InstallReason::Explicit, &db).unwrap();
+        tx.commit(&mut db).unwrap();
+        assert_eq!(fixture.read("usr/bin/shared").as_deref(), Some("second"));
+        assert_eq!(db.owners("/usr/bin/shared"), ["second"]);
+        assert_eq!(db.get("first").unwrap().files.len(), 1);
+    }
+
+    #[test]
+    fn refuses_files_of_installed_packages_unless_overwriting() {
+        let fixture = Fixture::new("owned-conflict");
+        let mut db = fixture.db();
+        let old = package("old", "1.0.0", &[("usr/bin/tool", "old"), ("usr/bin/old", "old")]);
+        install(&fixture, &mut db, &[old]);
+
+        let mut tx = fixture.begin();
+        let new = package("new", "1.0.0", &[("usr/bin/tool", "new")]);
+        let message = conflict(tx.install(&new, InstallReason::Explicit, &db));
+        assert!(message.contains("/usr/bin/tool (owned by old)"), "{}", message);
+        assert!(message.ends_with("; pass --force to overwrite"), "{}", message);
+        drop(tx);
+
+        let mut tx = fixture.begin().with_overwrite(true);
+        tx.install(&package("new", "1.0.0", &[("usr/bin/tool", "new")]), InstallReason::Explicit, &db).unwrap();
+        tx.commit(&mut db).unwrap();
+        assert_eq!(db.owners("/usr/bin/tool"), ["new"]);
+        let old: Vec<&str> = db.get("old").unwrap().files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(old, ["/usr/bin/old"]);
+    }
+
+    #[test]
+    fn hands_files_over_from_a_package_removed_in_the_same_transaction() {
+        let fixture = Fixture::new("replace");
+        let mut db = fixture.db();
+        let sendmail = package("sendmail", "1.0.0", &[("usr/sbin/sendmail", "old"), ("usr/lib/sendmail", "old")]);
+        install(&fixture, &mut db, &[sendmail]);
+        let replaced = db.get("sendmail").unwrap().clone();
+
+        // Staged first, the install only gets past the owner check when
+        // overwriting; either way the removal leaves the staged file alone.
+        for remove_first in [true, false] {
+            let mut tx = fixture.begin().with_overwrite(!remove_first);
+            let postfix = package("postfix", "1.0.0", &[("usr/sbin/sendmail", "new")]);
+            if remove_first {
+                tx.remove(&replaced).unwrap();
+                tx.install(&postfix, InstallReason::Explicit, &db).unwrap();
+            } else {
+                tx.install(&postfix, InstallReason::Explicit, &db).unwrap();
+                tx.remove(&replaced).unwrap();
+            }
+            tx.abort().unwrap();
+            assert_eq!(fixture.read("usr/sbin/sendmail").as_deref(), Some("old"));
+        }
+        let mut tx = fixture.begin();
+        tx.remove(&replaced).unwrap();
+        let postfix = package("postfix", "1.0.0", &[("usr/sbin/sendmail", "new")]);
+        tx.install(&postfix, InstallReason::Explicit, &db).unwrap();
+        tx.commit(&mut db).unwrap();
+
+        assert_eq!(fixture.read("usr/sbin/sendmail").as_deref(), Some("new"));
+        assert!(!fixture.root.join("usr/lib").exists());
+        assert!(db.get("sendmail").is_none());
+        assert_eq!(db.owners("/usr/sbin/sendmail"), ["postfix"]);
+    }
+
+    #[test]
+    fn recovery_rolls_back_unfinished_transactions() {
+        let fixture = Fixture::new("recover-pending");
+        let mut db = fixture.db();
+        let mut tx = fixture.begin();
+        tx.install(&package("demo", "1.0.0", &[("usr/bin/demo", "demo")]), InstallReason::Explicit, &db).unwrap();
+        // A crash leaves the journal and the staged files behind.
+        std::mem::forget(tx);
+        assert!(matches!(Transaction::begin(&fixture.state, &fixture.root), Err(PpmError::Transaction(_))));
+
+        assert_eq!(recover(&fixture.state, &mut db).unwrap(), Recovery::RolledBack);
+        assert!(!fixture.root.join("usr").exists());
+        assert!(db.get("demo").is_none());
+        assert!(fixture.leftovers().is_empty());
+        assert_eq!(recover(&fixture.state, &mut db).unwrap(), Recovery::Clean);
+    }
+
+    #[test]
+    fn recovery_finishes_committed_transactions() {
+        let fixture = Fixture::new("recover-committed");
+        let mut db = fixture.db();
+        let v1 = package("demo", "1.0.0", &[("usr/bin/demo", "v1"), ("usr/share/demo/old", "old")]);
+        install(&fixture, &mut db, &[v1]);
+
+        let mut tx = fixture.begin();
+        tx.install(&package("demo", "2.0.0", &[("usr/bin/demo", "v2")]), InstallReason::Explicit, &db).unwrap();
+        // Crash after the files were swapped in and the journal marked
+        // committed, but before the database was updated.
+        tx.apply().unwrap();
+        tx.journal.state = JournalState::Committed;
+        tx.write_journal().unwrap();
+        tx.finished = true;
+        drop(tx);
+        assert_eq!(db.get("demo").unwrap().version.to_string(), "1.0.0");
+
+        assert_eq!(recover(&fixture.state, &mut db).unwrap(), Recovery::RolledForward);
+        assert_eq!(fixture.read("usr/bin/demo").as_deref(), Some("v2"));
+        assert!(!fixture.root.join("usr/share/demo").exists());
+        assert_eq!(db.get("demo").unwrap().version.to_string(), "2.0.0");
+        assert!(fixture.leftovers().is_empty());
+    }
+
+    #[test]
+    fn stays_inside_the_root_through_symlinked_directories() {
+        let fixture = Fixture::new("symlinks");
+        let mut db = fixture.db();
+        let host = fixture.dir.join("host");
+        fs::create_dir_all(&host).unwrap();
+        fs::create_dir_all(fixture.root.join("usr/lib")).unwrap();
+        std::os::unix::fs::symlink("usr/lib", fixture.root.join("lib")).unwrap();
+        std::os::unix::fs::symlink(&host, fixture.root.join("usr/lib/escape")).unwrap();
+
+        // Links that stay inside the root are followed, and the file is
+        // recorded where it actually lands.
+        install(&fixture, &mut db, &[package("inside", "1.0.0", &[("lib/libinside.so", "lib")])]);
+        assert_eq!(fixture.read("usr/lib/libinside.so").as_deref(), Some("lib"));
+        assert_eq!(db.owners("/usr/lib/libinside.so"), ["inside"]);
+
+        // An absolute link means the host's directory here, not the root's.
+        let mut tx = fixture.begin();
+        let escape = package("escape", "1.0.0", &[("lib/escape/sub/evil", "evil"), ("usr/lib/escape/evil", "evil")]);
+        match tx.install(&escape, InstallReason::Explicit, &db) {
+            Err(PpmError::SecurityViolation(message)) => assert!(message.contains("outside the install root"), "{}", message),
+            other => panic!("expected a security violation, got {:?}", other),
+        }
+        tx.abort().unwrap();
+        assert_eq!(fs::read_dir(&host).unwrap().count(), 0);
+        assert!(fixture.leftovers().is_empty());
+    }
+
+    #[test]
+    fn refuses_invalid_package_names_before_staging() {
+        let fixture = Fixture::new("bad-name");
+        let mut db = fixture.db();
+        for name in ["", ".hidden", "../escape", "a\\b"] {
+            let mut tx = fixture.begin();
+            let mut bad = package("demo", "1.0.0", &[("usr/bin/demo", "demo")]);
+            bad.metadata.name = name.to_string();
+            match tx.install(&bad, InstallReason::Explicit, &db) {
+                Err(PpmError::InvalidPackage(message)) => assert!(message.contains("Invalid package name"), "{}", message),
+                other => panic!("expected an invalid package error, got {:?}", other),
+            }
+            assert!(!fixture.root.join("usr").exists());
+            tx.commit(&mut db).unwrap();
+        }
+        assert_eq!(recover(&fixture.state, &mut db).unwrap(), Recovery::Clean);
+        assert!(db.packages().next().is_none());
+    }
+
+    #[test]
+    fn keeps_unreadable_journals_for_manual_recovery() {
+        let fixture = Fixture::new("unreadable");
+        let mut db = fixture.db();
+        let journal = journal_path(&fixture.state);
+        fs::write(&journal, "id = \"1-2\"\nstate = \"pend").unwrap();
+        match recover(&fixture.state, &mut db) {
+            Err(PpmError::Transaction(message)) => {
+                assert!(message.contains("cannot read the transaction journal"), "{}", message)
+            }
+            other => panic!("expected a transaction error, got {:?}", other),
+        }
+        assert!(journal.exists());
+    }
+}
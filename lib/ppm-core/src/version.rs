@@ -0,0 +1,552 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Channel, PpmError, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Vec<PreRelease>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PreRelease {
+    Numeric(u64),
+    Alpha(String),
+}
+
+impl Version {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+            pre: Vec::new(),
+        }
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+
+    // The leading pre-release tag decides which channel a build belongs to:
+    // `1.4.0` is stable, `1.4.0-rc.1` testing, `1.4.0-alpha.2` unstable and
+    // `1.4.0-dev.7` (or any unknown tag) dev.
+    pub fn channel(&self) -> Channel {
+        match self.pre.first() {
+            None => Channel::Stable,
+            Some(PreRelease::Alpha(tag)) => match tag.to_ascii_lowercase().as_str() {
+                "rc" | "beta" | "testing" | "blossom" => Channel::Testing,
+                "alpha" | "unstable" | "seed" => Channel::Unstable,
+                _ => Channel::Dev,
+            },
+            Some(PreRelease::Numeric(_)) => Channel::Dev,
+        }
+    }
+
+    fn triple(&self) -> (u64, u64, u64) {
+        (self.major, self.minor, self.patch)
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.triple().cmp(&other.triple()).then_with(|| {
+            match (self.pre.is_empty(), other.pre.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => self.pre.cmp(&other.pre),
+            }
+        })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PreRelease {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (PreRelease::Numeric(a), PreRelease::Numeric(b)) => a.cmp(b),
+            (PreRelease::Numeric(_), PreRelease::Alpha(_)) => Ordering::Less,
+            (PreRelease::Alpha(_), PreRelease::Numeric(_)) => Ordering::Greater,
+            (PreRelease::Alpha(a), PreRelease::Alpha(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for PreRelease {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for PreRelease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreRelease::Numeric(n) => write!(f, "{}", n),
+            PreRelease::Alpha(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        for (i, ident) in self.pre.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { "-" } else { "." }, ident)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Version {
+    type Err = PpmError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let partial = Partial::parse(s)?;
+        Ok(partial.to_version())
+    }
+}
+
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn invalid_version(s: &str, reason: &str) -> PpmError {
+    PpmError::InvalidVersion(format!("'{}': {}", s, reason))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Partial {
+    major: u64,
+    minor: Option<u64>,
+    patch: Option<u64>,
+    pre: Vec<PreRelease>,
+}
+
+impl Partial {
+    fn parse(input: &str) -> Result<Self> {
+        let s = input.trim();
+        let s = s.strip_prefix('v').unwrap_or(s);
+        if s.is_empty() {
+            return Err(invalid_version(input, "empty version"));
+        }
+        if s.contains('+') {
+            return Err(invalid_version(input, "build metadata is not supported"));
+        }
+
+        let (core, pre) = match s.split_once('-') {
+            Some((core, pre)) => (core, Some(pre)),
+            None => (s, None),
+        };
+
+        let mut numbers = core.split('.');
+        let mut next_number = |required: bool| -> Result<Option<u64>> {
+            match numbers.next() {
+                None if required => Err(invalid_version(input, "missing major version")),
+                None => Ok(None),
+                Some(part) => part
+                    .parse::<u64>()
+                    .map(Some)
+                    .map_err(|_| invalid_version(input, "version components must be numbers")),
+            }
+        };
+        let major = next_number(true)?.unwrap_or(0);
+        let minor = next_number(false)?;
+        let patch = next_number(false)?;
+        if numbers.next().is_some() {
+            return Err(invalid_version(input, "too many version components"));
+        }
+
+        let pre = match pre {
+            None => Vec::new(),
+            Some(pre) => pre
+                .split('.')
+                .map(|ident| {
+                    if ident.is_empty()
+                        || !ident.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                    {
+                        Err(invalid_version(input, "invalid pre-release tag"))
+                    } else if let Ok(n) = ident.parse::<u64>() {
+                        Ok(PreRelease::Numeric(n))
+                    } else {
+                        Ok(PreRelease::Alpha(ident.to_string()))
+                    }
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+        if !pre.is_empty() && patch.is_none() {
+            return Err(invalid_version(input, "pre-release tags need a full major.minor.patch"));
+        }
+
+        Ok(Self {
+            major,
+            minor,
+            patch,
+            pre,
+        })
+    }
+
+    fn to_version(&self) -> Version {
+        Version {
+            major: self.major,
+            minor: self.minor.unwrap_or(0),
+            patch: self.patch.unwrap_or(0),
+            pre: self.pre.clone(),
+        }
+    }
+
+    // Smallest version that is no longer covered by this partial version,
+    // e.g. `1.4` -> `1.5.0` and `1` -> `2.0.0`.
+    fn next_after(&self) -> Version {
+        match (self.minor, self.patch) {
+            (None, _) => Version::new(self.major + 1, 0, 0),
+            (Some(minor), None) => Version::new(self.major, minor + 1, 0),
+            (Some(minor), Some(patch)) => Version::new(self.major, minor, patch + 1),
+        }
+    }
+}
+
+impl fmt::Display for Partial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.major)?;
+        if let Some(minor) = self.minor {
+            write!(f, ".{}", minor)?;
+        }
+        if let Some(patch) = self.patch {
+            write!(f, ".{}", patch)?;
+        }
+        for (i, ident) in self.pre.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { "-" } else { "." }, ident)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+}
+
+impl Op {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Op::Exact => "=",
+            Op::Greater => ">",
+            Op::GreaterEq => ">=",
+            Op::Less => "<",
+            Op::LessEq => "<=",
+            Op::Tilde => "~",
+            Op::Caret => "^",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comparator {
+    pub op: Op,
+    version: Partial,
+}
+
+impl Comparator {
+    pub fn version(&self) -> Version {
+        self.version.to_version()
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        let lower = self.version.to_version();
+        match self.op {
+            Op::Exact => {
+                if self.version.patch.is_some() {
+                    *version == lower
+                } else {
+                    *version >= lower && below(version, &self.version.next_after())
+                }
+            }
+            Op::Greater => {
+                if self.version.patch.is_some() {
+                    *version > lower
+                } else {
+                    *version >= self.version.next_after()
+                }
+            }
+            Op::GreaterEq => *version >= lower,
+            Op::Less => below(version, &lower),
+            Op::LessEq => {
+                if self.version.patch.is_some() {
+                    *version <= lower
+                } else {
+                    below(version, &self.version.next_after())
+                }
+            }
+            Op::Tilde => {
+                let upper = match self.version.minor {
+                    None => Version::new(self.version.major + 1, 0, 0),
+                    Some(minor) => Version::new(self.version.major, minor + 1, 0),
+                };
+                *version >= lower && below(version, &upper)
+            }
+            Op::Caret => {
+                let upper = match (self.version.major, self.version.minor, self.version.patch) {
+                    (0, Some(0), Some(patch)) => Version::new(0, 0, patch + 1),
+                    (0, Some(minor), _) => Version::new(0, minor + 1, 0),
+                    (major, _, _) => Version::new(major + 1, 0, 0),
+                };
+                *version >= lower && below(version, &upper)
+            }
+        }
+    }
+}
+
+// An upper bound without a pre-release tag excludes that release's own
+// pre-releases, so `<2` doesn't admit `2.0.0-dev.1`.
+fn below(version: &Version, bound: &Version) -> bool {
+    if bound.pre.is_empty() {
+        version.triple() < bound.triple()
+    } else {
+        version < bound
+    }
+}
+
+impl fmt::Display for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.op.as_str(), self.version)
+    }
+}
+
+impl FromStr for Comparator {
+    type Err = PpmError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (op, rest) = if let Some(rest) = s.strip_prefix(">=") {
+            (Op::GreaterEq, rest)
+        } else if let Some(rest) = s.strip_prefix("<=") {
+            (Op::LessEq, rest)
+        } else if let Some(rest) = s.strip_prefix("==") {
+            (Op::Exact, rest)
+        } else if let Some(rest) = s.strip_prefix('>') {
+            (Op::Greater, rest)
+        } else if let Some(rest) = s.strip_prefix('<') {
+            (Op::Less, rest)
+        } else if let Some(rest) = s.strip_prefix('=') {
+            (Op::Exact, rest)
+        } else if let Some(rest) = s.strip_prefix('~') {
+            (Op::Tilde, rest)
+        } else if let Some(rest) = s.strip_prefix('^') {
+            (Op::Caret, rest)
+        } else {
+            (Op::Exact, s)
+        };
+        Ok(Self {
+            op,
+            version: Partial::parse(rest)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VersionReq {
+    pub comparators: Vec<Comparator>,
+}
+
+impl VersionReq {
+    pub const STAR: VersionReq = VersionReq {
+        comparators: Vec::new(),
+    };
+
+    pub fn exact(version: &Version) -> Self {
+        Self {
+            comparators: vec![Comparator {
+                op: Op::Exact,
+                version: Partial {
+                    major: version.major,
+                    minor: Some(version.minor),
+                    patch: Some(version.patch),
+                    pre: version.pre.clone(),
+                },
+            }],
+        }
+    }
+
+    pub fn is_any(&self) -> bool {
+        self.comparators.is_empty()
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.comparators.iter().all(|c| c.matches(version))
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.comparators.is_empty() {
+            return write!(f, "*");
+        }
+        for (i, comparator) in self.comparators.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", comparator)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = PpmError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.is_empty() || s == "*" {
+            return Ok(Self::STAR);
+        }
+        let comparators = s
+            .split(',')
+            .map(|part| part.parse::<Comparator>())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { comparators })
+    }
+}
+
+impl Serialize for VersionReq {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for VersionReq {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        s.parse().unwrap()
+    }
+
+    fn req(s: &str) -> VersionReq {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn orders_pre_releases_below_the_release() {
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn parses_partial_and_prefixed_versions() {
+        assert_eq!(v("1"), Version::new(1, 0, 0));
+        assert_eq!(v("v1.4"), Version::new(1, 4, 0));
+        assert_eq!(v("1.2.3-rc.1").to_string(), "1.2.3-rc.1");
+    }
+
+    #[test]
+    fn rejects_malformed_versions() {
+        for bad in ["", "1.x", "1.2.3.4", "1.2.3+build.5", "1.2-rc.1", "1.2.3-", "1.2.3-rc..1"] {
+            assert!(matches!(bad.parse::<Version>(), Err(PpmError::InvalidVersion(_))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn maps_pre_release_tags_to_channels() {
+        assert_eq!(v("1.4.0").channel(), Channel::Stable);
+        assert_eq!(v("1.4.0-rc.1").channel(), Channel::Testing);
+        assert_eq!(v("1.4.0-alpha.2").channel(), Channel::Unstable);
+        assert_eq!(v("1.4.0-dev.7").channel(), Channel::Dev);
+        assert_eq!(v("1.4.0-7").channel(), Channel::Dev);
+    }
+
+    #[test]
+    fn tilde_allows_patch_updates() {
+        let r = req("~1.2.3");
+        assert!(r.matches(&v("1.2.3")));
+        assert!(r.matches(&v("1.2.9")));
+        assert!(!r.matches(&v("1.2.2")));
+        assert!(!r.matches(&v("1.3.0")));
+
+        assert!(req("~1.2").matches(&v("1.2.0")));
+        assert!(!req("~1.2").matches(&v("1.3.0")));
+        assert!(req("~1").matches(&v("1.9.0")));
+        assert!(!req("~1").matches(&v("2.0.0")));
+    }
+
+    #[test]
+    fn caret_stays_within_the_leftmost_non_zero_component() {
+        assert!(req("^1.2.3").matches(&v("1.9.0")));
+        assert!(!req("^1.2.3").matches(&v("1.2.2")));
+        assert!(!req("^1.2.3").matches(&v("2.0.0")));
+
+        assert!(req("^0.2.3").matches(&v("0.2.9")));
+        assert!(!req("^0.2.3").matches(&v("0.3.0")));
+
+        assert!(req("^0.0.3").matches(&v("0.0.3")));
+        assert!(!req("^0.0.3").matches(&v("0.0.4")));
+    }
+
+    #[test]
+    fn partial_comparators_cover_the_whole_range() {
+        assert!(req("=1.4").matches(&v("1.4.7")));
+        assert!(!req("=1.4").matches(&v("1.5.0")));
+        assert!(req(">1.4").matches(&v("1.5.0")));
+        assert!(!req(">1.4").matches(&v("1.4.9")));
+        assert!(req("<=1.4").matches(&v("1.4.9")));
+        assert!(!req("<=1.4").matches(&v("1.5.0")));
+    }
+
+    #[test]
+    fn upper_bounds_exclude_their_own_pre_releases() {
+        assert!(!req("<2").matches(&v("2.0.0-dev.1")));
+        assert!(!req("^1.2").matches(&v("2.0.0-rc.1")));
+        assert!(req("<2.0.0-rc.1").matches(&v("2.0.0-beta.1")));
+        assert!(req(">=1.0.0, <2").matches(&v("1.0.0")));
+    }
+
+    #[test]
+    fn displays_requirements_as_parsed() {
+        assert_eq!(req(">=1.2, <2").to_string(), ">=1.2, <2");
+        assert_eq!(req("*").to_string(), "*");
+        assert_eq!(VersionReq::exact(&v("1.2.3-rc.1")).to_string(), "=1.2.3-rc.1");
+    }
+}
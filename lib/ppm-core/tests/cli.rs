@@ -0,0 +1,44 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ppm-cli-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Runs `ppm` against an empty install root, with its configuration kept
+// inside `dir` rather than the user's own.
+fn ppm(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ppm"))
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .arg("--root")
+        .arg(dir.join("root"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn reports_failures_with_their_exit_code() {
+    let dir = temp_dir("failure");
+
+    let output = ppm(&dir, &["--output", "json", "files", "no-such-package"]);
+    assert_eq!(output.status.code(), Some(3), "{}", String::from_utf8_lossy(&output.stderr));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let mut keys: Vec<&str> = report.as_object().unwrap().keys().map(String::as_str).collect();
+    keys.sort();
+    assert_eq!(keys, ["code", "error"]);
+    assert_eq!(report["code"], 3);
+    assert!(report["error"].as_str().unwrap().contains("no-such-package"), "{}", report);
+
+    // Text output reports the same failure on stderr.
+    let output = ppm(&dir, &["files", "no-such-package"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no-such-package"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,646 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ppm_core::formats::index::{encode_index, IndexFormat};
+use ppm_core::package::PackageIndex;
+use ppm_core::{
+    compute_checksum, Architecture, Channel, NetworkConfig, Package, PackageCache, PpmError, Repository,
+    RepositoryManager,
+};
+
+#[derive(Default, Clone)]
+struct Route {
+    body: Vec<u8>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    failures: u32,
+    delay: Option<Duration>,
+    ranges: bool,
+    // Responses that announce the full body but stop halfway through it.
+    interruptions: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Hit {
+    path: String,
+    if_none_match: Option<String>,
+    if_modified_since: Option<String>,
+    range: Option<String>,
+    status: u16,
+}
+
+#[derive(Default)]
+struct State {
+    routes: HashMap<String, Route>,
+    hits: Vec<Hit>,
+}
+
+struct TestServer {
+    server: Arc<tiny_http::Server>,
+    state: Arc<Mutex<State>>,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+impl TestServer {
+    fn start() -> Self {
+        let server = Arc::new(tiny_http::Server::http("127.0.0.1:0").unwrap());
+        let state = Arc::new(Mutex::new(State::default()));
+        let worker = {
+            let server = Arc::clone(&server);
+            let state = Arc::clone(&state);
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let state = Arc::clone(&state);
+                    thread::spawn(move || respond(request, &state));
+                }
+            })
+        };
+        Self {
+            server,
+            state,
+            worker: Some(worker),
+        }
+    }
+
+    fn url(&self) -> String {
+        format!("http://{}", self.server.server_addr().to_ip().unwrap())
+    }
+
+    fn route(&self, path: &str, route: Route) {
+        self.state.lock().unwrap().routes.insert(path.to_string(), route);
+    }
+
+    fn hits(&self, path: &str) -> Vec<Hit> {
+        let state = self.state.lock().unwrap();
+        state.hits.iter().filter(|h| h.path == path).cloned().collect()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn respond(request: tiny_http::Request, state: &Mutex<State>) {
+    let header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.to_string())
+    };
+    let path = request.url().to_string();
+    let if_none_match = header("If-None-Match");
+    let if_modified_since = header("If-Modified-Since");
+    let range = header("Range");
+
+    let route = {
+        let mut state = state.lock().unwrap();
+        state.routes.get_mut(&path).map(|route| {
+            let failing = route.failures > 0;
+            route.failures = route.failures.saturating_sub(1);
+            let current = route.clone();
+            if !failing {
+                route.interruptions = route.interruptions.saturating_sub(1);
+            }
+            (current, failing)
+        })
+    };
+    if let Some((route, _)) = &route {
+        if let Some(delay) = route.delay {
+            thread::sleep(delay);
+        }
+    }
+
+    let (status, route) = match route {
+        None => (404, None),
+        Some((_, true)) => (503, None),
+        Some((route, false)) => {
+            let fresh = match (&route.etag, &if_none_match) {
+                (Some(etag), Some(sent)) => etag == sent,
+                _ => matches!((&route.last_modified, &if_modified_since), (Some(a), Some(b)) if a == b),
+            };
+            (if fresh { 304 } else { 200 }, Some(route))
+        }
+    };
+    let offset = range
+        .as_deref()
+        .and_then(|r| r.strip_prefix("bytes="))
+        .and_then(|r| r.strip_suffix('-'))
+        .and_then(|r| r.parse::<usize>().ok())
+        .filter(|_| status == 200 && route.as_ref().is_some_and(|r| r.ranges));
+    let status = match (&route, offset) {
+        (Some(route), Some(offset)) if offset >= route.body.len() => 416,
+        (_, Some(_)) => 206,
+        _ => status,
+    };
+    state.lock().unwrap().hits.push(Hit {
+        path,
+        if_none_match,
+        if_modified_since,
+        range,
+        status,
+    });
+
+    let body = match (&route, status) {
+        (Some(route), 200) => route.body.clone(),
+        (Some(route), 206) => route.body[offset.unwrap_or(0)..].to_vec(),
+        _ => Vec::new(),
+    };
+    let length = body.len();
+    let sent = match &route {
+        Some(route) if route.interruptions > 0 => body[..length / 2].to_vec(),
+        _ => body,
+    };
+    let mut response = tiny_http::Response::new(
+        tiny_http::StatusCode(status),
+        Vec::new(),
+        Cursor::new(sent),
+        Some(length),
+        None,
+    )
+    .with_chunked_threshold(usize::MAX);
+    if let (Some(route), Some(offset)) = (&route, offset.filter(|_| status == 206)) {
+        let range = format!("bytes {}-{}/{}", offset, route.body.len() - 1, route.body.len());
+        response.add_header(tiny_http::Header::from_bytes("Content-Range", range).unwrap());
+    }
+    if let Some(route) = route {
+        if let Some(etag) = route.etag {
+            response.add_header(tiny_http::Header::from_bytes("ETag", etag).unwrap());
+        }
+        if let Some(modified) = route.last_modified {
+            response.add_header(tiny_http::Header::from_bytes("Last-Modified", modified).unwrap());
+        }
+    }
+    let _ = request.respond(response);
+}
+
+fn package(name: &str, version: &str, data: &[u8]) -> Package {
+    Package {
+        name: name.to_string(),
+        version: version.parse().unwrap(),
+        description: Some(format!("{} test package", name)),
+        author: None,
+        license: None,
+        dependencies: Vec::new(),
+        conflicts: Vec::new(),
+        provides: Vec::new(),
+        replaces: Vec::new(),
+        recommends: Vec::new(),
+        features: Default::default(),
+        architecture: Architecture::X86_64,
+        channel: Channel::Stable,
+        file: format!("packages/{}-{}.plpm", name, version),
+        checksum: compute_checksum(data),
+        signature: None,
+        size: data.len() as u64,
+        install_size: data.len() as u64,
+    }
+}
+
+fn index(packages: Vec<Package>) -> PackageIndex {
+    PackageIndex {
+        packages,
+        generated: "0".to_string(),
+        channel: Channel::Stable,
+    }
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ppm-http-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn network(timeout_secs: u64, retries: u32) -> NetworkConfig {
+    NetworkConfig {
+        timeout_secs,
+        retries,
+        retry_delay_ms: 10,
+        ..NetworkConfig::default()
+    }
+}
+
+fn repository(server: &TestServer, cache: &Path, network: NetworkConfig) -> Repository {
+    Repository::new(server.url(), "test".to_string(), Channel::Stable, vec![Architecture::X86_64])
+        .with_cache_dir(cache)
+        .with_network(network)
+}
+
+#[tokio::test]
+async fn fetches_binary_index_and_caches_it() {
+    let server = TestServer::start();
+    let cache = temp_dir("binary");
+    let idx = index(vec![package("hello", "1.0.0", b"one"), package("hello", "1.1.0", b"two")]);
+    server.route(
+        "/stable/index.bin",
+        Route {
+            body: encode_index(&idx, IndexFormat::Binary).unwrap(),
+            ..Route::default()
+        },
+    );
+
+    let mut repo = repository(&server, &cache, network(5, 0));
+    repo.refresh().await.unwrap();
+    let found = repo.find_package("hello", None).unwrap().unwrap();
+    assert_eq!(found.version.to_string(), "1.1.0");
+    assert!(server.hits("/stable/index.toml").is_empty());
+
+    let offline = Repository::new(
+        "http://127.0.0.1:9".to_string(),
+        "test".to_string(),
+        Channel::Stable,
+        vec![Architecture::X86_64],
+    )
+    .with_cache_dir(&cache);
+    assert_eq!(offline.find_package("hello", None).unwrap().unwrap().version.to_string(), "1.1.0");
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+#[tokio::test]
+async fn falls_back_to_toml_index() {
+    let server = TestServer::start();
+    let cache = temp_dir("toml");
+    let idx = index(vec![package("tool", "2.0.0", b"tool")]);
+    server.route(
+        "/stable/index.toml",
+        Route {
+            body: encode_index(&idx, IndexFormat::Toml).unwrap(),
+            ..Route::default()
+        },
+    );
+
+    let mut repo = repository(&server, &cache, network(5, 0));
+    repo.refresh().await.unwrap();
+    assert!(repo.find_package("tool", None).unwrap().is_some());
+    assert_eq!(server.hits("/stable/index.bin")[0].status, 404);
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+#[tokio::test]
+async fn revalidates_with_etag() {
+    let server = TestServer::start();
+    let cache = temp_dir("etag");
+    let idx = index(vec![package("hello", "1.0.0", b"one")]);
+    server.route(
+        "/stable/index.bin",
+        Route {
+            body: encode_index(&idx, IndexFormat::Binary).unwrap(),
+            etag: Some("\"v1\"".to_string()),
+            ..Route::default()
+        },
+    );
+
+    let repo = repository(&server, &cache, network(5, 0));
+    repo.fetch_index().await.unwrap();
+    let cached = repo.fetch_index().await.unwrap();
+    assert_eq!(cached.packages.len(), 1);
+
+    let hits = server.hits("/stable/index.bin");
+    assert_eq!(hits.len(), 2);
+    assert_eq!(hits[0].if_none_match, None);
+    assert_eq!(hits[1].if_none_match.as_deref(), Some("\"v1\""));
+    assert_eq!(hits[1].status, 304);
+
+    let updated = index(vec![package("hello", "1.0.0", b"one"), package("hello", "2.0.0", b"two")]);
+    server.route(
+        "/stable/index.bin",
+        Route {
+            body: encode_index(&updated, IndexFormat::Binary).unwrap(),
+            etag: Some("\"v2\"".to_string()),
+            ..Route::default()
+        },
+    );
+    let refreshed = repo.fetch_index().await.unwrap();
+    assert_eq!(refreshed.packages.len(), 2);
+    assert_eq!(server.hits("/stable/index.bin")[2].status, 200);
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+#[tokio::test]
+async fn revalidates_with_last_modified() {
+    let server = TestServer::start();
+    let cache = temp_dir("modified");
+    let stamp = "Wed, 21 Oct 2026 07:28:00 GMT";
+    server.route(
+        "/stable/index.bin",
+        Route {
+            body: encode_index(&index(vec![package("hello", "1.0.0", b"one")]), IndexFormat::Binary).unwrap(),
+            last_modified: Some(stamp.to_string()),
+            ..Route::default()
+        },
+    );
+
+    let repo = repository(&server, &cache, network(5, 0));
+    repo.fetch_index().await.unwrap();
+    repo.fetch_index().await.unwrap();
+    let hits = server.hits("/stable/index.bin");
+    assert_eq!(hits[1].if_modified_since.as_deref(), Some(stamp));
+    assert_eq!(hits[1].status, 304);
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+#[tokio::test]
+async fn retries_transient_failures() {
+    let server = TestServer::start();
+    let cache = temp_dir("retry");
+    server.route(
+        "/stable/index.bin",
+        Route {
+            body: encode_index(&index(vec![package("hello", "1.0.0", b"one")]), IndexFormat::Binary).unwrap(),
+            failures: 2,
+            ..Route::default()
+        },
+    );
+
+    let repo = repository(&server, &cache, network(5, 2));
+    repo.fetch_index().await.unwrap();
+    let statuses: Vec<u16> = server.hits("/stable/index.bin").iter().map(|h| h.status).collect();
+    assert_eq!(statuses, vec![503, 503, 200]);
+
+    server.route(
+        "/stable/index.bin",
+        Route {
+            failures: 5,
+            ..Route::default()
+        },
+    );
+    let err = repository(&server, &cache, network(5, 1)).fetch_index().await.unwrap_err();
+    assert!(matches!(err, PpmError::Repository(ref msg) if msg.contains("2 attempts")), "{}", err);
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+#[tokio::test]
+async fn times_out_slow_servers() {
+    let server = TestServer::start();
+    let cache = temp_dir("timeout");
+    server.route(
+        "/stable/index.bin",
+        Route {
+            delay: Some(Duration::from_secs(3)),
+            ..Route::default()
+        },
+    );
+
+    let started = std::time::Instant::now();
+    let err = repository(&server, &cache, network(1, 0)).fetch_index().await.unwrap_err();
+    assert!(matches!(err, PpmError::Repository(_)), "{}", err);
+    assert!(started.elapsed() < Duration::from_secs(3));
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+#[tokio::test]
+async fn downloads_and_verifies_packages() {
+    let server = TestServer::start();
+    let cache = temp_dir("download");
+    let data = vec![7u8; 256 * 1024];
+    let pkg = package("big", "1.0.0", &data);
+    server.route(
+        "/stable/index.bin",
+        Route {
+            body: encode_index(&index(vec![pkg.clone()]), IndexFormat::Binary).unwrap(),
+            ..Route::default()
+        },
+    );
+    server.route(
+        "/stable/packages/big-1.0.0.plpm",
+        Route {
+            body: data.clone(),
+            ..Route::default()
+        },
+    );
+
+    let mut repo = repository(&server, &cache, network(5, 0));
+    repo.refresh().await.unwrap();
+    let path = repo.fetch_package(&pkg).await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+
+    repo.fetch_package(&pkg).await.unwrap();
+    assert_eq!(server.hits("/stable/packages/big-1.0.0.plpm").len(), 1);
+
+    std::fs::remove_file(&path).unwrap();
+    server.route(
+        "/stable/packages/big-1.0.0.plpm",
+        Route {
+            body: b"tampered".to_vec(),
+            ..Route::default()
+        },
+    );
+    let err = repo.fetch_package(&pkg).await.unwrap_err();
+    assert!(matches!(err, PpmError::SecurityViolation(_)), "{}", err);
+    assert!(!path.exists());
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+fn serve_package(server: &TestServer, pkg: &Package, data: &[u8], route: Route) {
+    server.route(
+        &format!("/stable/{}", pkg.file),
+        Route {
+            body: data.to_vec(),
+            ..route
+        },
+    );
+}
+
+#[tokio::test]
+async fn resumes_partial_downloads_with_range_requests() {
+    let server = TestServer::start();
+    let cache = temp_dir("resume");
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let pkg = package("resume", "1.0.0", &data);
+    server.route(
+        "/stable/index.bin",
+        Route {
+            body: encode_index(&index(vec![pkg.clone()]), IndexFormat::Binary).unwrap(),
+            ..Route::default()
+        },
+    );
+    serve_package(&server, &pkg, &data, Route { ranges: true, ..Route::default() });
+
+    let partial = PackageCache::open(&cache).partial_path(&pkg.checksum).unwrap();
+    std::fs::write(&partial, &data[..120_000]).unwrap();
+
+    let mut repo = repository(&server, &cache, network(5, 0));
+    repo.refresh().await.unwrap();
+    let path = repo.fetch_package(&pkg).await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    assert!(!partial.exists());
+
+    let hits = server.hits(&format!("/stable/{}", pkg.file));
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].range.as_deref(), Some("bytes=120000-"));
+    assert_eq!(hits[0].status, 206);
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+#[tokio::test]
+async fn resumes_interrupted_transfers() {
+    let server = TestServer::start();
+    let cache = temp_dir("interrupted");
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i % 241) as u8).collect();
+    let pkg = package("flaky", "1.0.0", &data);
+    server.route(
+        "/stable/index.bin",
+        Route {
+            body: encode_index(&index(vec![pkg.clone()]), IndexFormat::Binary).unwrap(),
+            ..Route::default()
+        },
+    );
+    serve_package(
+        &server,
+        &pkg,
+        &data,
+        Route {
+            ranges: true,
+            interruptions: 1,
+            ..Route::default()
+        },
+    );
+
+    let mut repo = repository(&server, &cache, network(2, 0));
+    repo.refresh().await.unwrap();
+    let err = repo.fetch_package(&pkg).await.unwrap_err();
+    assert!(matches!(err, PpmError::Repository(_)), "{}", err);
+    let partial = PackageCache::open(&cache).partial_path(&pkg.checksum).unwrap();
+    assert_eq!(std::fs::metadata(&partial).unwrap().len(), 50_000);
+
+    let path = repo.fetch_package(&pkg).await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    let hits = server.hits(&format!("/stable/{}", pkg.file));
+    assert_eq!(hits.last().unwrap().range.as_deref(), Some("bytes=50000-"));
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+#[tokio::test]
+async fn restarts_when_the_server_ignores_ranges() {
+    let server = TestServer::start();
+    let cache = temp_dir("no-ranges");
+    let data = vec![3u8; 64 * 1024];
+    let pkg = package("plain", "1.0.0", &data);
+    server.route(
+        "/stable/index.bin",
+        Route {
+            body: encode_index(&index(vec![pkg.clone()]), IndexFormat::Binary).unwrap(),
+            ..Route::default()
+        },
+    );
+    serve_package(&server, &pkg, &data, Route::default());
+
+    let partial = PackageCache::open(&cache).partial_path(&pkg.checksum).unwrap();
+    std::fs::write(&partial, vec![9u8; 10_000]).unwrap();
+
+    let mut repo = repository(&server, &cache, network(5, 0));
+    repo.refresh().await.unwrap();
+    let path = repo.fetch_package(&pkg).await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    assert_eq!(server.hits(&format!("/stable/{}", pkg.file))[0].status, 200);
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+#[tokio::test]
+async fn rejects_packages_of_the_wrong_size() {
+    let server = TestServer::start();
+    let cache = temp_dir("size");
+    let data = vec![5u8; 4096];
+    let mut pkg = package("short", "1.0.0", &data);
+    pkg.size += 1;
+    server.route(
+        "/stable/index.bin",
+        Route {
+            body: encode_index(&index(vec![pkg.clone()]), IndexFormat::Binary).unwrap(),
+            ..Route::default()
+        },
+    );
+    serve_package(&server, &pkg, &data, Route::default());
+
+    let mut repo = repository(&server, &cache, network(5, 0));
+    repo.refresh().await.unwrap();
+    let err = repo.fetch_package(&pkg).await.unwrap_err();
+    assert!(matches!(err, PpmError::SecurityViolation(_)), "{}", err);
+    let partial = PackageCache::open(&cache).partial_path(&pkg.checksum).unwrap();
+    assert!(!partial.exists());
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_downloads_that_exceed_the_package_size() {
+    let server = TestServer::start();
+    let cache = temp_dir("oversized");
+    let data = vec![6u8; 4096];
+    let mut pkg = package("long", "1.0.0", &data);
+    pkg.size = 1024;
+    server.route(
+        "/stable/index.bin",
+        Route {
+            body: encode_index(&index(vec![pkg.clone()]), IndexFormat::Binary).unwrap(),
+            ..Route::default()
+        },
+    );
+    serve_package(&server, &pkg, &data, Route::default());
+
+    let mut repo = repository(&server, &cache, network(5, 0));
+    repo.refresh().await.unwrap();
+    let err = repo.fetch_package(&pkg).await.unwrap_err();
+    assert!(matches!(err, PpmError::SecurityViolation(_)), "{}", err);
+    let partial = PackageCache::open(&cache).partial_path(&pkg.checksum).unwrap();
+    assert!(!partial.exists());
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn downloads_packages_concurrently() {
+    let server = TestServer::start();
+    let cache = temp_dir("parallel");
+    let packages: Vec<(Package, Vec<u8>)> = (0..4)
+        .map(|i| {
+            let data = vec![i as u8; 8 * 1024];
+            (package(&format!("pkg{}", i), "1.0.0", &data), data)
+        })
+        .collect();
+    server.route(
+        "/stable/index.bin",
+        Route {
+            body: encode_index(
+                &index(packages.iter().map(|(pkg, _)| pkg.clone()).collect()),
+                IndexFormat::Binary,
+            )
+            .unwrap(),
+            ..Route::default()
+        },
+    );
+    for (pkg, data) in &packages {
+        serve_package(
+            &server,
+            pkg,
+            data,
+            Route {
+                delay: Some(Duration::from_millis(400)),
+                ..Route::default()
+            },
+        );
+    }
+
+    let mut repo = repository(&server, &cache, network(5, 0));
+    repo.refresh().await.unwrap();
+    let mut manager = RepositoryManager::new();
+    manager.add_repository(repo);
+
+    let wanted: Vec<Package> = packages.iter().map(|(pkg, _)| pkg.clone()).collect();
+    let started = Instant::now();
+    let fetched = manager.fetch_packages(&wanted, 4).await.unwrap();
+    assert!(started.elapsed() < Duration::from_millis(1200), "{:?}", started.elapsed());
+    for ((_, data), (repo, path)) in packages.iter().zip(&fetched) {
+        assert_eq!(repo.name, "test");
+        assert_eq!(&std::fs::read(path).unwrap(), data);
+    }
+    std::fs::remove_dir_all(&cache).unwrap();
+}